@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+
+use causal::causal_block_rga::{BlockRGA, BlockRGACommand};
+use causal::causal_core::{CRDT, Event};
+use causal::causal_rga::{RGA, RGACommand};
+use causal::causal_time::VectorClock;
+
+const SIZES: [usize; 3] = [1_000, 5_000, 20_000];
+
+// Mimics a person typing: words are typed at the cursor, which sometimes jumps to a pseudo random position, and a few
+// characters before the cursor are sometimes deleted.
+fn edits(size: usize) -> Vec<BlockRGACommand<char>> {
+    let mut commands = vec![];
+    let mut seed = 42usize;
+    let mut len = 0;
+    let mut cursor = 0;
+
+    for _ in 0..size {
+        seed = (seed * 1103515245 + 12345) % (1 << 31);
+        match seed % 10 {
+            0 => cursor = seed % (len + 1),
+            1 if cursor > 0 => {
+                let removed = (seed % 4 + 1).min(cursor);
+                cursor -= removed;
+                len -= removed;
+                commands.push(BlockRGACommand::Remove(cursor, removed));
+            }
+            _ => {
+                let word = vec!['x'; seed % 8 + 1];
+                len += word.len();
+                commands.push(BlockRGACommand::Insert(cursor, word.clone()));
+                cursor += word.len();
+            }
+        }
+    }
+
+    commands
+}
+
+// The same edits as an RGA sees them, one value at a time.
+fn rga_edits(commands: &[BlockRGACommand<char>]) -> Vec<RGACommand<char>> {
+    commands
+        .iter()
+        .flat_map(|command| match command {
+            BlockRGACommand::Insert(index, values) => values
+                .iter()
+                .enumerate()
+                .map(|(offset, value)| RGACommand::Insert(index + offset, *value))
+                .collect::<Vec<_>>(),
+            BlockRGACommand::Remove(index, len) => (0..*len).map(|_| RGACommand::Remove(*index)).collect(),
+        })
+        .collect()
+}
+
+// Returns the time spent applying the edits locally and the time spent by another replica to apply them remotely,
+// together with the local CRDT. We call the CRDT directly, so that we don't measure the copies made by the replica.
+fn run<C, CMD, EVENT>(commands: &[CMD]) -> (Duration, Duration, C)
+    where C: CRDT<Vec<char>, CMD, EVENT>,
+          EVENT: Clone
+{
+    let mut crdt = C::default(Some(0));
+    let mut version = VectorClock::init();
+    let mut events = vec![];
+    let start = Instant::now();
+    for (seq_nr, command) in commands.iter().enumerate() {
+        version.increment(0);
+        let event = Event {
+            origin: 0,
+            origin_seq_nr: seq_nr as u64 + 1,
+            local_seq_nr: seq_nr as u64 + 1,
+            version: version.clone(),
+            data: crdt.prepare(command).unwrap(),
+        };
+        crdt.effect(&event).unwrap();
+        events.push(event);
+    }
+    let local = start.elapsed();
+
+    let mut remote_crdt = C::default(Some(1));
+    let start = Instant::now();
+    for event in &events {
+        remote_crdt.effect(event).unwrap();
+    }
+    let remote = start.elapsed();
+
+    assert_eq!(crdt.query(), remote_crdt.query());
+    (local, remote, crdt)
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>10} {:>12} {:>12} {:>12} {:>12} {:>10} {:>10}",
+        "edits", "values", "rga local", "rga remote", "block local", "block remote", "blocks", "stable",
+    );
+
+    for size in SIZES {
+        let commands = edits(size);
+        let values = rga_edits(&commands);
+        let (rga_local, rga_remote, rga) = run::<RGA<char>, _, _>(&values);
+        let (block_local, block_remote, mut block_rga) = run::<BlockRGA<char>, _, _>(&commands);
+        assert_eq!(rga.query(), block_rga.query());
+
+        // The RGA keeps one element per inserted value, while the block RGA keeps one block per run, and once the
+        // edits are stable it purges the removed blocks and merges the remaining ones.
+        let blocks = block_rga.block_count();
+        let mut frontier = VectorClock::init();
+        for _ in 0..commands.len() {
+            frontier.increment(0);
+        }
+        block_rga.stable(&frontier);
+
+        println!(
+            "{:>8} {:>10} {:>10}ms {:>10}ms {:>10}ms {:>10}ms {:>10} {:>10}",
+            size,
+            values.len(),
+            rga_local.as_millis(),
+            rga_remote.as_millis(),
+            block_local.as_millis(),
+            block_remote.as_millis(),
+            blocks,
+            block_rga.block_count(),
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use causal::causal_core::{CRDT, Event};
+use causal::causal_lseq::{AllocationStrategy, LSeq, LSeqCommand, LSeqOperation};
+use causal::causal_time::VectorClock;
+
+const EDITS: usize = 10_000;
+const STRATEGIES: [AllocationStrategy; 4] = [
+    AllocationStrategy::BoundaryPlus,
+    AllocationStrategy::BoundaryMinus,
+    AllocationStrategy::Alternating,
+    AllocationStrategy::Random,
+];
+
+// Inserts the values one after the other, either at the end or at the start of the document, and returns the average
+// and the maximum depth of the identifiers.
+fn run(strategy: AllocationStrategy, append: bool) -> (f64, usize) {
+    let mut lseq = LSeq::create(strategy, 0);
+    let mut version = VectorClock::init();
+    let mut depths = vec![];
+
+    for seq_nr in 0..EDITS {
+        let index = if append { seq_nr } else { 0 };
+        version.increment(0);
+        let data = lseq.prepare(&LSeqCommand::Insert(index, 0, 'x')).unwrap();
+        if let LSeqOperation::Inserted(v_ptr, _) = &data {
+            depths.push(v_ptr.depth());
+        }
+
+        lseq.effect(&Event {
+            origin: 0,
+            origin_seq_nr: seq_nr as u64 + 1,
+            local_seq_nr: seq_nr as u64 + 1,
+            version: version.clone(),
+            data,
+        }).unwrap();
+    }
+
+    let average = depths.iter().sum::<usize>() as f64 / depths.len() as f64;
+    (average, depths.into_iter().max().unwrap_or(0))
+}
+
+fn main() {
+    println!("Identifier depth after {} insertions", EDITS);
+    println!("{:>14} {:>12} {:>12} {:>12} {:>12}", "strategy", "append avg", "append max", "prepend avg", "prepend max");
+
+    for strategy in STRATEGIES {
+        let (append_average, append_max) = run(strategy, true);
+        let (prepend_average, prepend_max) = run(strategy, false);
+
+        println!(
+            "{:>14} {:>12.2} {:>12} {:>12.2} {:>12}",
+            format!("{:?}", strategy),
+            append_average,
+            append_max,
+            prepend_average,
+            prepend_max,
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::{Duration, Instant};
+
+use causal::causal_core::{CRDT, Event};
+use causal::causal_indexed_rga::IndexedRGA;
+use causal::causal_rga::{RGA, RGACommand, RGAOperation};
+use causal::causal_time::VectorClock;
+
+const SIZES: [usize; 3] = [1_000, 10_000, 50_000];
+
+// Types the document at pseudo random positions, removing a character every fourth edit.
+fn edits(size: usize) -> Vec<RGACommand<char>> {
+    let mut commands = vec![];
+    let mut seed = 42usize;
+    let mut len = 0;
+
+    for step in 0..size {
+        seed = (seed * 1103515245 + 12345) % (1 << 31);
+        if len > 0 && step % 4 == 0 {
+            commands.push(RGACommand::Remove(seed % len));
+            len -= 1;
+        } else {
+            commands.push(RGACommand::Insert(seed % (len + 1), 'x'));
+            len += 1;
+        }
+    }
+
+    commands
+}
+
+// Returns the time spent applying the edits locally and the time spent by another replica to apply them remotely. We
+// call the CRDT directly, so that we don't measure the copies of the state made by the replica.
+fn run<C>(commands: &[RGACommand<char>]) -> (Duration, Duration)
+    where C: CRDT<Vec<char>, RGACommand<char>, RGAOperation<char>>
+{
+    let mut crdt = C::default(Some(0));
+    let mut version = VectorClock::init();
+    let mut events = vec![];
+    let start = Instant::now();
+    for (seq_nr, command) in commands.iter().enumerate() {
+        version.increment(0);
+        let event = Event {
+            origin: 0,
+            origin_seq_nr: seq_nr as u64 + 1,
+            local_seq_nr: seq_nr as u64 + 1,
+            version: version.clone(),
+            data: crdt.prepare(command).unwrap(),
+        };
+        crdt.effect(&event).unwrap();
+        events.push(event);
+    }
+    let local = start.elapsed();
+
+    let mut remote_crdt = C::default(Some(1));
+    let start = Instant::now();
+    for event in &events {
+        remote_crdt.effect(event).unwrap();
+    }
+    let remote = start.elapsed();
+
+    assert_eq!(crdt.query(), remote_crdt.query());
+    (local, remote)
+}
+
+fn main() {
+    println!("{:>8} {:>14} {:>14} {:>14} {:>14}", "edits", "rga local", "rga remote", "indexed local", "indexed remote");
+
+    for size in SIZES {
+        let commands = edits(size);
+        let (rga_local, rga_remote) = run::<RGA<char>>(&commands);
+        let (indexed_local, indexed_remote) = run::<IndexedRGA<char>>(&commands);
+
+        println!(
+            "{:>8} {:>12}ms {:>12}ms {:>12}ms {:>12}ms",
+            size,
+            rga_local.as_millis(),
+            rga_remote.as_millis(),
+            indexed_local.as_millis(),
+            indexed_remote.as_millis(),
+        );
+    }
+}
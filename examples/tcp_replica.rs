@@ -0,0 +1,96 @@
+use std::{env, io, thread};
+
+use actix::prelude::*;
+
+use causal::causal_actix::{Replica, ValuedCausalMessage};
+use causal::causal_actix::VoidCausalMessage::{Command, Connect, Sync};
+use causal::causal_console::InputField;
+use causal::causal_core::CRDT;
+use causal::causal_net::{RemoteReplica, ReplicaListener};
+use causal::causal_rga::{RGA, RGACommand, RGAOperation, RGAReceiver};
+use causal::causal_utils::InMemory;
+
+// Runs a single RGA replica which replicates with replicas living in other processes, e.g.:
+//   cargo run --example tcp_replica --features net -- 0 127.0.0.1:7000 1=127.0.0.1:7001
+//   cargo run --example tcp_replica --features net -- 1 127.0.0.1:7001 0=127.0.0.1:7000
+fn start() {
+    let mut args = env::args().skip(1);
+    let usage = "Usage: tcp_replica <ID> <LISTEN_ADDR> [<PEER_ID>=<PEER_ADDR>...]";
+    let id: isize = args.next().expect(usage).parse().expect(usage);
+    let listen_address = args.next().expect(usage);
+    let peers: Vec<(isize, String)> = args
+        .map(|peer| {
+            let (peer_id, peer_address) = peer.split_once('=').expect(usage);
+            (peer_id.parse().expect(usage), String::from(peer_address))
+        })
+        .collect();
+
+    let system = System::new();
+
+    let replica = system.block_on(async {
+        let replica = Replica::create(
+            id,
+            RGA::<char>::default(Some(id)),
+            InMemory::<RGA<char>, Vec<char>, RGACommand<char>, RGAOperation<char>>::create(),
+        ).start();
+
+        // We accept the messages of the remote replicas and forward them to the local one.
+        ReplicaListener::bind(&listen_address, replica.clone().recipient())
+            .expect("Failed to bind the listener")
+            .start();
+
+        // We connect the local replica to every remote replica through a connector.
+        for (peer_id, peer_address) in peers {
+            let address = peer_address.parse().expect("Failed to parse the peer address");
+            let remote = RemoteReplica::create(address).start();
+            replica.do_send(Connect(peer_id, remote.recipient()));
+        }
+
+        replica
+    });
+
+    thread::spawn(move || {
+        let _ = System::new();
+        let arbiter = Arbiter::new();
+
+        arbiter.spawn(async move {
+            loop {
+                println!("Choose an operation ([E],[Q],[S])");
+
+                let mut command = String::new();
+                io::stdin()
+                    .read_line(&mut command)
+                    .expect("Failed to read from CLI");
+
+                match command.trim() {
+                    "Q" => {
+                        let state = replica.send(ValuedCausalMessage::Query(Default::default())).await.unwrap().0;
+                        println!("{}", String::from_iter(state.iter()));
+                    }
+                    "S" => {
+                        replica.do_send(Sync);
+                    }
+                    "E" => {
+                        let state = replica.send(ValuedCausalMessage::Query(Default::default())).await.unwrap().0;
+
+                        let mut receiver = RGAReceiver::new();
+                        InputField::start(String::from_iter(state.iter()), &mut receiver);
+
+                        for command in receiver.commands {
+                            replica.do_send(Command(command));
+                        }
+                    }
+                    _ => println!("The command is not parsable")
+                };
+            }
+        });
+
+        arbiter.join().unwrap();
+    });
+
+    system.run().unwrap();
+}
+
+fn main() {
+    start();
+}
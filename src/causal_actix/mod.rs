@@ -1,20 +1,25 @@
+use std::cmp;
 use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::time::Duration;
 
 use actix::{Actor, Context, Handler, Recipient};
 use actix::prelude::*;
+use rand::Rng;
+use rand::seq::IteratorRandom;
 
-use crate::causal_core::{CRDT, Event, EventStore, ReplicaId, ReplicaState, SeqNr, VTime};
-use crate::VoidCausalMessage::{Command, Connect, Replicate, Replicated, Sync};
+use crate::causal_core::{CausalError, CRDT, Event, EventMetadata, EventStore, ReplicaId, ReplicaState, SeqNr, VTime};
+use crate::VoidCausalMessage::{Command, Connect, Pushed, Replicate, Replicated, Sync};
 
 /** TYPES **/
 pub type VoidReplicasTable<CMD, EVENT> = HashMap<ReplicaId, Recipient<VoidCausalMessage<CMD, EVENT>>>;
 pub type VoidCausalRecipient<CMD, EVENT> = Recipient<VoidCausalMessage<CMD, EVENT>>;
+pub type ReplicasTable<C, STATE, CMD, EVENT, STORE> = HashMap<ReplicaId, Addr<Replica<C, STATE, CMD, EVENT, STORE>>>;
 
 
 /** MESSAGES **/
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Result<(), CausalError>")]
 pub enum VoidCausalMessage<CMD, EVENT>
     where CMD: Send + Unpin,
           EVENT: Send + Clone
@@ -25,15 +30,45 @@ pub enum VoidCausalMessage<CMD, EVENT>
     Connect(ReplicaId, VoidCausalRecipient<CMD, EVENT>),
     // Message that represents the start of sync between the receiving replica and all the replicas connected.
     Sync,
-    // Message that represents a request to replicate the content of the receiving replica.
-    Replicate(ReplicaId, SeqNr, VTime),
-    // Message that represents the replicated events that the receiving replica will apply locally.
-    Replicated(ReplicaId, SeqNr, Vec<Event<EVENT>>),
+    // Message that represents a request to replicate the content of the receiving replica, with at most the given
+    // number of events.
+    Replicate(ReplicaId, SeqNr, VTime, usize),
+    // Message that represents the replicated events that the receiving replica will apply locally, together with the
+    // seq nr from which the next batch must be requested if the sender has more events.
+    Replicated(ReplicaId, SeqNr, Vec<Event<EVENT>>, Option<SeqNr>),
+    // Message that represents the events pushed by the sender as soon as it created them.
+    Pushed(ReplicaId, Vec<Event<EVENT>>),
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<EventMetadata, CausalError>")]
+pub enum AcknowledgedCausalMessage<CMD>
+    where CMD: Send + Unpin
+{
+    // Message that represents the execution of a command in the receiving replica, which is answered with the
+    // metadata of the event produced by the command.
+    Command(CMD),
+}
+
+#[derive(Message)]
+#[rtype(result = "SyncStats")]
+pub enum StatsCausalMessage {
+    // Message that represents the request of the counters of the sync rounds performed by the replica.
+    Sync,
 }
 
 #[derive(MessageResponse)]
 pub struct State<STATE>(pub STATE);
 
+#[derive(MessageResponse, Clone, Debug, Default, PartialEq)]
+pub struct SyncStats {
+    // Number of sync rounds performed, both the requested and the scheduled ones.
+    pub rounds: u64,
+    pub scheduled_rounds: u64,
+    // Number of [REPLICATE] requests sent by the rounds, without the ones asking for the next batch.
+    pub requests: u64,
+}
+
 #[derive(Message)]
 #[rtype(result = "State<STATE>")]
 pub enum ValuedCausalMessage<STATE: 'static> {
@@ -41,6 +76,58 @@ pub enum ValuedCausalMessage<STATE: 'static> {
     Query(PhantomData<STATE>),
 }
 
+/** CONFIGURATION **/
+pub enum SnapshotPolicy {
+    // The replica never takes snapshots, thus its whole log is replayed at startup.
+    Never,
+    // A snapshot is taken once at least the given number of events has been stored since the last one.
+    EveryEvents(usize),
+    // A snapshot is taken periodically, if some events have been stored since the last one.
+    EveryInterval(Duration),
+}
+
+pub enum ReplicationMode {
+    // The events are exchanged only when the replica is asked to sync.
+    Pull,
+    // Every event created by a command is immediately pushed to the connected replicas, the sync is still used to
+    // recover the events that got lost.
+    Eager,
+}
+
+// Periodic sync rounds, which recover the events that haven't been received otherwise.
+pub struct AntiEntropyConfig {
+    // The time between the end of a round and the start of the next one.
+    pub interval: Duration,
+    // Every round is delayed by a random duration up to the jitter, so that the replicas don't sync all at once.
+    pub jitter: Duration,
+    // Number of random connected replicas synced at every round, all of them if not set.
+    pub fan_out: Option<usize>,
+}
+
+pub struct ReplicaConfig {
+    // Maximum number of events exchanged in a single [REPLICATED] message.
+    pub replay_batch_size: usize,
+    pub snapshot_policy: SnapshotPolicy,
+    pub replication_mode: ReplicationMode,
+    pub anti_entropy: Option<AntiEntropyConfig>,
+    // The replicas known from the start, whose acknowledgements are awaited before truncating the log, even if they
+    // have not connected yet.
+    pub peers: Vec<ReplicaId>,
+}
+
+impl Default for ReplicaConfig {
+    fn default() -> Self {
+        ReplicaConfig {
+            replay_batch_size: 100,
+            snapshot_policy: SnapshotPolicy::Never,
+            replication_mode: ReplicationMode::Pull,
+            anti_entropy: None,
+            peers: vec![],
+        }
+    }
+}
+
+
 /** ACTORS **/
 pub struct Replica<C: 'static, STATE: 'static, CMD: 'static, EVENT: 'static, STORE: 'static>
     where C: CRDT<STATE, CMD, EVENT> + Clone + Unpin,
@@ -55,6 +142,10 @@ pub struct Replica<C: 'static, STATE: 'static, CMD: 'static, EVENT: 'static, STO
     replica_state: Option<ReplicaState<C, STATE, CMD, EVENT>>,
     replicating_nodes: VoidReplicasTable<CMD, EVENT>,
     event_store: STORE,
+    config: ReplicaConfig,
+    // Number of events stored since the last snapshot.
+    unsnapshotted_events: usize,
+    sync_stats: SyncStats,
 }
 
 
@@ -67,36 +158,63 @@ impl<C: 'static, STATE: 'static, CMD: 'static, EVENT: 'static, STORE: 'static> R
           STORE: EventStore<C, STATE, CMD, EVENT> + Unpin
 {
     pub fn create(id: ReplicaId, crdt: C, store: STORE) -> Replica<C, STATE, CMD, EVENT, STORE> {
+        Replica::create_with_config(id, crdt, store, ReplicaConfig::default())
+    }
+
+    pub fn create_with_config(
+        id: ReplicaId,
+        crdt: C,
+        store: STORE,
+        config: ReplicaConfig,
+    ) -> Replica<C, STATE, CMD, EVENT, STORE> {
         Replica {
             init_id: id,
             init_crdt: crdt,
             replica_state: None,
             replicating_nodes: HashMap::new(),
             event_store: store,
+            config,
+            unsnapshotted_events: 0,
+            sync_stats: SyncStats::default(),
         }
     }
 
-    pub fn load_state(&mut self) {
-        // TODO: implement a more efficient state initialization.
+    pub fn load_state(&mut self) -> Result<(), CausalError> {
         let mut state = self.event_store
-            .load_snapshot()
-            .or(Some(ReplicaState::create(self.init_id, self.init_crdt.clone())))
-            .unwrap();
+            .load_snapshot()?
+            .unwrap_or_else(|| ReplicaState::create(self.init_id, self.init_crdt.clone()));
 
-        for event in self.event_store.load_events(state.seq_nr + 1) {
-            state = state.process_event(&event);
+        for event in self.event_store.load_events(state.seq_nr + 1)? {
+            state = state.process_event(&event)?;
+        }
+        for replica_id in &self.config.peers {
+            state.process_connect(*replica_id);
         }
 
         self.replica_state = Some(state);
+        Ok(())
     }
 
-    pub fn handle_command(&mut self, command: CMD) {
-        let state = self.replica_state
+    pub fn handle_command(&mut self, command: CMD) -> Result<EventMetadata, CausalError> {
+        let event = self.replica_state
             .as_mut()
             .unwrap()
-            .process_command(&command, &mut self.event_store);
+            .process_command(&command, &mut self.event_store)?;
 
-        self.replica_state = Some(state);
+        let metadata = EventMetadata {
+            origin: event.origin,
+            seq_nr: event.origin_seq_nr,
+            version: event.version.clone(),
+        };
+
+        if let ReplicationMode::Eager = self.config.replication_mode {
+            for replica_receiver in self.replicating_nodes.values() {
+                replica_receiver.do_send(Pushed(event.origin, vec![event.clone()]));
+            }
+        }
+
+        self.record_events(1);
+        Ok(metadata)
     }
 
     pub fn handle_connect(
@@ -104,38 +222,75 @@ impl<C: 'static, STATE: 'static, CMD: 'static, EVENT: 'static, STORE: 'static> R
         replica_id: ReplicaId,
         replica_receiver: VoidCausalRecipient<CMD, EVENT>,
     ) {
+        self.replica_state
+            .as_mut()
+            .unwrap()
+            .process_connect(replica_id);
         self.replicating_nodes.insert(replica_id, replica_receiver.clone());
     }
 
     pub fn handle_sync(&mut self) {
-        for (replica_id, replica_receiver) in &self.replicating_nodes {
+        let replica_ids = self.replicating_nodes.keys().copied().collect::<Vec<ReplicaId>>();
+        self.sync_with(&replica_ids);
+    }
+
+    // Syncs with a random subset of the connected replicas, as configured by the anti-entropy.
+    pub fn handle_anti_entropy(&mut self) {
+        let fan_out = self.config.anti_entropy
+            .as_ref()
+            .and_then(|anti_entropy| anti_entropy.fan_out)
+            .unwrap_or(self.replicating_nodes.len());
+        let replica_ids = self.replicating_nodes
+            .keys()
+            .copied()
+            .choose_multiple(&mut rand::thread_rng(), fan_out);
+
+        self.sync_stats.scheduled_rounds += 1;
+        self.sync_with(&replica_ids);
+    }
+
+    fn sync_with(&mut self, replica_ids: &[ReplicaId]) {
+        self.sync_stats.rounds += 1;
+
+        for replica_id in replica_ids {
+            let Some(replica_receiver) = self.replicating_nodes.get(replica_id) else {
+                continue;
+            };
             let (current_replica_id, seq_nr, version) = self.replica_state
                 .as_mut()
                 .unwrap()
-                .process_sync(replica_id.clone());
+                .process_sync(*replica_id);
 
             replica_receiver
-                .do_send(Replicate(current_replica_id, seq_nr, version));
-            // .expect(&*format!("Error while sending [REPLICATE] request from {} to {}", self.init_id, replica_id));
+                .do_send(Replicate(current_replica_id, seq_nr, version, self.config.replay_batch_size));
+            self.sync_stats.requests += 1;
         }
     }
 
+    pub fn sync_stats(&self) -> &SyncStats {
+        &self.sync_stats
+    }
+
     pub fn handle_replicate(
         &mut self,
         sender: ReplicaId,
         seq_nr: SeqNr,
         version: VTime,
-    ) {
-        let (current_replica_id, last_seq_nr, events) = self.replica_state
+        batch_size: usize,
+    ) -> Result<(), CausalError> {
+        let replica_receiver = self.replicating_nodes
+            .get(&sender)
+            .ok_or(CausalError::UnknownReplica(sender))?;
+
+        // The batch is bounded both by the requester and by our own configuration, but never empty.
+        let batch_size = cmp::max(cmp::min(batch_size, self.config.replay_batch_size), 1);
+        let (current_replica_id, last_seq_nr, events, continuation) = self.replica_state
             .as_mut()
             .unwrap()
-            .process_replay(seq_nr, version, &self.event_store);
+            .process_replay(sender, seq_nr, version, batch_size, &self.event_store)?;
 
-        self.replicating_nodes
-            .get(&sender)
-            .expect(&*format!("Replica {} doesn't know about replica {}", self.init_id, sender))
-            .do_send(Replicated(current_replica_id, last_seq_nr, events));
-        // .expect("Error while sending [REPLICATED] request.");
+        replica_receiver.do_send(Replicated(current_replica_id, last_seq_nr, events, continuation));
+        Ok(())
     }
 
     pub fn handle_replicated(
@@ -143,7 +298,9 @@ impl<C: 'static, STATE: 'static, CMD: 'static, EVENT: 'static, STORE: 'static> R
         sender: ReplicaId,
         last_seq_nr: SeqNr,
         events: Vec<Event<EVENT>>,
-    ) {
+        continuation: Option<SeqNr>,
+    ) -> Result<(), CausalError> {
+        let previous_seq_nr = self.replica_state.as_ref().unwrap().seq_nr;
         let state = self.replica_state
             .as_mut()
             .unwrap()
@@ -151,11 +308,91 @@ impl<C: 'static, STATE: 'static, CMD: 'static, EVENT: 'static, STORE: 'static> R
 
         // If a new state has been created as a result of the events received, we are going to apply
         // it to the replica.
-        if let Some(new_state) = state {
-            self.replica_state = Some(new_state);
+        if let Ok(Some(new_state)) = &state {
+            self.replica_state = Some(new_state.clone());
+        }
+        // Even when an event fails, the events delivered before it are stored.
+        let new_events = (self.replica_state.as_ref().unwrap().seq_nr - previous_seq_nr) as usize;
+        self.record_events(new_events);
+
+        // If the sender has more events, we immediately request the next batch until we catch up.
+        if let Some(next_seq_nr) = continuation {
+            let (current_replica_id, _, version) = self.replica_state
+                .as_mut()
+                .unwrap()
+                .process_sync(sender);
+
+            if let Some(replica_receiver) = self.replicating_nodes.get(&sender) {
+                replica_receiver
+                    .do_send(Replicate(current_replica_id, next_seq_nr, version, self.config.replay_batch_size));
+            }
+        }
+
+        state.map(|_| ())
+    }
+
+    pub fn handle_pushed(&mut self, sender: ReplicaId, events: Vec<Event<EVENT>>) -> Result<(), CausalError> {
+        let previous_seq_nr = self.replica_state.as_ref().unwrap().seq_nr;
+        let state = self.replica_state
+            .as_mut()
+            .unwrap()
+            .process_pushed(sender, events, &mut self.event_store);
+
+        if let Ok(Some(new_state)) = &state {
+            self.replica_state = Some(new_state.clone());
+        }
+        let new_events = (self.replica_state.as_ref().unwrap().seq_nr - previous_seq_nr) as usize;
+        self.record_events(new_events);
+
+        state.map(|_| ())
+    }
+
+    pub fn handle_snapshot(&mut self) -> Result<(), CausalError> {
+        if self.unsnapshotted_events == 0 {
+            return Ok(());
+        }
+
+        self.replica_state
+            .as_ref()
+            .unwrap()
+            .process_snapshot(&mut self.event_store)?;
+        self.unsnapshotted_events = 0;
+        Ok(())
+    }
+
+    fn record_events(&mut self, events: usize) {
+        self.unsnapshotted_events += events;
+
+        if let SnapshotPolicy::EveryEvents(threshold) = self.config.snapshot_policy {
+            if self.unsnapshotted_events >= threshold {
+                // The events are already stored, a failed snapshot is retried once more events are recorded.
+                self.snapshot_or_report();
+            }
+        }
+    }
+
+    fn snapshot_or_report(&mut self) {
+        if let Err(error) = self.handle_snapshot() {
+            println!("@{} failed to take a snapshot: {}", self.init_id, error);
         }
     }
 
+    // Every round is scheduled once the previous one is over, thus the rounds never overlap whatever the jitter.
+    fn schedule_anti_entropy(&self, ctx: &mut Context<Self>) {
+        let Some(anti_entropy) = &self.config.anti_entropy else {
+            return;
+        };
+        let jitter = match anti_entropy.jitter.is_zero() {
+            true => Duration::ZERO,
+            false => rand::thread_rng().gen_range(Duration::ZERO..=anti_entropy.jitter),
+        };
+
+        ctx.run_later(anti_entropy.interval + jitter, |replica, ctx| {
+            replica.handle_anti_entropy();
+            replica.schedule_anti_entropy(ctx);
+        });
+    }
+
     pub fn handle_query(&mut self) -> STATE {
         self.replica_state
             .as_mut()
@@ -173,8 +410,18 @@ impl<C: 'static, STATE: 'static, CMD: 'static, EVENT: 'static, STORE: 'static> A
 {
     type Context = Context<Self>;
 
-    fn started(&mut self, _: &mut Self::Context) {
-        self.load_state();
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Err(error) = self.load_state() {
+            println!("@{} failed to load its state: {}", self.init_id, error);
+            ctx.stop();
+            return;
+        }
+
+        if let SnapshotPolicy::EveryInterval(interval) = self.config.snapshot_policy {
+            ctx.run_interval(interval, |replica, _| replica.snapshot_or_report());
+        }
+
+        self.schedule_anti_entropy(ctx);
     }
 }
 
@@ -185,34 +432,82 @@ impl<C: 'static, STATE: 'static, CMD: 'static, EVENT: 'static, STORE: 'static> H
           EVENT: Send + Clone + Unpin,
           STORE: EventStore<C, STATE, CMD, EVENT> + Unpin
 {
-    type Result = ();
+    type Result = Result<(), CausalError>;
 
     fn handle(&mut self, msg: VoidCausalMessage<CMD, EVENT>, _: &mut Self::Context) -> Self::Result {
-        match msg {
+        let result = match msg {
             Command(command) => {
                 println!("APP-[COMMAND]->@{}", self.init_id);
-                self.handle_command(command);
+                self.handle_command(command).map(|_| ())
             }
             Connect(replica_id, replica_receiver) => {
                 println!("APP-[CONNECT]->@{} with replica_id: {}", self.init_id, replica_id);
                 self.handle_connect(replica_id, replica_receiver);
+                Ok(())
             }
             Sync => {
                 println!("APP-[SYNC]->@{}", self.init_id);
                 self.handle_sync();
+                Ok(())
+            }
+            Replicate(sender, seq_nr, version, batch_size) => {
+                println!("@{}-[REPLICATE]->@{} with seq_nr:{}, version_vector:{}, batch_size:{}", sender, self.init_id, seq_nr, version, batch_size);
+                self.handle_replicate(sender, seq_nr, version, batch_size)
+            }
+            Replicated(sender, last_seq_nr, events, continuation) => {
+                println!("@{}-[REPLICATED]->@{} with last_seq_nr:{}, n_events:{}, continuation:{:?}", sender, self.init_id, last_seq_nr, events.len(), continuation);
+                self.handle_replicated(sender, last_seq_nr, events, continuation)
             }
-            Replicate(sender, seq_nr, version) => {
-                println!("@{}-[REPLICATE]->@{} with seq_nr:{}, version_vector:{}", sender, self.init_id, seq_nr, version);
-                self.handle_replicate(sender, seq_nr, version);
+            Pushed(sender, events) => {
+                println!("@{}-[PUSHED]->@{} with n_events:{}", sender, self.init_id, events.len());
+                self.handle_pushed(sender, events)
             }
-            Replicated(sender, last_seq_nr, events) => {
-                println!("@{}-[REPLICATED]->@{} with last_seq_nr:{}, n_events:{}", sender, self.init_id, last_seq_nr, events.len());
-                self.handle_replicated(sender, last_seq_nr, events);
+        };
+
+        // Messages sent with do_send drop their result, thus we always report the failure here as well.
+        if let Err(error) = &result {
+            println!("@{} failed with: {}", self.init_id, error);
+        }
+
+        result
+    }
+}
+
+impl<C: 'static, STATE: 'static, CMD: 'static, EVENT: 'static, STORE: 'static> Handler<AcknowledgedCausalMessage<CMD>> for Replica<C, STATE, CMD, EVENT, STORE>
+    where C: CRDT<STATE, CMD, EVENT> + Clone + Unpin,
+          STATE: Send + Unpin,
+          CMD: Send + Unpin,
+          EVENT: Send + Clone + Unpin,
+          STORE: EventStore<C, STATE, CMD, EVENT> + Unpin
+{
+    type Result = Result<EventMetadata, CausalError>;
+
+    fn handle(&mut self, msg: AcknowledgedCausalMessage<CMD>, _: &mut Self::Context) -> Self::Result {
+        match msg {
+            AcknowledgedCausalMessage::Command(command) => {
+                println!("APP-[ACKNOWLEDGED COMMAND]->@{}", self.init_id);
+                self.handle_command(command)
             }
         }
     }
 }
 
+impl<C: 'static, STATE: 'static, CMD: 'static, EVENT: 'static, STORE: 'static> Handler<StatsCausalMessage> for Replica<C, STATE, CMD, EVENT, STORE>
+    where C: CRDT<STATE, CMD, EVENT> + Clone + Unpin,
+          STATE: Send + Unpin,
+          CMD: Send + Unpin,
+          EVENT: Send + Clone + Unpin,
+          STORE: EventStore<C, STATE, CMD, EVENT> + Unpin
+{
+    type Result = SyncStats;
+
+    fn handle(&mut self, msg: StatsCausalMessage, _: &mut Self::Context) -> Self::Result {
+        match msg {
+            StatsCausalMessage::Sync => self.sync_stats.clone(),
+        }
+    }
+}
+
 impl<C: 'static, STATE: 'static, CMD: 'static, EVENT: 'static, STORE: 'static> Handler<ValuedCausalMessage<STATE>> for Replica<C, STATE, CMD, EVENT, STORE>
     where C: CRDT<STATE, CMD, EVENT> + Clone + Unpin,
           STATE: Send + Unpin,
@@ -235,10 +530,10 @@ impl<C: 'static, STATE: 'static, CMD: 'static, EVENT: 'static, STORE: 'static> H
 
 /** UTILS **/
 pub fn send_void<C, STATE, CMD, EVENT, STORE>(
-    replicas: &HashMap<ReplicaId, Addr<Replica<C, STATE, CMD, EVENT, STORE>>>,
+    replicas: &ReplicasTable<C, STATE, CMD, EVENT, STORE>,
     replica_id: ReplicaId,
     message: VoidCausalMessage<CMD, EVENT>,
-)
+) -> Result<(), CausalError>
     where C: CRDT<STATE, CMD, EVENT> + Clone + Unpin,
           STATE: Send + Unpin,
           CMD: Send + Unpin,
@@ -247,30 +542,226 @@ pub fn send_void<C, STATE, CMD, EVENT, STORE>(
 {
     replicas
         .get(&replica_id)
-        .unwrap()
-        .clone()
-        .recipient()
+        .ok_or(CausalError::UnknownReplica(replica_id))?
         .do_send(message);
-    //.expect(&*format!("The delivery of the message to replica {} failed!", replica_id));
+
+    Ok(())
 }
 
 pub async fn send_valued<C, STATE, CMD, EVENT, STORE>(
-    replicas: &HashMap<ReplicaId, Addr<Replica<C, STATE, CMD, EVENT, STORE>>>,
+    replicas: &ReplicasTable<C, STATE, CMD, EVENT, STORE>,
     replica_id: ReplicaId,
     message: ValuedCausalMessage<STATE>,
-) -> STATE
+) -> Result<STATE, CausalError>
     where C: CRDT<STATE, CMD, EVENT> + Clone + Unpin,
           STATE: Send + Unpin,
           CMD: Send + Unpin,
           EVENT: Send + Clone + Unpin,
           STORE: EventStore<C, STATE, CMD, EVENT> + Unpin
 {
-    replicas
+    let state = replicas
         .get(&replica_id)
-        .unwrap()
-        .clone()
+        .ok_or(CausalError::UnknownReplica(replica_id))?
         .send(message)
         .await
-        .unwrap()
-        .0
-}
\ No newline at end of file
+        .map_err(|_| CausalError::Unreachable(replica_id))?;
+
+    Ok(state.0)
+}
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::marker::PhantomData;
+    use std::time::{Duration, Instant};
+
+    use actix::{Actor, System};
+
+    use crate::{CRDT, VectorClock};
+    use crate::causal_actix::{AcknowledgedCausalMessage, AntiEntropyConfig, Replica, ReplicaConfig, ReplicationMode, send_valued, send_void, SnapshotPolicy, StatsCausalMessage, ValuedCausalMessage, VoidCausalMessage};
+    use crate::causal_core::CausalError;
+    use crate::causal_rga::RGA;
+    use crate::causal_rga::RGACommand::{Insert, Remove};
+    use crate::causal_utils::InMemory;
+
+    #[test]
+    fn test_snapshot_every_events() {
+        let config = ReplicaConfig {
+            snapshot_policy: SnapshotPolicy::EveryEvents(2),
+            peers: vec![1],
+            ..ReplicaConfig::default()
+        };
+        let mut replica = Replica::create_with_config(0, RGA::default(Some(0)), InMemory::create(), config);
+        replica.load_state().unwrap();
+
+        for index in 0..5 {
+            replica.handle_command(Insert(index, 'a')).unwrap();
+        }
+
+        // The configured peer has not acknowledged anything yet, thus the whole log is kept for it.
+        assert_eq!(replica.event_store.last_snapshot.as_ref().unwrap().seq_nr, 4);
+        assert_eq!(replica.event_store.events.len(), 5);
+
+        // Once the peer asks for events with a version covering the first five, they can be removed.
+        let state = replica.replica_state.as_mut().unwrap();
+        state.process_replay(1, 6, state.version.clone(), 10, &replica.event_store).unwrap();
+        replica.handle_command(Insert(5, 'a')).unwrap();
+
+        assert_eq!(replica.event_store.last_snapshot.as_ref().unwrap().seq_nr, 6);
+        assert_eq!(replica.event_store.events.len(), 1);
+
+        replica.load_state().unwrap();
+        assert_eq!(replica.handle_query().len(), 6);
+    }
+
+    #[test]
+    fn test_invalid_messages_do_not_stop_the_replica() {
+        System::new().block_on(async {
+            let replica = Replica::create(0, RGA::default(Some(0)), InMemory::create()).start();
+
+            let result = replica.send(VoidCausalMessage::Command(Remove(3))).await.unwrap();
+            assert_eq!(result, Err(CausalError::IndexOutOfBounds(3)));
+            let result = replica.send(VoidCausalMessage::Replicate(1, 1, VectorClock::init(), 10)).await.unwrap();
+            assert_eq!(result, Err(CausalError::UnknownReplica(1)));
+
+            replica.send(VoidCausalMessage::Command(Insert(0, 'a'))).await.unwrap().unwrap();
+            let replicas = HashMap::from([(0, replica)]);
+            assert_eq!(send_valued(&replicas, 0, ValuedCausalMessage::Query(PhantomData)).await, Ok(vec!['a']));
+            assert_eq!(send_valued(&replicas, 1, ValuedCausalMessage::Query(PhantomData)).await, Err(CausalError::UnknownReplica(1)));
+            assert_eq!(send_void(&replicas, 1, VoidCausalMessage::Sync), Err(CausalError::UnknownReplica(1)));
+        });
+    }
+
+    #[test]
+    fn test_acknowledged_command_returns_event_metadata() {
+        System::new().block_on(async {
+            let replica = Replica::create(0, RGA::default(Some(0)), InMemory::create()).start();
+
+            let first = replica.send(AcknowledgedCausalMessage::Command(Insert(0, 'a'))).await.unwrap().unwrap();
+            let second = replica.send(AcknowledgedCausalMessage::Command(Insert(1, 'b'))).await.unwrap().unwrap();
+            let failed = replica.send(AcknowledgedCausalMessage::Command(Remove(2))).await.unwrap();
+
+            assert_eq!((first.origin, first.seq_nr, first.version.get(&0)), (0, 1, 1));
+            assert_eq!((second.origin, second.seq_nr, second.version.get(&0)), (0, 2, 2));
+            assert_eq!(failed, Err(CausalError::IndexOutOfBounds(2)));
+
+            let state = replica.send(ValuedCausalMessage::Query(PhantomData)).await.unwrap().0;
+            assert_eq!(state, vec!['a', 'b']);
+        });
+
+        // The metadata matches the event stored in the log.
+        let mut replica = Replica::create(0, RGA::default(Some(0)), InMemory::create());
+        replica.load_state().unwrap();
+        let metadata = replica.handle_command(Insert(0, 'a')).unwrap();
+        assert_eq!(metadata, replica.event_store.events[0].metadata());
+    }
+
+    #[test]
+    fn test_empty_batches_still_replicate() {
+        let config = ReplicaConfig {
+            replay_batch_size: 0,
+            ..ReplicaConfig::default()
+        };
+
+        let value = System::new().block_on(async {
+            let replica_0 = Replica::create(0, RGA::default(Some(0)), InMemory::create()).start();
+            let replica_1 = Replica::create_with_config(1, RGA::default(Some(1)), InMemory::create(), config).start();
+            replica_0.send(VoidCausalMessage::Connect(1, replica_1.clone().recipient())).await.unwrap().unwrap();
+            replica_1.send(VoidCausalMessage::Connect(0, replica_0.clone().recipient())).await.unwrap().unwrap();
+            for (index, value) in "abc".chars().enumerate() {
+                replica_0.send(VoidCausalMessage::Command(Insert(index, value))).await.unwrap().unwrap();
+            }
+
+            // Replica 1 asks for empty batches, which are answered one event at a time until it catches up.
+            replica_1.send(VoidCausalMessage::Sync).await.unwrap().unwrap();
+            let replicas = HashMap::from([(1, replica_1)]);
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                actix_rt::time::sleep(Duration::from_millis(10)).await;
+                let value = send_valued(&replicas, 1, ValuedCausalMessage::Query(PhantomData)).await;
+                if value == Ok(vec!['a', 'b', 'c']) || Instant::now() > deadline {
+                    break value;
+                }
+            }
+        });
+
+        assert_eq!(value, Ok(vec!['a', 'b', 'c']));
+    }
+
+    #[test]
+    fn test_eager_replication() {
+        let eager = || ReplicaConfig {
+            replication_mode: ReplicationMode::Eager,
+            ..ReplicaConfig::default()
+        };
+
+        System::new().block_on(async {
+            let replica_0 = Replica::create_with_config(0, RGA::default(Some(0)), InMemory::create(), eager()).start();
+            let replica_1 = Replica::create_with_config(1, RGA::default(Some(1)), InMemory::create(), eager()).start();
+            replica_0.send(VoidCausalMessage::Connect(1, replica_1.clone().recipient())).await.unwrap().unwrap();
+            replica_1.send(VoidCausalMessage::Connect(0, replica_0.clone().recipient())).await.unwrap().unwrap();
+
+            // The events reach the other replica without any sync.
+            replica_0.send(VoidCausalMessage::Command(Insert(0, 'a'))).await.unwrap().unwrap();
+            replica_1.send(VoidCausalMessage::Command(Insert(1, 'b'))).await.unwrap().unwrap();
+            let replicas = HashMap::from([(0, replica_0), (1, replica_1)]);
+            assert_eq!(send_valued(&replicas, 0, ValuedCausalMessage::Query(PhantomData)).await, Ok(vec!['a', 'b']));
+            assert_eq!(send_valued(&replicas, 1, ValuedCausalMessage::Query(PhantomData)).await, Ok(vec!['a', 'b']));
+
+            // The sync doesn't deliver the pushed events again. The mailboxes are processed in order, thus the
+            // first queries are answered after the [REPLICATE] requests and the second ones after the answers.
+            for replica in replicas.values() {
+                replica.send(VoidCausalMessage::Sync).await.unwrap().unwrap();
+            }
+            for _ in 0..2 {
+                for replica_id in [0, 1] {
+                    assert_eq!(send_valued(&replicas, replica_id, ValuedCausalMessage::Query(PhantomData)).await, Ok(vec!['a', 'b']));
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_anti_entropy_rounds() {
+        let config = |fan_out| ReplicaConfig {
+            anti_entropy: Some(AntiEntropyConfig {
+                interval: Duration::from_millis(20),
+                jitter: Duration::from_millis(5),
+                fan_out,
+            }),
+            ..ReplicaConfig::default()
+        };
+
+        System::new().block_on(async {
+            let replica_0 = Replica::create_with_config(0, RGA::default(Some(0)), InMemory::create(), config(Some(1))).start();
+            let replica_1 = Replica::create_with_config(1, RGA::default(Some(1)), InMemory::create(), config(None)).start();
+            let replica_2 = Replica::create(2, RGA::default(Some(2)), InMemory::create()).start();
+            for (replica, other_id, other) in [(&replica_0, 1, &replica_1), (&replica_0, 2, &replica_2), (&replica_1, 0, &replica_0)] {
+                replica.send(VoidCausalMessage::Connect(other_id, other.clone().recipient())).await.unwrap().unwrap();
+            }
+            replica_0.send(VoidCausalMessage::Command(Insert(0, 'a'))).await.unwrap().unwrap();
+
+            // The events reach the other replica without any sync being requested. We wait until both replicas have
+            // run their rounds, or until the deadline.
+            let replicas = HashMap::from([(1, replica_1)]);
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let (value, stats) = loop {
+                actix_rt::time::sleep(Duration::from_millis(10)).await;
+                let value = send_valued(&replicas, 1, ValuedCausalMessage::Query(PhantomData)).await;
+                let stats = replica_0.send(StatsCausalMessage::Sync).await.unwrap();
+                if (value == Ok(vec!['a']) && stats.scheduled_rounds > 0) || Instant::now() > deadline {
+                    break (value, stats);
+                }
+            };
+            assert_eq!(value, Ok(vec!['a']));
+
+            // Replica 0 syncs with a single one of its two replicas at every round.
+            assert!(stats.scheduled_rounds > 0);
+            assert_eq!(stats.rounds, stats.scheduled_rounds);
+            assert_eq!(stats.requests, stats.rounds);
+
+            replica_2.send(VoidCausalMessage::Sync).await.unwrap().unwrap();
+            let stats = replica_2.send(StatsCausalMessage::Sync).await.unwrap();
+            assert_eq!((stats.rounds, stats.scheduled_rounds, stats.requests), (1, 0, 0));
+        });
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::{BTreeMap, HashMap};
+use std::iter;
+
+use crate::{CRDT, Event, ReplicaId};
+use crate::causal_block_rga::BlockRGACommand::{Insert, Remove};
+use crate::causal_block_rga::BlockRGAOperation::{Inserted, Removed};
+use crate::causal_core::{CausalError, SeqNr, VTime};
+use crate::causal_rga::RGAPtr;
+use crate::causal_time::ClockComparison;
+
+/** DATA STRUCTURES **/
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockRGACommand<T>
+    where T: Clone
+{
+    // Inserts all the values at the index, e.g. a whole string.
+    Insert(usize, Vec<T>),
+    // Removes the given number of values starting from the index.
+    Remove(usize, usize),
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockRGAOperation<T>
+    where T: Clone
+{
+    // The values are inserted after the given element, or at the head, and take consecutive pointers from the first one.
+    Inserted(Option<RGAPtr>, RGAPtr, Vec<T>),
+    // Runs of consecutive pointers, each one given by its first pointer and its length.
+    Removed(Vec<(RGAPtr, usize)>),
+}
+
+// A run of values inserted one after the other by the same replica, whose pointers are consecutive. The values are
+// none once the run has been removed.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Block<T>
+    where T: Clone
+{
+    start: RGAPtr,
+    len: usize,
+    values: Option<Vec<T>>,
+    // The start of the block which follows this one.
+    next: Option<RGAPtr>,
+    // The version of the latest insertion in the block, until it is known to be delivered by every replica.
+    inserted: Option<VTime>,
+    // The version of the removal of the block.
+    removed: Option<VTime>,
+}
+
+// An RGA which stores runs of consecutive insertions as a single block instead of one element per value. Every value
+// still has its own pointer, and the blocks are split whenever an operation refers to a value in the middle of one,
+// so the order of the values is the same as the one of an RGA where the values are inserted one at a time. The blocks
+// are linked one to the other and indexed by their first pointer, thus remote operations find their block in
+// logarithmic time in the number of blocks.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockRGA<T>
+    where T: Clone
+{
+    sequencer: RGAPtr,
+    // The start of the first block, the other ones are reached through the links.
+    head: Option<RGAPtr>,
+    blocks: HashMap<RGAPtr, Block<T>>,
+    // The start of every block, ordered by replica and sequence number, to find the block that contains a pointer.
+    starts: BTreeMap<(ReplicaId, SeqNr), RGAPtr>,
+}
+
+
+/** IMPLEMENTATIONS **/
+impl<T> Block<T>
+    where T: Clone
+{
+    fn is_visible(&self) -> bool {
+        self.values.is_some()
+    }
+
+    fn last(&self) -> RGAPtr {
+        self.start.offset(self.len - 1)
+    }
+
+    // Keeps the values before the offset in this block and returns a new block with the remaining ones, which follows
+    // this one.
+    fn split(&mut self, offset: usize) -> Block<T> {
+        let values = self.values.as_mut().map(|values| values.split_off(offset));
+        let block = Block {
+            start: self.start.offset(offset),
+            len: self.len - offset,
+            values,
+            next: self.next.take(),
+            inserted: self.inserted.clone(),
+            removed: self.removed.clone(),
+        };
+        self.len = offset;
+        self.next = Some(block.start.clone());
+        block
+    }
+}
+
+impl<T> Clone for Block<T>
+    where T: Clone
+{
+    fn clone(&self) -> Self {
+        Block {
+            start: self.start.clone(),
+            len: self.len,
+            values: self.values.clone(),
+            next: self.next.clone(),
+            inserted: self.inserted.clone(),
+            removed: self.removed.clone(),
+        }
+    }
+}
+
+impl<T> BlockRGA<T>
+    where T: Clone
+{
+    // Returns the number of blocks, removed ones included.
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    // Returns the blocks in order.
+    fn iter(&self) -> impl Iterator<Item = &Block<T>> {
+        iter::successors(self.head.as_ref().map(|start| &self.blocks[start]), |block| {
+            block.next.as_ref().map(|start| &self.blocks[start])
+        })
+    }
+
+    // Returns the start of the block which contains the pointer, and the offset of the pointer in it.
+    fn find(&self, v_ptr: &RGAPtr) -> Result<(RGAPtr, usize), CausalError> {
+        self.starts
+            .range(..=v_ptr.run_key())
+            .next_back()
+            .and_then(|(_, start)| {
+                let offset = v_ptr.distance_from(start)?;
+                (offset < self.blocks[start].len).then(|| (start.clone(), offset))
+            })
+            .ok_or(CausalError::UnknownElement)
+    }
+
+    // Splits the block at the offset, if it isn't its start, and returns the start of the block which follows the
+    // split.
+    fn split(&mut self, start: &RGAPtr, offset: usize) -> RGAPtr {
+        if offset == 0 {
+            return start.clone();
+        }
+
+        let block = self.blocks.get_mut(start).map(|block| block.split(offset)).expect("The block must exist.");
+        let block_start = block.start.clone();
+        self.starts.insert(block_start.run_key(), block_start.clone());
+        self.blocks.insert(block_start.clone(), block);
+        block_start
+    }
+
+    // Returns the start of the block which follows the given one, or of the first block.
+    fn next_of(&self, start: &Option<RGAPtr>) -> Option<RGAPtr> {
+        match start {
+            Some(start) => self.blocks[start].next.clone(),
+            None => self.head.clone(),
+        }
+    }
+
+    // Returns the visible runs which cover the values from the index, splitting them only at the boundaries.
+    fn visible_runs(&self, index: usize, len: usize) -> Result<Vec<(RGAPtr, usize)>, CausalError> {
+        let mut runs = vec![];
+        let mut skipped = 0;
+        let mut remaining = len;
+
+        for block in self.iter().filter(|block| block.is_visible()) {
+            if remaining == 0 {
+                break;
+            }
+            if skipped + block.len <= index {
+                skipped += block.len;
+                continue;
+            }
+
+            let offset = index.saturating_sub(skipped);
+            let run_len = (block.len - offset).min(remaining);
+            runs.push((block.start.offset(offset), run_len));
+            skipped += block.len;
+            remaining -= run_len;
+        }
+
+        if remaining > 0 {
+            return Err(CausalError::IndexOutOfBounds(index + len - 1));
+        }
+
+        Ok(runs)
+    }
+
+    fn insert(&mut self, prev_v_ptr: &Option<RGAPtr>, start_v_ptr: &RGAPtr, values: &[T], version: &VTime) -> Result<(), CausalError> {
+        // The predecessor must end its block, so that the new values can be linked right after it.
+        let predecessor = match prev_v_ptr {
+            Some(prev_v_ptr) => {
+                let (start, offset) = self.find(prev_v_ptr)?;
+                if offset + 1 < self.blocks[&start].len {
+                    self.split(&start, offset + 1);
+                }
+                Some(start)
+            }
+            None => None,
+        };
+
+        // As in the RGA, we skip the blocks inserted concurrently after the same one which have a greater pointer.
+        let mut after = predecessor.clone();
+        while let Some(next) = self.next_of(&after) {
+            if *start_v_ptr < next {
+                after = Some(next);
+            } else {
+                break;
+            }
+        }
+
+        // A run typed right after the previous one of the same replica extends its block.
+        if let (Some(start), true) = (&after, after == predecessor) {
+            let block = self.blocks.get_mut(start).expect("The block must exist.");
+            if block.last().offset(1) == *start_v_ptr {
+                if let Some(block_values) = block.values.as_mut() {
+                    block_values.extend_from_slice(values);
+                    block.len += values.len();
+                    block.inserted = Some(version.clone());
+                    return Ok(());
+                }
+            }
+        }
+
+        let block = Block {
+            start: start_v_ptr.clone(),
+            len: values.len(),
+            values: Some(values.to_vec()),
+            next: self.next_of(&after),
+            inserted: Some(version.clone()),
+            removed: None,
+        };
+        match &after {
+            Some(start) => self.blocks.get_mut(start).expect("The block must exist.").next = Some(start_v_ptr.clone()),
+            None => self.head = Some(start_v_ptr.clone()),
+        }
+        self.starts.insert(start_v_ptr.run_key(), start_v_ptr.clone());
+        self.blocks.insert(start_v_ptr.clone(), block);
+
+        Ok(())
+    }
+
+    fn remove(&mut self, start_v_ptr: &RGAPtr, len: usize, version: &VTime) -> Result<(), CausalError> {
+        // The run might have been split by concurrent insertions, thus we remove it block by block.
+        let mut v_ptr = start_v_ptr.clone();
+        let mut remaining = len;
+
+        while remaining > 0 {
+            let (start, offset) = self.find(&v_ptr)?;
+            let start = self.split(&start, offset);
+            if self.blocks[&start].len > remaining {
+                self.split(&start, remaining);
+            }
+
+            let block = self.blocks.get_mut(&start).expect("The block must exist.");
+            block.values = None;
+            block.removed.get_or_insert_with(|| version.clone());
+            remaining -= block.len;
+            v_ptr = v_ptr.offset(block.len);
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> Clone for BlockRGA<T>
+    where T: Clone
+{
+    fn clone(&self) -> Self {
+        BlockRGA {
+            sequencer: self.sequencer.clone(),
+            head: self.head.clone(),
+            blocks: self.blocks.clone(),
+            starts: self.starts.clone(),
+        }
+    }
+}
+
+impl<T> CRDT<Vec<T>, BlockRGACommand<T>, BlockRGAOperation<T>> for BlockRGA<T>
+    where T: Clone
+{
+    fn default(replica_id: Option<ReplicaId>) -> Self {
+        let replica_id = replica_id.expect("You must set a valid replica id for the block RGA to work.");
+
+        BlockRGA {
+            sequencer: RGAPtr::new(replica_id),
+            head: None,
+            blocks: HashMap::new(),
+            starts: BTreeMap::new(),
+        }
+    }
+
+    fn query(&self) -> Vec<T> {
+        self.iter()
+            .filter_map(|block| block.values.as_ref())
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    fn prepare(&self, command: &BlockRGACommand<T>) -> Result<BlockRGAOperation<T>, CausalError> {
+        match command {
+            Insert(index, values) => {
+                // We insert after the value visible right before the index, or at the head.
+                let prev_v_ptr = match index {
+                    0 => None,
+                    _ => {
+                        let runs = self.visible_runs(*index - 1, 1).map_err(|_| CausalError::IndexOutOfBounds(*index))?;
+                        Some(runs[0].0.clone())
+                    }
+                };
+
+                Ok(Inserted(prev_v_ptr, self.sequencer.next_seq_nr(), values.clone()))
+            }
+            Remove(index, len) => Ok(Removed(self.visible_runs(*index, *len)?)),
+        }
+    }
+
+    fn effect(&mut self, event: &Event<BlockRGAOperation<T>>) -> Result<(), CausalError> {
+        match &event.data {
+            Inserted(prev_v_ptr, start_v_ptr, values) => {
+                if values.is_empty() {
+                    return Ok(());
+                }
+
+                self.insert(prev_v_ptr, start_v_ptr, values, &event.version)?;
+                self.sequencer.update_highest_observed_seq_nr(&start_v_ptr.offset(values.len() - 1));
+            }
+            Removed(runs) => {
+                // We check every run first, so that the state is left unchanged if one of them is unknown.
+                for (start_v_ptr, len) in runs {
+                    self.find(start_v_ptr)?;
+                    self.find(&start_v_ptr.offset(len.saturating_sub(1)))?;
+                }
+
+                for (start_v_ptr, len) in runs {
+                    self.remove(start_v_ptr, *len, &event.version)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stable(&mut self, frontier: &VTime) {
+        let is_stable = |version: &VTime| {
+            let comparison = version.compare(frontier);
+            comparison == ClockComparison::Less || comparison == ClockComparison::Equal
+        };
+
+        for block in self.blocks.values_mut() {
+            if block.inserted.as_ref().is_some_and(is_stable) {
+                block.inserted = None;
+            }
+        }
+
+        // As in the RGA, a removed block is purged once its removal is stable and the insertion of the block that
+        // follows it is stable too. We purge in a single pass from the end, so that the successor is the final one.
+        let order = self.iter().map(|block| block.start.clone()).collect::<Vec<_>>();
+        let mut kept = Vec::with_capacity(order.len());
+        let mut successor_stable = true;
+        for start in order.into_iter().rev() {
+            let block = &self.blocks[&start];
+            if successor_stable && block.removed.as_ref().is_some_and(is_stable) {
+                self.starts.remove(&start.run_key());
+                self.blocks.remove(&start);
+                continue;
+            }
+
+            successor_stable = block.inserted.is_none();
+            kept.push(start);
+        }
+        kept.reverse();
+
+        // The visible blocks that were split by operations which are now gone are merged back, as long as their
+        // insertions are stable. A later operation that refers to the middle of the block splits it again.
+        let mut merged: Vec<RGAPtr> = Vec::with_capacity(kept.len());
+        for start in kept {
+            if let Some(previous) = merged.last() {
+                let (previous_block, block) = (&self.blocks[previous], &self.blocks[&start]);
+                let mergeable = previous_block.is_visible() && block.is_visible()
+                    && previous_block.inserted.is_none() && block.inserted.is_none()
+                    && previous_block.last().offset(1) == block.start;
+
+                if mergeable {
+                    self.starts.remove(&start.run_key());
+                    let block = self.blocks.remove(&start).expect("The block must exist.");
+                    let previous_block = self.blocks.get_mut(previous).expect("The block must exist.");
+                    previous_block.len += block.len;
+                    if let (Some(previous_values), Some(values)) = (previous_block.values.as_mut(), block.values) {
+                        previous_values.extend(values);
+                    }
+                    continue;
+                }
+            }
+
+            merged.push(start);
+        }
+
+        self.head = merged.first().cloned();
+        for (index, start) in merged.iter().enumerate() {
+            self.blocks.get_mut(start).expect("The block must exist.").next = merged.get(index + 1).cloned();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CRDT, ReplicaState};
+    use crate::causal_block_rga::{BlockRGA, BlockRGACommand, BlockRGAOperation};
+    use crate::causal_core::CausalError;
+    use crate::causal_utils::InMemory;
+
+    type BlockRGAState = ReplicaState<BlockRGA<char>, Vec<char>, BlockRGACommand<char>, BlockRGAOperation<char>>;
+    type BlockRGAStore = InMemory<BlockRGA<char>, Vec<char>, BlockRGACommand<char>, BlockRGAOperation<char>>;
+
+    fn replica(id: isize) -> (BlockRGAState, BlockRGAStore) {
+        (ReplicaState::create(id, BlockRGA::default(Some(id))), InMemory::create())
+    }
+
+    fn insert(index: usize, text: &str) -> BlockRGACommand<char> {
+        BlockRGACommand::Insert(index, text.chars().collect())
+    }
+
+    fn text(state: &BlockRGAState) -> String {
+        state.process_query().into_iter().collect()
+    }
+
+    #[test]
+    fn test_typing_extends_a_single_block() {
+        let (mut state, mut store) = replica(0);
+        state.process_command(&insert(0, "Hello"), &mut store).unwrap();
+        for (index, character) in " world".chars().enumerate() {
+            state.process_command(&BlockRGACommand::Insert(5 + index, vec![character]), &mut store).unwrap();
+        }
+
+        assert_eq!(text(&state), "Hello world");
+        assert_eq!(state.crdt.blocks.len(), 1);
+    }
+
+    #[test]
+    fn test_insertions_and_removals_split_blocks() {
+        let (mut state, mut store) = replica(0);
+        state.process_command(&insert(0, "Hello world"), &mut store).unwrap();
+        state.process_command(&insert(5, ","), &mut store).unwrap();
+        state.process_command(&BlockRGACommand::Remove(7, 2), &mut store).unwrap();
+
+        assert_eq!(text(&state), "Hello, rld");
+        assert_eq!(state.crdt.blocks.len(), 5);
+
+        assert_eq!(
+            state.process_command(&BlockRGACommand::Remove(8, 3), &mut store).err(),
+            Some(CausalError::IndexOutOfBounds(10))
+        );
+    }
+
+    #[test]
+    fn test_concurrent_edits_converge() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+
+        state_0.process_command(&insert(0, "abcdef"), &mut store_0).unwrap();
+        state_1.process_replicated(0, 1, store_0.events.clone(), &mut store_1).unwrap();
+
+        state_0.process_command(&insert(3, "XY"), &mut store_0).unwrap();
+        state_1.process_command(&insert(3, "12"), &mut store_1).unwrap();
+        state_1.process_command(&BlockRGACommand::Remove(1, 4), &mut store_1).unwrap();
+        state_0.process_replicated(1, 3, store_1.events[1..].to_vec(), &mut store_0).unwrap();
+        state_1.process_replicated(0, 2, store_0.events[1..2].to_vec(), &mut store_1).unwrap();
+
+        assert_eq!(text(&state_0), text(&state_1));
+        assert_eq!(text(&state_0), "aXYdef");
+    }
+
+    #[test]
+    fn test_stable_removals_purge_and_merge_blocks() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+        state_0.process_command(&insert(0, "Hello world"), &mut store_0).unwrap();
+        state_0.process_command(&insert(5, ","), &mut store_0).unwrap();
+        state_0.process_command(&BlockRGACommand::Remove(5, 1), &mut store_0).unwrap();
+        state_0.process_command(&BlockRGACommand::Remove(6, 2), &mut store_0).unwrap();
+        state_1.process_replicated(0, 4, store_0.events.clone(), &mut store_1).unwrap();
+        assert_eq!(state_0.crdt.block_count(), 5);
+
+        let frontier = state_0.version.clone();
+        state_0.crdt.stable(&frontier);
+
+        // The removed blocks are purged, then "Hello" and " " are merged back since their pointers are consecutive.
+        assert_eq!(text(&state_0), "Hello rld");
+        assert_eq!(state_0.crdt.block_count(), 2);
+
+        // The purged state still applies the operations of the other replicas.
+        state_1.process_command(&insert(5, "!"), &mut store_1).unwrap();
+        state_1.process_command(&BlockRGACommand::Remove(6, 2), &mut store_1).unwrap();
+        state_0.process_replicated(1, 2, store_1.events[4..].to_vec(), &mut store_0).unwrap();
+
+        assert_eq!(text(&state_0), "Hello!ld");
+        assert_eq!(text(&state_0), text(&state_1));
+    }
+}
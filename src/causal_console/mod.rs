@@ -41,11 +41,9 @@ impl InputField {
                         receiver.insert_at(self.cursor_position, character);
                         self.insert(&term, character);
                     }
-                    Key::Backspace => {
-                        if self.cursor_position > 0 {
-                            receiver.remove_at(self.cursor_position - 1);
-                            self.remove(&term);
-                        }
+                    Key::Backspace if self.cursor_position > 0 => {
+                        receiver.remove_at(self.cursor_position - 1);
+                        self.remove(&term);
                     }
                     Key::Enter => {
                         break;
@@ -69,26 +67,26 @@ impl InputField {
     fn cursor_backward(&mut self, term: &Term) {
         if self.cursor_position > 0 {
             self.cursor_position -= 1;
-            self.render_value(&term);
+            self.render_value(term);
         }
     }
 
     fn cursor_forward(&mut self, term: &Term) {
         if self.cursor_position < self.value.len() {
             self.cursor_position += 1;
-            self.render_value(&term);
+            self.render_value(term);
         }
     }
 
     fn insert(&mut self, term: &Term, character: char) {
         self.value.insert(self.cursor_position, character);
         self.cursor_position += 1;
-        self.render_value(&term);
+        self.render_value(term);
     }
 
     fn remove(&mut self, term: &Term) {
         self.value.remove(self.cursor_position - 1);
         self.cursor_position -= 1;
-        self.render_value(&term);
+        self.render_value(term);
     }
 }
\ No newline at end of file
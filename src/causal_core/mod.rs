@@ -1,17 +1,45 @@
 use std::cmp;
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io;
 use std::marker::PhantomData;
 
 use crate::{Concurrent, Greater, VectorClock};
+use crate::causal_time::ClockComparison::{Equal, Less};
 
 /** TYPES **/
 pub type ReplicaId = isize;
 pub type SeqNr = u64;
 pub type VTime = VectorClock<ReplicaId>;
 pub type ObservedMap = HashMap<ReplicaId, SeqNr>;
+pub type MatrixClock = HashMap<ReplicaId, VTime>;
+// The id of the replaying replica, the last seq nr it looped over, the events and where the next batch starts.
+pub type Replay<EVENT> = (ReplicaId, SeqNr, Vec<Event<EVENT>>, Option<SeqNr>);
+
+// Number of events read at a time while looking for the end of the stable prefix of the log.
+const TRUNCATION_SCAN_SIZE: usize = 100;
 
 
 /** DATA STRUCTURES **/
+// Identifies an event by its origin and its seq nr in the log of the origin, no matter which replica relayed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dot {
+    pub origin: ReplicaId,
+    pub seq_nr: SeqNr,
+}
+
+// The dots of the events delivered by a replica. The events of an origin are always delivered in the order they were
+// created, thus for every origin it is enough to keep the seq nr of the latest delivered event, which covers all the
+// previous ones. Unlike a vector clock of the replica versions, it can tell whether a single event has been delivered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DottedVersionVector {
+    vector: HashMap<ReplicaId, SeqNr>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Event<EVENT>
     where EVENT: Clone
 {
@@ -22,6 +50,17 @@ pub struct Event<EVENT>
     pub data: EVENT,
 }
 
+// The metadata which identifies an event, without its data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EventMetadata {
+    pub origin: ReplicaId,
+    // The seq nr of the event in the log of its origin.
+    pub seq_nr: SeqNr,
+    pub version: VTime,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ReplicaState<C, STATE, CMD, EVENT>
     where C: CRDT<STATE, CMD, EVENT> + Clone,
           EVENT: Clone
@@ -30,6 +69,12 @@ pub struct ReplicaState<C, STATE, CMD, EVENT>
     pub seq_nr: SeqNr,
     pub version: VTime,
     pub observed: ObservedMap,
+    // The dots of the delivered events, used to drop the duplicates received through different replicas.
+    pub delivered: DottedVersionVector,
+    // Events received from other replicas whose causal dependencies have not been delivered yet.
+    pub pending: Vec<Event<EVENT>>,
+    // The versions that each connected replica is known to have delivered.
+    pub acknowledged: MatrixClock,
     pub crdt: C,
     _1: PhantomData<STATE>,
     _2: PhantomData<CMD>,
@@ -37,6 +82,25 @@ pub struct ReplicaState<C, STATE, CMD, EVENT>
 }
 
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CausalError {
+    // The index given in a command is not valid for the current state of the CRDT.
+    IndexOutOfBounds(usize),
+    // The event refers to an element that the CRDT doesn't know about.
+    UnknownElement,
+    // The path given in a command doesn't lead to a value of the expected type.
+    InvalidPath,
+    // The message refers to a replica that is not connected.
+    UnknownReplica(ReplicaId),
+    // The message couldn't be delivered to the replica.
+    Unreachable(ReplicaId),
+    // The event store couldn't be read or written.
+    Storage(String),
+    // The command would take a value beyond the range of its type.
+    Overflow,
+}
+
+
 /** TRAITS **/
 pub trait CRDT<STATE, CMD, EVENT>
     where EVENT: Clone
@@ -45,25 +109,110 @@ pub trait CRDT<STATE, CMD, EVENT>
     fn default(replica_id: Option<ReplicaId>) -> Self;
     // Queries the state of the CRDT.
     fn query(&self) -> STATE;
-    // Takes some operation send by the user, and changes it into event.
-    fn prepare(&self, command: &CMD) -> EVENT;
-    // Called when a new event arrives.
-    fn effect(&mut self, event: &Event<EVENT>);
+    // Takes some operation send by the user, and changes it into event. It fails if the command is not valid for the
+    // current state, in which case nothing is changed.
+    fn prepare(&self, command: &CMD) -> Result<EVENT, CausalError>;
+    // Called when a new event arrives. It must not change the state of the CRDT when it fails.
+    fn effect(&mut self, event: &Event<EVENT>) -> Result<(), CausalError>;
+    // Called when the causally stable frontier advances, that is, when all the events with a version lower or equal
+    // than the frontier have been delivered by every replica. It can be used to garbage collect metadata.
+    fn stable(&mut self, _frontier: &VTime) {}
 }
 
 pub trait EventStore<C, STATE, CMD, EVENT>
     where C: CRDT<STATE, CMD, EVENT> + Clone,
           EVENT: Clone
 {
-    fn save_snapshot(&mut self, state: &ReplicaState<C, STATE, CMD, EVENT>);
-    fn load_snapshot(&self) -> Option<ReplicaState<C, STATE, CMD, EVENT>>;
-
-    fn save_events(&mut self, events: Vec<Event<EVENT>>);
-    fn load_events(&self, start_seq_nr: SeqNr) -> Vec<Event<EVENT>>;
+    // All the operations fail with CausalError::Storage when the underlying storage can't be read or written.
+    fn save_snapshot(&mut self, state: &ReplicaState<C, STATE, CMD, EVENT>) -> Result<(), CausalError>;
+    fn load_snapshot(&self) -> Result<Option<ReplicaState<C, STATE, CMD, EVENT>>, CausalError>;
+
+    fn save_events(&mut self, events: Vec<Event<EVENT>>) -> Result<(), CausalError>;
+    fn load_events(&self, start_seq_nr: SeqNr) -> Result<Vec<Event<EVENT>>, CausalError>;
+    // Loads at most limit events, starting from start_seq_nr.
+    fn load_events_batch(&self, start_seq_nr: SeqNr, limit: usize) -> Result<Vec<Event<EVENT>>, CausalError>;
+    // Removes the events whose local seq nr is lower than end_seq_nr. A store may keep some of them around, e.g. when
+    // they share a file with events that must be kept.
+    fn truncate_events(&mut self, end_seq_nr: SeqNr) -> Result<(), CausalError>;
 }
 
 
 /** IMPLEMENTATIONS **/
+impl Display for CausalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CausalError::IndexOutOfBounds(index) => write!(f, "The index {} is out of bounds.", index),
+            CausalError::UnknownElement => write!(f, "The event refers to an unknown element."),
+            CausalError::InvalidPath => write!(f, "The path doesn't lead to a value of the expected type."),
+            CausalError::UnknownReplica(replica_id) => write!(f, "The replica {} is not connected.", replica_id),
+            CausalError::Unreachable(replica_id) => write!(f, "The replica {} is unreachable.", replica_id),
+            CausalError::Storage(message) => write!(f, "The event store failed: {}", message),
+            CausalError::Overflow => write!(f, "The value would overflow."),
+        }
+    }
+}
+
+impl Error for CausalError {}
+
+impl From<io::Error> for CausalError {
+    fn from(error: io::Error) -> Self {
+        CausalError::Storage(error.to_string())
+    }
+}
+
+impl DottedVersionVector {
+    pub fn from(vector: HashMap<ReplicaId, SeqNr>) -> DottedVersionVector {
+        DottedVersionVector {
+            vector,
+        }
+    }
+
+    pub fn vector(&self) -> &HashMap<ReplicaId, SeqNr> {
+        &self.vector
+    }
+
+    pub fn contains(&self, dot: &Dot) -> bool {
+        self.vector.get(&dot.origin).is_some_and(|seq_nr| dot.seq_nr <= *seq_nr)
+    }
+
+    pub fn add(&mut self, dot: Dot) {
+        let seq_nr = self.vector.entry(dot.origin).or_insert(0);
+        *seq_nr = cmp::max(*seq_nr, dot.seq_nr);
+    }
+}
+
+impl<EVENT> Event<EVENT>
+    where EVENT: Clone
+{
+    pub fn dot(&self) -> Dot {
+        Dot {
+            origin: self.origin,
+            seq_nr: self.origin_seq_nr,
+        }
+    }
+
+    // The event seen by a CRDT nested in another one, with the same metadata as the event of the outer CRDT.
+    pub fn nested<NESTED>(&self, data: NESTED) -> Event<NESTED>
+        where NESTED: Clone
+    {
+        Event {
+            origin: self.origin,
+            origin_seq_nr: self.origin_seq_nr,
+            local_seq_nr: self.local_seq_nr,
+            version: self.version.clone(),
+            data,
+        }
+    }
+
+    pub fn metadata(&self) -> EventMetadata {
+        EventMetadata {
+            origin: self.origin,
+            seq_nr: self.origin_seq_nr,
+            version: self.version.clone(),
+        }
+    }
+}
+
 impl<EVENT> Clone for Event<EVENT>
     where EVENT: Clone
 {
@@ -87,6 +236,9 @@ impl<C, STATE, CMD, EVENT> Clone for ReplicaState<C, STATE, CMD, EVENT>
             seq_nr: self.seq_nr,
             version: self.version.clone(),
             observed: self.observed.clone(),
+            delivered: self.delivered.clone(),
+            pending: self.pending.clone(),
+            acknowledged: self.acknowledged.clone(),
             crdt: self.crdt.clone(),
             _1: PhantomData,
             _2: PhantomData,
@@ -111,6 +263,9 @@ impl<C, STATE, CMD, EVENT> ReplicaState<C, STATE, CMD, EVENT>
             seq_nr,
             version,
             observed,
+            delivered: DottedVersionVector::default(),
+            pending: vec![],
+            acknowledged: HashMap::new(),
             crdt,
             _1: PhantomData,
             _2: PhantomData,
@@ -128,84 +283,85 @@ impl<C, STATE, CMD, EVENT> ReplicaState<C, STATE, CMD, EVENT>
         )
     }
 
-    pub fn process_event(&mut self, event: &Event<EVENT>) -> ReplicaState<C, STATE, CMD, EVENT> {
+    pub fn process_event(&mut self, event: &Event<EVENT>) -> Result<ReplicaState<C, STATE, CMD, EVENT>, CausalError> {
+        // We dispatch the event to the crdt.
+        self.crdt.effect(event)?;
         // We merge the vector clock.
         self.version.merge(self.id, &event.version);
         // We update the point in which we were consuming events from the other machine.
         self.observed.insert(event.origin, event.origin_seq_nr);
-        // We dispatch the event to the crdt.
-        self.crdt.effect(event);
+        self.delivered.add(event.dot());
+        self.seq_nr = cmp::max(self.seq_nr, event.local_seq_nr);
 
-        return ReplicaState::new(
-            self.id,
-            cmp::max(self.seq_nr, event.local_seq_nr),
-            self.version.clone(),
-            self.observed.clone(),
-            self.crdt.clone(),
-        );
+        Ok(self.clone())
     }
 
     pub fn process_command(
         &mut self,
         command: &CMD,
         event_store: &mut impl EventStore<C, STATE, CMD, EVENT>,
-    ) -> ReplicaState<C, STATE, CMD, EVENT> {
+    ) -> Result<Event<EVENT>, CausalError> {
+        // We prepare the data for the event, an invalid command leaves the replica untouched.
+        let data = self.crdt.prepare(command)?;
         // We increment both the sequence number and the vector clock for this replica.
         let seq_nr = self.seq_nr + 1;
-        self.version.increment(self.id);
-        // We prepare the data for the event.
-        let data = self.crdt.prepare(&command);
+        let mut version = self.version.clone();
+        version.increment(self.id);
         // We create, apply and store the event.
         let event = Event {
             origin: self.id,
             origin_seq_nr: seq_nr,
             local_seq_nr: seq_nr,
-            version: self.version.clone(),
+            version,
             data,
         };
-        self.crdt.effect(&event);
-        event_store.save_events(vec![event]);
+        self.crdt.effect(&event)?;
+        self.version = event.version.clone();
+        self.seq_nr = seq_nr;
+        self.delivered.add(event.dot());
+        event_store.save_events(vec![event.clone()])?;
 
-        return ReplicaState::new(
-            self.id,
-            seq_nr,
-            self.version.clone(),
-            self.observed.clone(),
-            self.crdt.clone(),
-        );
+        Ok(event)
     }
 
-    // pub fn process_connect(&mut self, replica_id: ReplicaId) -> (SeqNr, VTime) {
-    //     return (
-    //         // We send the next seq number we want. If we didn't observe anything, it will be 1.
-    //         *self.observed.get(&replica_id).or(Some(&0)).unwrap() + 1,
-    //         // We send our version vector because it will be used to avoid duplicates being sent.
-    //         self.version.clone()
-    //     );
-    // }
+    pub fn process_connect(&mut self, replica_id: ReplicaId) {
+        // Every connected replica takes part in the computation of the stable frontier, until we hear from it nothing
+        // can be considered stable.
+        self.acknowledged.entry(replica_id).or_insert_with(VectorClock::init);
+    }
 
     pub fn process_sync(&mut self, replica_id: ReplicaId) -> (ReplicaId, SeqNr, VTime) {
-        return (
+        (
             self.id,
             // We send the next seq number we want. If we didn't observe anything, it will be 1.
-            *self.observed.get(&replica_id).or(Some(&0)).unwrap() + 1,
+            self.observed.get(&replica_id).copied().unwrap_or(0) + 1,
             // We send our version vector because it will be used to avoid duplicates being sent.
             self.version.clone()
-        );
+        )
     }
 
-    // TODO: implement fetch limit in order to have an upper bound.
     pub fn process_replay(
         &mut self,
+        sender: ReplicaId,
         seq_nr: SeqNr,
         version: VTime,
+        batch_size: usize,
         event_store: &impl EventStore<C, STATE, CMD, EVENT>,
-    ) -> (ReplicaId, SeqNr, Vec<Event<EVENT>>) {
+    ) -> Result<Replay<EVENT>, CausalError> {
+        // An empty batch would never reach the end of the log, thus at least one event is replayed.
+        let batch_size = cmp::max(batch_size, 1);
+        // We load one more event than requested, just to know whether another batch must be fetched.
+        let mut batch = event_store.load_events_batch(seq_nr, batch_size.saturating_add(1))?;
+        let continuation = if batch.len() > batch_size {
+            batch.truncate(batch_size);
+            batch.last().map(|event| event.local_seq_nr + 1)
+        } else {
+            None
+        };
+
         // By default the last seq nr is 0 and it will change in case some events are consumed.
         let mut last_seq_nr = 0;
-        let events = event_store
-            .load_events(seq_nr)
-            .clone()
+        let events = batch
             .into_iter()
             .filter(|event| {
                 // For each event we keep track of the maximum seq nr seen so far.
@@ -215,117 +371,481 @@ impl<C, STATE, CMD, EVENT> ReplicaState<C, STATE, CMD, EVENT>
             })
             .collect();
 
-        return (self.id, last_seq_nr, events);
+        // The version of the sender tells us what it has delivered so far, which might make new events stable.
+        self.acknowledge(sender, &version);
+        self.stabilize();
+
+        Ok((self.id, last_seq_nr, events, continuation))
     }
 
     pub fn process_replicated(
         &mut self,
         sender: ReplicaId,
-        // The last_seq_nr is not used for now because we just compute it, however it should be used as an optimization
-        // we for example we receive empty events from the server but we did actually loop over elements.
+        // The last_seq_nr is the last seq nr that the sender has looped over, even if the event was not sent.
         // E.g. I request for events from 5 and the recipient has from 5 to 10 but all of those elements have their
-        // version <= than mine, therefore they won't be sent but if they aren't sent I will not loop over them and so the observed
-        // map is not updated with 10 inside, but it remains with 5.
+        // version <= than mine, therefore they won't be sent but the observed map must still be updated with 10,
+        // otherwise the next request would start again from 5.
         last_seq_nr: SeqNr,
         events: Vec<Event<EVENT>>,
         event_store: &mut impl EventStore<C, STATE, CMD, EVENT>,
-    ) -> Option<ReplicaState<C, STATE, CMD, EVENT>> {
-        // We get the last observed seq nr of the sender, that is, the replica sending us new events.
-        let mut remote_seq_nr = self.observed
+    ) -> Result<Option<ReplicaState<C, STATE, CMD, EVENT>>, CausalError> {
+        // We save the last remote seq nr we know to have read, explanation given above. Events that are not
+        // yet deliverable are kept in the pending queue, thus we consider them as read.
+        let remote_seq_nr = self.observed
             .get(&sender)
-            .or(Some(&0))
-            .unwrap()
-            .clone();
-        let mut new_state = None;
+            .copied()
+            .unwrap_or(0);
+        self.observed.insert(sender, cmp::max(remote_seq_nr, last_seq_nr));
 
+        self.receive(events, event_store)
+    }
+
+    // The events pushed by a replica as soon as they are created are delivered like the replicated ones, but they
+    // don't move the point from which we read the log of the sender, since the events before them might be missing.
+    pub fn process_pushed(
+        &mut self,
+        _sender: ReplicaId,
+        events: Vec<Event<EVENT>>,
+        event_store: &mut impl EventStore<C, STATE, CMD, EVENT>,
+    ) -> Result<Option<ReplicaState<C, STATE, CMD, EVENT>>, CausalError> {
+        self.receive(events, event_store)
+    }
+
+    fn receive(
+        &mut self,
+        events: Vec<Event<EVENT>>,
+        event_store: &mut impl EventStore<C, STATE, CMD, EVENT>,
+    ) -> Result<Option<ReplicaState<C, STATE, CMD, EVENT>>, CausalError> {
         // We filter all the events received by unseen because in case of concurrency we might have
-        // received some duplicates.
+        // received some duplicates, either already applied or already waiting in the pending queue.
         let unseen_events = events
             .into_iter()
-            .filter(|event| self.unseen(event))
+            .filter(|event| self.unseen(event) && !self.is_pending(event))
             .collect::<Vec<Event<EVENT>>>();
 
-        // Explanation given above.
-        if unseen_events.is_empty() {
-            self.observed.insert(sender, last_seq_nr);
+        self.pending.extend(unseen_events);
+
+        // We deliver all the pending events that became causally ready, until no more progress can be made.
+        let (new_events, error) = self.deliver_pending();
+
+        // The events delivered before a failure are part of the state, thus they must be stored anyway.
+        let delivered = !new_events.is_empty();
+        if delivered {
+            // We store all the modified events into the event store.
+            event_store.save_events(new_events)?;
+            self.stabilize();
         }
 
-        println!("Unseen size {} from {}", unseen_events.len(), sender);
+        match error {
+            Some(error) => Err(error),
+            None if delivered => Ok(Some(self.clone())),
+            None => Ok(None),
+        }
+    }
 
-        let new_events = unseen_events
-            .into_iter()
-            .map(|event| {
-                // We increment the local seq nr.
-                let seq_nr = self.seq_nr + 1;
-                // We merge the version vector with the incoming vector.
-                self.version.merge(self.id, &event.version);
-                // We compute the seq nr until which we have read all the events from sender.
-                remote_seq_nr = cmp::max(remote_seq_nr, event.local_seq_nr);
-                // We save the last remote seq nr we know to have read.
-                self.observed.insert(sender, remote_seq_nr);
-                // We perform the effect on the crdt.
-                self.crdt.effect(&event);
-                // We compute the new replica state.
-                new_state = Some(ReplicaState::new(
-                    self.id,
-                    seq_nr,
-                    self.version.clone(),
-                    self.observed.clone(),
-                    self.crdt.clone(),
-                ));
-                // We create a new event to be applied locally with the newly updated local seq nr.
-                Event {
-                    origin: event.origin,
-                    origin_seq_nr: event.origin_seq_nr,
-                    local_seq_nr: seq_nr,
-                    version: event.version.clone(),
-                    data: event.data.clone(),
-                }
-            })
-            .collect::<Vec<Event<EVENT>>>();
+    // Delivers the causally ready events, stopping at the first event whose effect fails. The failed event is put back
+    // into the pending queue, since the observed map already covers it, and its delivery is retried the next time
+    // events are received.
+    fn deliver_pending(&mut self) -> (Vec<Event<EVENT>>, Option<CausalError>) {
+        let mut new_events = vec![];
+        let mut error = None;
+
+        while let Some(index) = self.pending.iter().position(|event| self.is_causally_ready(event)) {
+            let event = self.pending.remove(index);
+            // We perform the effect on the crdt.
+            if let Err(effect_error) = self.crdt.effect(&event) {
+                self.pending.insert(index, event);
+                error = Some(effect_error);
+                break;
+            }
+            // We increment the local seq nr.
+            self.seq_nr += 1;
+            // We merge the version vector with the incoming vector.
+            self.version.merge(self.id, &event.version);
+            self.delivered.add(event.dot());
+            // The origin must have delivered everything the event depends on.
+            self.acknowledged
+                .entry(event.origin)
+                .or_insert_with(VectorClock::init)
+                .merge(event.origin, &event.version);
+            // We create a new event to be applied locally with the newly updated local seq nr.
+            new_events.push(Event {
+                origin: event.origin,
+                origin_seq_nr: event.origin_seq_nr,
+                local_seq_nr: self.seq_nr,
+                version: event.version,
+                data: event.data,
+            });
+        }
+
+        // Events that have been delivered in the meanwhile through another path are dropped.
+        let delivered = &self.delivered;
+        self.pending.retain(|event| !delivered.contains(&event.dot()));
+
+        (new_events, error)
+    }
+
+    // An event is causally ready when it is the next event produced by its origin and when all the events it
+    // depends on, coming from the other replicas, have already been delivered.
+    pub fn is_causally_ready(&self, event: &Event<EVENT>) -> bool {
+        let mut next_version = self.version.clone();
+        next_version.increment(event.origin);
+
+        let comparison = event.version.compare(&next_version);
+        (comparison == Less || comparison == Equal)
+            && event.version.get(&event.origin) == next_version.get(&event.origin)
+    }
+
+    fn acknowledge(&mut self, replica_id: ReplicaId, version: &VTime) {
+        // We can trust the version of a replica only if we have delivered all of its own events covered by the
+        // version, otherwise an event concurrent to a stable one might still be on its way to us.
+        if version.get(&replica_id) > self.version.get(&replica_id) {
+            return;
+        }
+
+        self.acknowledged
+            .entry(replica_id)
+            .or_insert_with(VectorClock::init)
+            .merge(replica_id, version);
+    }
+
+    // The stable frontier is the greatest version that every known replica, including this one, has delivered. A
+    // replica that doesn't know any other one can't tell who might join later, thus nothing is stable for it.
+    pub fn stable_frontier(&self) -> VTime {
+        if self.acknowledged.keys().all(|replica_id| *replica_id == self.id) {
+            return VectorClock::init();
+        }
+
+        let mut frontier = self.version.clone();
+        for (replica_id, version) in &self.acknowledged {
+            if *replica_id != self.id {
+                frontier.meet(version);
+            }
+        }
+
+        frontier
+    }
+
+    fn stabilize(&mut self) {
+        let frontier = self.stable_frontier();
+        self.crdt.stable(&frontier);
+    }
+
+    fn is_pending(&self, event: &Event<EVENT>) -> bool {
+        let dot = event.dot();
+        self.pending.iter().any(|pending_event| pending_event.dot() == dot)
+    }
 
-        // We store all the modified events into the event store.
-        event_store.save_events(new_events);
-
-        return new_state;
-    }
-
-    // Supposing we have three replicas 1,2,3 with this initial state (replica 2 pulled from 1):
-    // replica_id -> local_seq_nr-event [vector_clock] [observed]
-    // 1 -> 1-A [1,0,0] []
-    // 2 -> 1-A [1,0,0] [1:1]
-    // 3 -> empty
-    // Suppose now 3 sends a [REPLICATE] request concurrently to both 1 and 2 (containing the vector clock [0,0,0]) and the answer from 2 is applied first:
-    // 1 -> 1-A [1,0,0] []
-    // 2 -> 1-A [1,0,0] [1:1]
-    // 3 -> 1-A [1,0,0] [2:1]
-    // Then answer from 1 is received and unseen() call sees that we observed 0 from 1 and we received an event with seq nr 1, therefore
-    // we apply this event resulting in:
-    // 1 -> 1-A [1,0,0] []
-    // 2 -> 1-A [1,0,0] [1:1]
-    // 3 -> 1-A [1,0,0] 2-A [1,0,0] [2:1,1:1] **DUPLICATE EVENT**
-    //
-    // A solution might be to add to the observed also the origin but it needs further testing.
-    // let origin_observed = self.observed.get(&event.origin).or(Some(&0)).unwrap();
-    // self.observed.insert(event.origin, cmp::max(*origin_observed, event.origin_seq_nr));
-    //
-    // pub fn unseen(&self, event: &Event<EVENT>) -> bool {
-    //     match self.observed.get(&event.origin) {
-    //         Some(observed_seq_nr) if event.origin_seq_nr > *observed_seq_nr => true,
-    //         _ => {
-    //             let comparison = event.version.compare(&self.version);
-    //             comparison == Greater || comparison == Concurrent
-    //         }
-    //     }
-    // }
-
-    // This variant just uses vector clocks for comparison, which is enough.
+    // An event is identified by its dot, thus a duplicate is detected no matter which replica relayed it.
     pub fn unseen(&self, event: &Event<EVENT>) -> bool {
-        let comparison = event.version.compare(&self.version);
-        comparison == Greater || comparison == Concurrent
+        !self.delivered.contains(&event.dot())
+    }
+
+    pub fn process_snapshot(&self, event_store: &mut impl EventStore<C, STATE, CMD, EVENT>) -> Result<(), CausalError> {
+        event_store.save_snapshot(self)?;
+
+        // The snapshot covers the whole log, but an event that is not stable yet might still be requested by a
+        // replica which hasn't delivered it, therefore we only remove the stable prefix of the log.
+        let end_seq_nr = self.stable_prefix_end(event_store)?;
+        event_store.truncate_events(end_seq_nr)
+    }
+
+    // Returns the local seq nr of the first event in the log which is not yet stable.
+    fn stable_prefix_end(&self, event_store: &impl EventStore<C, STATE, CMD, EVENT>) -> Result<SeqNr, CausalError> {
+        let frontier = self.stable_frontier();
+        let mut seq_nr = 0;
+
+        loop {
+            let batch = event_store.load_events_batch(seq_nr, TRUNCATION_SCAN_SIZE)?;
+            if batch.is_empty() {
+                return Ok(self.seq_nr + 1);
+            }
+
+            for event in batch {
+                let comparison = event.version.compare(&frontier);
+                if comparison != Less && comparison != Equal {
+                    return Ok(event.local_seq_nr);
+                }
+                seq_nr = event.local_seq_nr + 1;
+            }
+        }
     }
 
     pub fn process_query(&self) -> STATE {
         self.crdt.query()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CRDT, Event, ReplicaId, ReplicaState};
+    use crate::causal_core::CausalError;
+    use crate::causal_rga::{RGA, RGACommand, RGAOperation};
+    use crate::causal_utils::InMemory;
+
+    // Appends the values of the events, its effect fails as many times as requested before succeeding again.
+    #[derive(Clone)]
+    struct Flaky {
+        values: Vec<i64>,
+        failures: usize,
+    }
+
+    impl CRDT<Vec<i64>, i64, i64> for Flaky {
+        fn default(_: Option<ReplicaId>) -> Self {
+            Flaky {
+                values: vec![],
+                failures: 0,
+            }
+        }
+
+        fn query(&self) -> Vec<i64> {
+            self.values.clone()
+        }
+
+        fn prepare(&self, command: &i64) -> Result<i64, CausalError> {
+            Ok(*command)
+        }
+
+        fn effect(&mut self, event: &Event<i64>) -> Result<(), CausalError> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(CausalError::UnknownElement);
+            }
+
+            self.values.push(event.data);
+            Ok(())
+        }
+    }
+
+    type RGAState = ReplicaState<RGA<char>, Vec<char>, RGACommand<char>, RGAOperation<char>>;
+    type RGAStore = InMemory<RGA<char>, Vec<char>, RGACommand<char>, RGAOperation<char>>;
+
+    fn replica(id: isize) -> (RGAState, RGAStore) {
+        (ReplicaState::create(id, RGA::default(Some(id))), InMemory::create())
+    }
+
+    fn produce(commands: Vec<RGACommand<char>>) -> Vec<Event<RGAOperation<char>>> {
+        let (mut state, mut store) = replica(0);
+        for command in commands {
+            state.process_command(&command, &mut store).unwrap();
+        }
+
+        store.events
+    }
+
+    #[test]
+    fn test_out_of_order_events_are_buffered() {
+        let events = produce(vec![RGACommand::Insert(0, 'a'), RGACommand::Insert(1, 'b')]);
+        let (mut state, mut store) = replica(1);
+
+        let new_state = state.process_replicated(0, 2, vec![events[1].clone()], &mut store).unwrap();
+
+        assert!(new_state.is_none());
+        assert_eq!(state.pending.len(), 1);
+        assert!(state.process_query().is_empty());
+        assert!(store.events.is_empty());
+    }
+
+    #[test]
+    fn test_buffered_events_are_released() {
+        let events = produce(vec![RGACommand::Insert(0, 'a'), RGACommand::Insert(1, 'b')]);
+        let (mut state, mut store) = replica(1);
+
+        state.process_replicated(0, 2, vec![events[1].clone()], &mut store).unwrap();
+        let new_state = state
+            .process_replicated(0, 1, vec![events[0].clone()], &mut store).unwrap()
+            .unwrap();
+
+        assert!(new_state.pending.is_empty());
+        assert_eq!(new_state.process_query(), vec!['a', 'b']);
+        assert_eq!(new_state.seq_nr, 2);
+        assert_eq!(store.events.iter().map(|event| event.local_seq_nr).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_duplicate_events_are_not_buffered() {
+        let events = produce(vec![RGACommand::Insert(0, 'a'), RGACommand::Insert(1, 'b')]);
+        let (mut state, mut store) = replica(1);
+
+        state.process_replicated(0, 2, vec![events[1].clone()], &mut store).unwrap();
+        state.process_replicated(0, 2, vec![events[1].clone()], &mut store).unwrap();
+
+        assert_eq!(state.pending.len(), 1);
+
+        state.process_replicated(0, 2, events, &mut store).unwrap();
+
+        assert!(state.pending.is_empty());
+        assert_eq!(state.process_query(), vec!['a', 'b']);
+    }
+
+    #[test]
+    fn test_failed_effect_is_retried() {
+        let mut store_0 = InMemory::create();
+        let mut state_0 = ReplicaState::create(0, Flaky::default(Some(0)));
+        state_0.process_command(&1, &mut store_0).unwrap();
+        state_0.process_command(&2, &mut store_0).unwrap();
+
+        let mut store_1 = InMemory::create();
+        let mut state_1 = ReplicaState::create(1, Flaky::default(Some(1)));
+        state_1.crdt.failures = 1;
+        let result = state_1.process_replicated(0, 2, store_0.events.clone(), &mut store_1);
+
+        // The log of replica 0 has been read up to the second event, which won't be sent again.
+        assert_eq!(result.err(), Some(CausalError::UnknownElement));
+        assert_eq!(state_1.observed.get(&0), Some(&2));
+        assert_eq!(state_1.pending.len(), 2);
+        assert!(state_1.process_query().is_empty());
+
+        // The next sync doesn't send anything new, but the pending events are delivered.
+        let (_, seq_nr, version) = state_1.process_sync(0);
+        let (sender, last_seq_nr, events, _) = state_0.process_replay(1, seq_nr, version, 10, &store_0).unwrap();
+        assert!(events.is_empty());
+        state_1.process_replicated(sender, last_seq_nr, events, &mut store_1).unwrap();
+
+        assert!(state_1.pending.is_empty());
+        assert_eq!(state_1.process_query(), vec![1, 2]);
+        assert_eq!(store_1.events.len(), 2);
+    }
+
+    #[test]
+    fn test_pushed_events_do_not_move_observed() {
+        let events = produce(vec![RGACommand::Insert(0, 'a'), RGACommand::Insert(1, 'b')]);
+        let (mut state, mut store) = replica(1);
+
+        // The first event is lost, the second one waits for it without skipping it in the log of the sender.
+        state.process_pushed(0, vec![events[1].clone()], &mut store).unwrap();
+        assert_eq!(state.pending.len(), 1);
+        assert_eq!(state.process_sync(0).1, 1);
+
+        state.process_replicated(0, 2, events.clone(), &mut store).unwrap();
+        state.process_pushed(0, vec![events[1].clone()], &mut store).unwrap();
+
+        assert!(state.pending.is_empty());
+        assert_eq!(store.events.len(), 2);
+        assert_eq!(state.process_query(), vec!['a', 'b']);
+    }
+
+    #[test]
+    fn test_stable_frontier() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+        state_0.process_connect(1);
+        state_1.process_connect(0);
+
+        state_0.process_command(&RGACommand::Insert(0, 'a'), &mut store_0).unwrap();
+        state_0.process_command(&RGACommand::Insert(1, 'b'), &mut store_0).unwrap();
+
+        // Nothing is stable until replica 1 tells us what it has delivered.
+        assert_eq!(state_0.stable_frontier().get(&0), 0);
+
+        state_1.process_replicated(0, 2, store_0.events.clone(), &mut store_1).unwrap();
+        assert_eq!(state_1.stable_frontier().get(&0), 2);
+
+        let (_, seq_nr, version) = state_1.process_sync(0);
+        state_0.process_replay(1, seq_nr, version, 10, &store_0).unwrap();
+        assert_eq!(state_0.stable_frontier().get(&0), 2);
+    }
+
+    #[test]
+    fn test_acknowledgement_ahead_of_delivery_is_ignored() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+        state_0.process_connect(1);
+
+        state_0.process_command(&RGACommand::Insert(0, 'a'), &mut store_0).unwrap();
+        state_1.process_replicated(0, 1, store_0.events.clone(), &mut store_1).unwrap();
+        // Replica 1 produced an event that replica 0 has not delivered yet, thus its version can't be trusted.
+        state_1.process_command(&RGACommand::Insert(1, 'b'), &mut store_1).unwrap();
+
+        let (_, seq_nr, version) = state_1.process_sync(0);
+        state_0.process_replay(1, seq_nr, version, 10, &store_0).unwrap();
+        assert_eq!(state_0.stable_frontier().get(&0), 0);
+    }
+
+    #[test]
+    fn test_snapshot_truncates_stable_prefix() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+        state_0.process_connect(1);
+        for index in 0..3 {
+            state_0.process_command(&RGACommand::Insert(index, 'a'), &mut store_0).unwrap();
+        }
+
+        // Replica 1 has delivered only the first two events, the third one must be kept for it.
+        state_1.process_replicated(0, 2, store_0.events[..2].to_vec(), &mut store_1).unwrap();
+        let (_, seq_nr, version) = state_1.process_sync(0);
+        state_0.process_replay(1, seq_nr, version, 10, &store_0).unwrap();
+
+        state_0.process_snapshot(&mut store_0).unwrap();
+
+        assert_eq!(store_0.last_snapshot.as_ref().unwrap().seq_nr, 3);
+        assert_eq!(store_0.events.iter().map(|event| event.local_seq_nr).collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[test]
+    fn test_snapshot_without_peers_keeps_the_log() {
+        let (mut state_0, mut store_0) = replica(0);
+        for index in 0..3 {
+            state_0.process_command(&RGACommand::Insert(index, 'a'), &mut store_0).unwrap();
+        }
+
+        state_0.process_snapshot(&mut store_0).unwrap();
+        assert_eq!(store_0.events.len(), 3);
+
+        // A replica joining later can still read the whole log.
+        let (mut state_1, mut store_1) = replica(1);
+        let (_, seq_nr, version) = state_1.process_sync(0);
+        let (sender, last_seq_nr, events, _) = state_0.process_replay(1, seq_nr, version, 10, &store_0).unwrap();
+        state_1.process_replicated(sender, last_seq_nr, events, &mut store_1).unwrap();
+        assert_eq!(state_1.process_query(), state_0.process_query());
+    }
+
+    #[test]
+    fn test_replay_in_batches() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+        for index in 0..5 {
+            state_0.process_command(&RGACommand::Insert(index, 'a'), &mut store_0).unwrap();
+        }
+
+        let mut batches = 0;
+        let (_, mut seq_nr, mut version) = state_1.process_sync(0);
+        loop {
+            let (sender, last_seq_nr, events, continuation) = state_0
+                .process_replay(1, seq_nr, version, 2, &store_0).unwrap();
+            assert!(events.len() <= 2);
+            state_1.process_replicated(sender, last_seq_nr, events, &mut store_1).unwrap();
+            batches += 1;
+
+            match continuation {
+                Some(next_seq_nr) => {
+                    seq_nr = next_seq_nr;
+                    version = state_1.version.clone();
+                }
+                None => break
+            }
+        }
+
+        assert_eq!(batches, 3);
+        assert_eq!(state_1.process_query(), state_0.process_query());
+        assert_eq!(state_1.observed.get(&0), Some(&5));
+    }
+
+    #[test]
+    fn test_replay_batch_size_bounds() {
+        let (mut state_0, mut store_0) = replica(0);
+        for index in 0..3 {
+            state_0.process_command(&RGACommand::Insert(index, 'a'), &mut store_0).unwrap();
+        }
+        let (_, seq_nr, version) = replica(1).0.process_sync(0);
+
+        // An empty batch is replayed as a single event, with a continuation.
+        let (_, last_seq_nr, events, continuation) = state_0
+            .process_replay(1, seq_nr, version.clone(), 0, &store_0).unwrap();
+        assert_eq!((last_seq_nr, events.len(), continuation), (1, 1, Some(2)));
+
+        let (_, last_seq_nr, events, continuation) = state_0
+            .process_replay(1, seq_nr, version, usize::MAX, &store_0).unwrap();
+        assert_eq!((last_seq_nr, events.len(), continuation), (3, 3, None));
+    }
+}
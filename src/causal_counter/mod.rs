@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use crate::{CRDT, Event, ReplicaId};
+use crate::causal_core::CausalError;
+use crate::causal_counter::GCounterCommand::Increment;
+use crate::causal_counter::GCounterOperation::Incremented;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GCounterCommand {
+    Increment(u64),
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GCounterOperation {
+    Incremented(u64),
+}
+
+// A grow-only counter. Every replica keeps track of the increments of each origin, so that the value is simply the
+// sum of all of them.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GCounter {
+    increments: HashMap<ReplicaId, u64>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PNCounterCommand {
+    Increment(u64),
+    Decrement(u64),
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PNCounterOperation {
+    Incremented(u64),
+    Decremented(u64),
+}
+
+// A counter which supports both increments and decrements, made of two grow-only counters.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PNCounter {
+    increments: GCounter,
+    decrements: GCounter,
+}
+
+impl GCounter {
+    // The increments of concurrent replicas might still exceed the range together, thus they saturate.
+    fn add(&mut self, replica_id: ReplicaId, value: u64) {
+        let increments = self.increments.entry(replica_id).or_insert(0);
+        *increments = increments.saturating_add(value);
+    }
+
+    fn value(&self) -> u64 {
+        self.increments.values().fold(0, |sum, increments| sum.saturating_add(*increments))
+    }
+
+    // A replica refuses the increments that would overflow the value it currently knows.
+    fn check(&self, value: u64) -> Result<u64, CausalError> {
+        self.value().checked_add(value).map(|_| value).ok_or(CausalError::Overflow)
+    }
+}
+
+impl Clone for GCounter {
+    fn clone(&self) -> Self {
+        GCounter {
+            increments: self.increments.clone(),
+        }
+    }
+}
+
+impl CRDT<u64, GCounterCommand, GCounterOperation> for GCounter {
+    fn default(_: Option<ReplicaId>) -> Self {
+        GCounter {
+            increments: HashMap::new(),
+        }
+    }
+
+    fn query(&self) -> u64 {
+        self.value()
+    }
+
+    fn prepare(&self, command: &GCounterCommand) -> Result<GCounterOperation, CausalError> {
+        match command {
+            Increment(value) => Ok(Incremented(self.check(*value)?)),
+        }
+    }
+
+    fn effect(&mut self, event: &Event<GCounterOperation>) -> Result<(), CausalError> {
+        match &event.data {
+            Incremented(value) => self.add(event.origin, *value),
+        }
+
+        Ok(())
+    }
+}
+
+impl Clone for PNCounter {
+    fn clone(&self) -> Self {
+        PNCounter {
+            increments: self.increments.clone(),
+            decrements: self.decrements.clone(),
+        }
+    }
+}
+
+impl CRDT<i64, PNCounterCommand, PNCounterOperation> for PNCounter {
+    fn default(replica_id: Option<ReplicaId>) -> Self {
+        PNCounter {
+            increments: GCounter::default(replica_id),
+            decrements: GCounter::default(replica_id),
+        }
+    }
+
+    fn query(&self) -> i64 {
+        let value = self.increments.value() as i128 - self.decrements.value() as i128;
+        value.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+
+    fn prepare(&self, command: &PNCounterCommand) -> Result<PNCounterOperation, CausalError> {
+        match command {
+            PNCounterCommand::Increment(value) => Ok(PNCounterOperation::Incremented(self.increments.check(*value)?)),
+            PNCounterCommand::Decrement(value) => Ok(PNCounterOperation::Decremented(self.decrements.check(*value)?)),
+        }
+    }
+
+    fn effect(&mut self, event: &Event<PNCounterOperation>) -> Result<(), CausalError> {
+        match &event.data {
+            PNCounterOperation::Incremented(value) => self.increments.add(event.origin, *value),
+            PNCounterOperation::Decremented(value) => self.decrements.add(event.origin, *value),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::marker::PhantomData;
+    use std::time::{Duration, Instant};
+
+    use actix::{Actor, Addr, System};
+
+    use crate::{CRDT, EventStore, ReplicaState};
+    use crate::causal_actix::{Replica, ValuedCausalMessage, VoidCausalMessage};
+    use crate::causal_core::CausalError;
+    use crate::causal_counter::{GCounter, GCounterCommand, GCounterOperation, PNCounter, PNCounterCommand, PNCounterOperation};
+    use crate::causal_utils::InMemory;
+
+    type PNCounterReplica = Replica<PNCounter, i64, PNCounterCommand, PNCounterOperation, InMemory<PNCounter, i64, PNCounterCommand, PNCounterOperation>>;
+
+    #[test]
+    fn test_g_counter_merges_increments() {
+        let mut store_0 = InMemory::create();
+        let mut store_1 = InMemory::create();
+        let mut state_0 = ReplicaState::create(0, GCounter::default(Some(0)));
+        let mut state_1 = ReplicaState::create(1, GCounter::default(Some(1)));
+
+        state_0.process_command(&GCounterCommand::Increment(2), &mut store_0).unwrap();
+        state_0.process_command(&GCounterCommand::Increment(3), &mut store_0).unwrap();
+        state_1.process_command(&GCounterCommand::Increment(4), &mut store_1).unwrap();
+
+        state_0.process_replicated(1, 1, store_1.load_events(1).unwrap(), &mut store_0).unwrap();
+        state_1.process_replicated(0, 2, store_0.load_events(1).unwrap()[..2].to_vec(), &mut store_1).unwrap();
+
+        assert_eq!(state_0.process_query(), 9);
+        assert_eq!(state_1.process_query(), 9);
+        assert_eq!(state_0.crdt.increments.get(&0), Some(&5));
+    }
+
+    #[test]
+    fn test_g_counter_replay() {
+        let mut store = InMemory::<GCounter, u64, GCounterCommand, GCounterOperation>::create();
+        let mut state = ReplicaState::create(0, GCounter::default(Some(0)));
+        state.process_command(&GCounterCommand::Increment(1), &mut store).unwrap();
+        state.process_command(&GCounterCommand::Increment(1), &mut store).unwrap();
+
+        let mut recovered = ReplicaState::create(0, GCounter::default(Some(0)));
+        for event in store.load_events(1).unwrap() {
+            recovered = recovered.process_event(&event).unwrap();
+        }
+
+        assert_eq!(recovered.process_query(), 2);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut store_0 = InMemory::create();
+        let mut store_1 = InMemory::create();
+        let mut state_0 = ReplicaState::create(0, PNCounter::default(Some(0)));
+        let mut state_1 = ReplicaState::create(1, PNCounter::default(Some(1)));
+
+        state_0.process_command(&PNCounterCommand::Increment(u64::MAX), &mut store_0).unwrap();
+        let result = state_0.process_command(&PNCounterCommand::Increment(1), &mut store_0);
+        assert_eq!(result.err(), Some(CausalError::Overflow));
+
+        // Concurrent increments can't be refused, the value saturates instead.
+        state_1.process_command(&PNCounterCommand::Increment(1), &mut store_1).unwrap();
+        state_1.process_command(&PNCounterCommand::Decrement(1), &mut store_1).unwrap();
+        state_0.process_replicated(1, 2, store_1.events.clone(), &mut store_0).unwrap();
+
+        assert_eq!(state_0.process_query(), i64::MAX);
+    }
+
+    #[test]
+    fn test_pn_counters_converge_through_replicas() {
+        let replicas_number = 3;
+
+        let values = System::new().block_on(async move {
+            let replicas: HashMap<isize, Addr<PNCounterReplica>> = (0..replicas_number)
+                .map(|id| (id, Replica::create(id, PNCounter::default(Some(id)), InMemory::create()).start()))
+                .collect();
+
+            for (from, replica) in &replicas {
+                for (to, other) in &replicas {
+                    if from != to {
+                        replica.do_send(VoidCausalMessage::Connect(*to, other.clone().recipient()));
+                    }
+                }
+            }
+
+            // Every replica concurrently updates the counter.
+            for (id, replica) in &replicas {
+                replica.do_send(VoidCausalMessage::Command(PNCounterCommand::Increment(10 * (*id as u64 + 1))));
+                replica.do_send(VoidCausalMessage::Command(PNCounterCommand::Decrement(*id as u64)));
+            }
+
+            // We sync until every replica has the final value, or until the deadline.
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                for replica in replicas.values() {
+                    replica.do_send(VoidCausalMessage::Sync);
+                }
+                actix_rt::time::sleep(Duration::from_millis(10)).await;
+
+                let mut values = vec![];
+                for replica in replicas.values() {
+                    values.push(replica.send(ValuedCausalMessage::Query(PhantomData)).await.unwrap().0);
+                }
+                if values.iter().all(|value| *value == 57) || Instant::now() > deadline {
+                    break values;
+                }
+            }
+        });
+
+        // (10 + 20 + 30) - (0 + 1 + 2)
+        assert_eq!(values, vec![57; replicas_number as usize]);
+    }
+}
@@ -0,0 +1,805 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use crate::{CRDT, Event, EventStore, ReplicaId, ReplicaState, VectorClock};
+use crate::causal_core::{CausalError, DottedVersionVector, SeqNr, VTime};
+
+/** TYPES **/
+// A segment is identified by the local seq nr of the first event it contains.
+type SegmentId = SeqNr;
+type Location = (SegmentId, u64);
+
+const SEGMENT_EXTENSION: &str = "log";
+const SNAPSHOT_EXTENSION: &str = "snapshot";
+// Every record is prefixed by its length and its checksum.
+const RECORD_HEADER_SIZE: u64 = 8;
+
+
+/** TRAITS **/
+pub trait Codec<T> {
+    fn encode(&self, value: &T) -> Vec<u8>;
+    fn decode(&self, bytes: &[u8]) -> io::Result<T>;
+}
+
+
+/** DATA STRUCTURES **/
+pub enum FsyncPolicy {
+    // Every batch of saved events is flushed to disk before returning.
+    Always,
+    // The log is flushed to disk once at least the given number of events has been written since the last flush.
+    EveryEvents(usize),
+    // Flushing is left to the operating system.
+    Never,
+}
+
+pub struct FileStoreConfig {
+    // Size in bytes after which a new segment is started.
+    pub segment_size: u64,
+    pub fsync_policy: FsyncPolicy,
+}
+
+struct Segment {
+    id: SegmentId,
+    file: File,
+    size: u64,
+}
+
+pub struct FileStore<C, STATE, CMD, EVENT, CODEC>
+    where C: CRDT<STATE, CMD, EVENT> + Clone,
+          EVENT: Clone,
+          CODEC: Codec<EVENT> + Codec<C>
+{
+    directory: PathBuf,
+    codec: CODEC,
+    config: FileStoreConfig,
+    // The location of each event in the log, indexed by its local seq nr.
+    index: BTreeMap<SeqNr, Location>,
+    segments: Vec<SegmentId>,
+    active_segment: Option<Segment>,
+    unsynced_events: usize,
+    // Set when a failed write couldn't be rolled back, since the next records would follow a partial one.
+    failed: bool,
+    _1: PhantomData<C>,
+    _2: PhantomData<STATE>,
+    _3: PhantomData<CMD>,
+    _4: PhantomData<EVENT>,
+}
+
+struct BinaryWriter {
+    bytes: Vec<u8>,
+}
+
+struct BinaryReader<'a> {
+    bytes: &'a [u8],
+}
+
+
+/** IMPLEMENTATIONS **/
+impl Default for FileStoreConfig {
+    fn default() -> Self {
+        FileStoreConfig {
+            segment_size: 64 * 1024 * 1024,
+            fsync_policy: FsyncPolicy::Always,
+        }
+    }
+}
+
+impl<C, STATE, CMD, EVENT, CODEC> FileStore<C, STATE, CMD, EVENT, CODEC>
+    where C: CRDT<STATE, CMD, EVENT> + Clone,
+          EVENT: Clone,
+          CODEC: Codec<EVENT> + Codec<C>
+{
+    pub fn open(directory: impl AsRef<Path>, codec: CODEC) -> io::Result<FileStore<C, STATE, CMD, EVENT, CODEC>> {
+        FileStore::open_with_config(directory, codec, FileStoreConfig::default())
+    }
+
+    pub fn open_with_config(
+        directory: impl AsRef<Path>,
+        codec: CODEC,
+        config: FileStoreConfig,
+    ) -> io::Result<FileStore<C, STATE, CMD, EVENT, CODEC>> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+
+        let mut store = FileStore {
+            directory,
+            codec,
+            config,
+            index: BTreeMap::new(),
+            segments: vec![],
+            active_segment: None,
+            unsynced_events: 0,
+            failed: false,
+            _1: PhantomData,
+            _2: PhantomData,
+            _3: PhantomData,
+            _4: PhantomData,
+        };
+        store.recover()?;
+
+        Ok(store)
+    }
+
+    // Rebuilds the index by scanning all the segments. A partially written record at the end of the last segment,
+    // e.g. caused by a crash in the middle of a write, is discarded. The older segments were complete when the next one
+    // was started, thus a damaged record in one of them is reported as an error.
+    fn recover(&mut self) -> io::Result<()> {
+        self.segments = self.list_files(SEGMENT_EXTENSION)?;
+
+        for (position, segment_id) in self.segments.clone().into_iter().enumerate() {
+            let path = self.segment_path(segment_id);
+            let mut reader = BufReader::new(File::open(&path)?);
+            let mut offset = 0;
+
+            while let Some(payload) = read_record(&mut reader)? {
+                let event = self.decode_event(&payload)?;
+                self.index.insert(event.local_seq_nr, (segment_id, offset));
+                offset += RECORD_HEADER_SIZE + payload.len() as u64;
+            }
+
+            if position < self.segments.len() - 1 {
+                if offset != fs::metadata(&path)?.len() {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("The segment {} is damaged at offset {}.", segment_id, offset),
+                    ));
+                }
+            } else {
+                let file = OpenOptions::new().append(true).open(&path)?;
+                file.set_len(offset)?;
+                self.active_segment = Some(Segment {
+                    id: segment_id,
+                    file,
+                    size: offset,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn list_files(&self, extension: &str) -> io::Result<Vec<SeqNr>> {
+        let mut seq_nrs = fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|inner_extension| inner_extension == extension))
+            .filter_map(|path| path.file_stem()?.to_str()?.parse::<SeqNr>().ok())
+            .collect::<Vec<SeqNr>>();
+        seq_nrs.sort_unstable();
+
+        Ok(seq_nrs)
+    }
+
+    fn segment_path(&self, segment_id: SegmentId) -> PathBuf {
+        self.directory.join(format!("{:020}.{}", segment_id, SEGMENT_EXTENSION))
+    }
+
+    fn snapshot_path(&self, seq_nr: SeqNr) -> PathBuf {
+        self.directory.join(format!("{:020}.{}", seq_nr, SNAPSHOT_EXTENSION))
+    }
+
+    fn append(&mut self, event: &Event<EVENT>) -> io::Result<()> {
+        if self.failed {
+            return Err(io::Error::other("A previous write couldn't be rolled back, the store must be reopened."));
+        }
+        let payload = self.encode_event(event);
+
+        // We start a new segment when there is none or when the current one is full.
+        let roll = match &self.active_segment {
+            Some(segment) => segment.size >= self.config.segment_size,
+            None => true,
+        };
+        if roll {
+            if let Some(segment) = &self.active_segment {
+                segment.file.sync_data()?;
+            }
+
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.segment_path(event.local_seq_nr))?;
+            self.segments.push(event.local_seq_nr);
+            self.active_segment = Some(Segment {
+                id: event.local_seq_nr,
+                file,
+                size: 0,
+            });
+        }
+
+        let segment = self.active_segment.as_mut().unwrap();
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE as usize + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&checksum(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+        // A partially written record is removed, otherwise the next records would be indexed at the wrong offset.
+        if let Err(error) = segment.file.write_all(&record) {
+            if segment.file.set_len(segment.size).is_err() {
+                self.failed = true;
+            }
+            return Err(error);
+        }
+
+        self.index.insert(event.local_seq_nr, (segment.id, segment.size));
+        segment.size += record.len() as u64;
+
+        Ok(())
+    }
+
+    fn sync(&mut self, written_events: usize) -> io::Result<()> {
+        self.unsynced_events += written_events;
+
+        let should_sync = match self.config.fsync_policy {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::EveryEvents(events) => self.unsynced_events >= events,
+            FsyncPolicy::Never => false,
+        };
+
+        if should_sync {
+            if let Some(segment) = &self.active_segment {
+                segment.file.sync_data()?;
+            }
+            self.unsynced_events = 0;
+        }
+
+        Ok(())
+    }
+
+    // Removes the segments that only contain events lower than end_seq_nr. Segments are never rewritten, thus the
+    // events lower than end_seq_nr that share a segment with greater ones are kept. The active segment is never removed.
+    fn remove_segments(&mut self, end_seq_nr: SeqNr) -> io::Result<()> {
+        let removable = self.segments
+            .windows(2)
+            .take_while(|window| window[1] <= end_seq_nr)
+            .count();
+
+        for segment_id in self.segments.drain(..removable).collect::<Vec<SegmentId>>() {
+            fs::remove_file(self.segment_path(segment_id))?;
+        }
+
+        // The index only keeps the events still present in the log.
+        match self.segments.first() {
+            Some(first_segment_id) => self.index = self.index.split_off(first_segment_id),
+            None => self.index.clear(),
+        }
+
+        Ok(())
+    }
+
+    fn read_events(&self, start_seq_nr: SeqNr, limit: usize) -> io::Result<Vec<Event<EVENT>>> {
+        let mut events = vec![];
+
+        // We find the location of the first event to read, from there on the log is read sequentially.
+        let (first_segment_id, first_offset) = match self.index.range(start_seq_nr..).next() {
+            Some((_, location)) => *location,
+            None => return Ok(events),
+        };
+
+        for segment_id in self.segments.iter().filter(|segment_id| **segment_id >= first_segment_id) {
+            let mut file = File::open(self.segment_path(*segment_id))?;
+            if *segment_id == first_segment_id {
+                file.seek(SeekFrom::Start(first_offset))?;
+            }
+
+            let mut reader = BufReader::new(file);
+            while let Some(payload) = read_record(&mut reader)? {
+                if events.len() >= limit {
+                    return Ok(events);
+                }
+                events.push(self.decode_event(&payload)?);
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn encode_event(&self, event: &Event<EVENT>) -> Vec<u8> {
+        let mut writer = BinaryWriter::new();
+        write_event(&mut writer, &self.codec, event);
+        writer.bytes
+    }
+
+    fn decode_event(&self, bytes: &[u8]) -> io::Result<Event<EVENT>> {
+        read_event(&mut BinaryReader::new(bytes), &self.codec)
+    }
+
+    fn encode_snapshot(&self, state: &ReplicaState<C, STATE, CMD, EVENT>) -> Vec<u8> {
+        let mut writer = BinaryWriter::new();
+        writer.write_i64(state.id as i64);
+        writer.write_u64(state.seq_nr);
+        write_version(&mut writer, &state.version);
+        writer.write_u64(state.observed.len() as u64);
+        for (replica_id, seq_nr) in &state.observed {
+            writer.write_i64(*replica_id as i64);
+            writer.write_u64(*seq_nr);
+        }
+        writer.write_u64(state.delivered.vector().len() as u64);
+        for (replica_id, seq_nr) in state.delivered.vector() {
+            writer.write_i64(*replica_id as i64);
+            writer.write_u64(*seq_nr);
+        }
+        writer.write_u64(state.pending.len() as u64);
+        for event in &state.pending {
+            write_event(&mut writer, &self.codec, event);
+        }
+        writer.write_u64(state.acknowledged.len() as u64);
+        for (replica_id, version) in &state.acknowledged {
+            writer.write_i64(*replica_id as i64);
+            write_version(&mut writer, version);
+        }
+        writer.write_bytes(&Codec::<C>::encode(&self.codec, &state.crdt));
+        writer.bytes
+    }
+
+    fn decode_snapshot(&self, bytes: &[u8]) -> io::Result<ReplicaState<C, STATE, CMD, EVENT>> {
+        let mut reader = BinaryReader::new(bytes);
+        let id = reader.read_i64()? as ReplicaId;
+        let seq_nr = reader.read_u64()?;
+        let version = read_version(&mut reader)?;
+        let mut observed = HashMap::new();
+        for _ in 0..reader.read_u64()? {
+            observed.insert(reader.read_i64()? as ReplicaId, reader.read_u64()?);
+        }
+        let mut delivered = HashMap::new();
+        for _ in 0..reader.read_u64()? {
+            delivered.insert(reader.read_i64()? as ReplicaId, reader.read_u64()?);
+        }
+        let mut pending = vec![];
+        for _ in 0..reader.read_u64()? {
+            pending.push(read_event(&mut reader, &self.codec)?);
+        }
+        let mut acknowledged = HashMap::new();
+        for _ in 0..reader.read_u64()? {
+            acknowledged.insert(reader.read_i64()? as ReplicaId, read_version(&mut reader)?);
+        }
+        let crdt = Codec::<C>::decode(&self.codec, reader.read_bytes()?)?;
+
+        let mut state = ReplicaState::new(id, seq_nr, version, observed, crdt);
+        state.delivered = DottedVersionVector::from(delivered);
+        state.pending = pending;
+        state.acknowledged = acknowledged;
+
+        Ok(state)
+    }
+}
+
+impl<C, STATE, CMD, EVENT, CODEC> EventStore<C, STATE, CMD, EVENT> for FileStore<C, STATE, CMD, EVENT, CODEC>
+    where C: CRDT<STATE, CMD, EVENT> + Clone,
+          EVENT: Clone,
+          CODEC: Codec<EVENT> + Codec<C>
+{
+    fn save_snapshot(&mut self, state: &ReplicaState<C, STATE, CMD, EVENT>) -> Result<(), CausalError> {
+        let bytes = self.encode_snapshot(state);
+        let previous_snapshots = self.list_files(SNAPSHOT_EXTENSION)?;
+
+        // The snapshot is first written to a temporary file, so that a crash never leaves a partial snapshot behind.
+        let temporary_path = self.directory.join("snapshot.tmp");
+        let mut file = File::create(&temporary_path)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&temporary_path, self.snapshot_path(state.seq_nr))?;
+
+        for seq_nr in previous_snapshots.into_iter().filter(|seq_nr| *seq_nr != state.seq_nr) {
+            fs::remove_file(self.snapshot_path(seq_nr))?;
+        }
+
+        Ok(())
+    }
+
+    fn load_snapshot(&self) -> Result<Option<ReplicaState<C, STATE, CMD, EVENT>>, CausalError> {
+        let seq_nr = match self.list_files(SNAPSHOT_EXTENSION)?.last() {
+            Some(seq_nr) => *seq_nr,
+            None => return Ok(None),
+        };
+        let bytes = fs::read(self.snapshot_path(seq_nr))?;
+
+        Ok(Some(self.decode_snapshot(&bytes)?))
+    }
+
+    fn save_events(&mut self, events: Vec<Event<EVENT>>) -> Result<(), CausalError> {
+        for event in &events {
+            self.append(event)?;
+        }
+
+        Ok(self.sync(events.len())?)
+    }
+
+    fn load_events(&self, start_seq_nr: SeqNr) -> Result<Vec<Event<EVENT>>, CausalError> {
+        Ok(self.read_events(start_seq_nr, usize::MAX)?)
+    }
+
+    fn load_events_batch(&self, start_seq_nr: SeqNr, limit: usize) -> Result<Vec<Event<EVENT>>, CausalError> {
+        Ok(self.read_events(start_seq_nr, limit)?)
+    }
+
+    fn truncate_events(&mut self, end_seq_nr: SeqNr) -> Result<(), CausalError> {
+        Ok(self.remove_segments(end_seq_nr)?)
+    }
+}
+
+impl BinaryWriter {
+    fn new() -> BinaryWriter {
+        BinaryWriter {
+            bytes: vec![],
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_i64(&mut self, value: i64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_bytes(&mut self, value: &[u8]) {
+        self.write_u64(value.len() as u64);
+        self.bytes.extend_from_slice(value);
+    }
+}
+
+impl<'a> BinaryReader<'a> {
+    fn new(bytes: &'a [u8]) -> BinaryReader<'a> {
+        BinaryReader {
+            bytes,
+        }
+    }
+
+    fn read_exact(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < length {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "The record is truncated."));
+        }
+
+        let (value, remaining) = self.bytes.split_at(length);
+        self.bytes = remaining;
+        Ok(value)
+    }
+
+    fn read_u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.read_exact(8)?.try_into().unwrap()))
+    }
+
+    fn read_i64(&mut self) -> io::Result<i64> {
+        Ok(i64::from_le_bytes(self.read_exact(8)?.try_into().unwrap()))
+    }
+
+    fn read_bytes(&mut self) -> io::Result<&'a [u8]> {
+        let length = self.read_u64()? as usize;
+        self.read_exact(length)
+    }
+}
+
+
+/** UTILS **/
+fn write_version(writer: &mut BinaryWriter, version: &VTime) {
+    writer.write_u64(version.vector().len() as u64);
+    for (replica_id, clock) in version.vector() {
+        writer.write_i64(*replica_id as i64);
+        writer.write_i64(*clock as i64);
+    }
+}
+
+fn read_version(reader: &mut BinaryReader) -> io::Result<VTime> {
+    let mut vector = HashMap::new();
+    for _ in 0..reader.read_u64()? {
+        vector.insert(reader.read_i64()? as ReplicaId, reader.read_i64()? as i32);
+    }
+
+    Ok(VectorClock::from(vector))
+}
+
+fn write_event<EVENT: Clone>(writer: &mut BinaryWriter, codec: &impl Codec<EVENT>, event: &Event<EVENT>) {
+    writer.write_i64(event.origin as i64);
+    writer.write_u64(event.origin_seq_nr);
+    writer.write_u64(event.local_seq_nr);
+    write_version(writer, &event.version);
+    writer.write_bytes(&codec.encode(&event.data));
+}
+
+fn read_event<EVENT: Clone>(reader: &mut BinaryReader, codec: &impl Codec<EVENT>) -> io::Result<Event<EVENT>> {
+    Ok(Event {
+        origin: reader.read_i64()? as ReplicaId,
+        origin_seq_nr: reader.read_u64()?,
+        local_seq_nr: reader.read_u64()?,
+        version: read_version(reader)?,
+        data: codec.decode(reader.read_bytes()?)?,
+    })
+}
+
+// Reads the next record, returning None at the end of the segment or when the last record was only partially written.
+fn read_record(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+    if !read_fully(reader, &mut header)? {
+        return Ok(None);
+    }
+
+    let length = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+    let expected_checksum = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    let mut payload = vec![0u8; length];
+    if !read_fully(reader, &mut payload)? || checksum(&payload) != expected_checksum {
+        return Ok(None);
+    }
+
+    Ok(Some(payload))
+}
+
+fn read_fully(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buffer) {
+        Ok(()) => Ok(true),
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+// CRC-32 (IEEE) of the given bytes.
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::OpenOptions;
+    use std::io;
+    use std::marker::PhantomData;
+    use std::path::PathBuf;
+
+    use actix::{Actor, System};
+
+    use crate::{CRDT, Event, EventStore, ReplicaId, ReplicaState};
+    use crate::causal_actix::{Replica, ValuedCausalMessage, VoidCausalMessage};
+    use crate::causal_core::CausalError;
+    use crate::causal_file::{Codec, FileStore, FileStoreConfig, FsyncPolicy};
+
+    #[derive(Clone)]
+    struct Sum(i64);
+
+    impl CRDT<i64, i64, i64> for Sum {
+        fn default(_: Option<ReplicaId>) -> Self {
+            Sum(0)
+        }
+
+        fn query(&self) -> i64 {
+            self.0
+        }
+
+        fn prepare(&self, command: &i64) -> Result<i64, CausalError> {
+            Ok(*command)
+        }
+
+        fn effect(&mut self, event: &Event<i64>) -> Result<(), CausalError> {
+            self.0 += event.data;
+            Ok(())
+        }
+    }
+
+    struct SumCodec;
+
+    impl Codec<i64> for SumCodec {
+        fn encode(&self, value: &i64) -> Vec<u8> {
+            value.to_le_bytes().to_vec()
+        }
+
+        fn decode(&self, bytes: &[u8]) -> io::Result<i64> {
+            Ok(i64::from_le_bytes(bytes.try_into().unwrap()))
+        }
+    }
+
+    impl Codec<Sum> for SumCodec {
+        fn encode(&self, value: &Sum) -> Vec<u8> {
+            value.0.to_le_bytes().to_vec()
+        }
+
+        fn decode(&self, bytes: &[u8]) -> io::Result<Sum> {
+            Ok(Sum(i64::from_le_bytes(bytes.try_into().unwrap())))
+        }
+    }
+
+    type SumStore = FileStore<Sum, i64, i64, i64, SumCodec>;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("causal-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn open(directory: &PathBuf, segment_size: u64) -> SumStore {
+        FileStore::open_with_config(directory, SumCodec, FileStoreConfig {
+            segment_size,
+            fsync_policy: FsyncPolicy::EveryEvents(2),
+        }).unwrap()
+    }
+
+    fn write(store: &mut SumStore, values: Vec<i64>) -> ReplicaState<Sum, i64, i64, i64> {
+        let mut state = ReplicaState::create(0, Sum::default(Some(0)));
+        for value in values {
+            state.process_command(&value, store).unwrap();
+        }
+
+        state
+    }
+
+    #[test]
+    fn test_events_survive_reopening() {
+        let directory = directory("reopen");
+        let mut store = open(&directory, 64);
+        write(&mut store, (1..=10).collect());
+        drop(store);
+
+        let store = open(&directory, 64);
+        let events = store.load_events(4).unwrap();
+
+        assert!(store.segments.len() > 1);
+        assert_eq!(events.iter().map(|event| event.local_seq_nr).collect::<Vec<_>>(), (4..=10).collect::<Vec<_>>());
+        assert_eq!(events.iter().map(|event| event.data).sum::<i64>(), (4..=10).sum::<i64>());
+        assert_eq!(store.load_events_batch(4, 3).unwrap().len(), 3);
+        assert_eq!(store.load_events(11).unwrap().len(), 0);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_partial_record_is_discarded() {
+        let directory = directory("partial");
+        let mut store = open(&directory, 1024);
+        write(&mut store, vec![1, 2, 3]);
+        let segment_path = store.segment_path(store.segments[0]);
+        drop(store);
+
+        // We simulate a crash in the middle of the write of the last record.
+        let file = OpenOptions::new().write(true).open(&segment_path).unwrap();
+        let length = file.metadata().unwrap().len();
+        file.set_len(length - 3).unwrap();
+        drop(file);
+
+        let mut store = open(&directory, 1024);
+        assert_eq!(store.load_events(1).unwrap().len(), 2);
+
+        let mut state = ReplicaState::create(0, Sum::default(Some(0)));
+        state.seq_nr = 2;
+        state.process_command(&4, &mut store).unwrap();
+        assert_eq!(store.load_events(1).unwrap().iter().map(|event| event.data).collect::<Vec<_>>(), vec![1, 2, 4]);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_damaged_record_in_older_segment_is_an_error() {
+        let directory = directory("damaged");
+        let mut store = open(&directory, 64);
+        write(&mut store, (1..=10).collect());
+        let segment_path = store.segment_path(store.segments[0]);
+        drop(store);
+
+        // We flip the last byte of the first segment, which breaks the checksum of its last record.
+        let mut bytes = fs::read(&segment_path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xFF;
+        fs::write(&segment_path, bytes).unwrap();
+
+        let result = FileStore::open_with_config(&directory, SumCodec, FileStoreConfig::default())
+            as io::Result<SumStore>;
+        assert_eq!(result.err().map(|error| error.kind()), Some(io::ErrorKind::InvalidData));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_io_errors_are_returned() {
+        let directory = directory("io-error");
+        let mut store = open(&directory, 1024);
+        let state = write(&mut store, vec![1]);
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(matches!(store.save_snapshot(&state), Err(CausalError::Storage(_))));
+        assert!(matches!(store.load_snapshot(), Err(CausalError::Storage(_))));
+    }
+
+    #[test]
+    fn test_truncation_removes_whole_segments() {
+        let directory = directory("truncate");
+        // Every segment holds two events.
+        let mut store = open(&directory, 128);
+        write(&mut store, (1..=10).collect());
+        assert_eq!(store.segments, vec![1, 3, 5, 7, 9]);
+
+        store.truncate_events(5).unwrap();
+        assert_eq!(store.segments, vec![5, 7, 9]);
+        assert_eq!(store.load_events(0).unwrap().first().unwrap().local_seq_nr, 5);
+        drop(store);
+
+        // Truncating in the middle of a segment keeps it, and the active segment is never removed.
+        let mut store = open(&directory, 128);
+        store.truncate_events(6).unwrap();
+        assert_eq!(store.segments, vec![5, 7, 9]);
+        store.truncate_events(11).unwrap();
+        assert_eq!(store.segments, vec![9]);
+        assert_eq!(store.load_events(0).unwrap().iter().map(|event| event.local_seq_nr).collect::<Vec<_>>(), vec![9, 10]);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_failed_write_without_rollback_stops_appends() {
+        let directory = directory("failed-write");
+        let mut store = open(&directory, 1024);
+        let mut state = write(&mut store, vec![1, 2]);
+
+        // A read-only handle makes both the write and its rollback fail.
+        let read_only = fs::File::open(store.segment_path(store.active_segment.as_ref().unwrap().id)).unwrap();
+        let writable = std::mem::replace(&mut store.active_segment.as_mut().unwrap().file, read_only);
+        assert!(state.process_command(&3, &mut store).is_err());
+
+        // The next records would be written after whatever the failed write left behind.
+        store.active_segment.as_mut().unwrap().file = writable;
+        assert!(state.process_command(&4, &mut store).is_err());
+        drop(store);
+
+        let store = open(&directory, 1024);
+        assert_eq!(store.load_events(1).unwrap().iter().map(|event| event.data).collect::<Vec<_>>(), vec![1, 2]);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_is_saved_and_loaded() {
+        let directory = directory("snapshot");
+        let mut store = open(&directory, 1024);
+        assert!(store.load_snapshot().unwrap().is_none());
+
+        let mut state = write(&mut store, vec![1, 2]);
+        state.process_connect(1);
+        store.save_snapshot(&state).unwrap();
+        state.process_command(&3, &mut store).unwrap();
+        store.save_snapshot(&state).unwrap();
+        drop(store);
+
+        let store = open(&directory, 1024);
+        let snapshot = store.load_snapshot().unwrap().unwrap();
+
+        assert_eq!(snapshot.seq_nr, 3);
+        assert_eq!(snapshot.process_query(), 6);
+        assert_eq!(snapshot.version, state.version);
+        assert_eq!(snapshot.delivered, state.delivered);
+        assert!(snapshot.acknowledged.contains_key(&1));
+        assert_eq!(store.list_files("snapshot").unwrap().len(), 1);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_replica_recovers_after_restart() {
+        let directory = directory("restart");
+
+        let query = |commands: Vec<i64>| {
+            let directory = directory.clone();
+            System::new().block_on(async move {
+                let store = open(&directory, 64);
+                let replica = Replica::create(0, Sum::default(Some(0)), store).start();
+                for command in commands {
+                    replica.send(VoidCausalMessage::Command(command)).await.unwrap().unwrap();
+                }
+
+                replica.send(ValuedCausalMessage::Query(PhantomData)).await.unwrap().0
+            })
+        };
+
+        assert_eq!(query(vec![1, 2, 3]), 6);
+        assert_eq!(query(vec![4]), 10);
+        assert_eq!(query(vec![]), 10);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use crate::{CRDT, Event, InputReceiver, ReplicaId};
+use crate::causal_core::CausalError;
+use crate::causal_fugue::FugueCommand::{Insert, Remove};
+use crate::causal_fugue::FugueOperation::{Inserted, Removed};
+use crate::causal_rga::RGAPtr;
+
+/** DATA STRUCTURES **/
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FugueCommand<T>
+    where T: Clone
+{
+    Insert(usize, T),
+    Remove(usize),
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FugueOperation<T>
+    where T: Clone
+{
+    // The new element, its parent and the side of the parent it is inserted on.
+    Inserted(RGAPtr, RGAPtr, Side, T),
+    Removed(RGAPtr),
+}
+
+// The position of an element in the tree, with its children sorted by their pointers.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct FugueNode {
+    left: Vec<RGAPtr>,
+    right: Vec<RGAPtr>,
+}
+
+// A sequence as described in "The Art of the Fugue" by Weidner and Kleppmann. The elements form a tree, where every
+// element is either a left or a right child of another one, and the order of the sequence is the in-order traversal
+// of the tree. A new element is a right child of the element before it, unless that one has right children already,
+// in which case it is a left child of the element after it. The elements typed together end up in the same subtree,
+// thus the text typed concurrently at the same position is never interleaved, both forwards and backwards.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fugue<T>
+    where T: Clone
+{
+    sequencer: RGAPtr,
+    // The elements in the order of the traversal, the removed ones are kept as tombstones.
+    elements: Vec<(RGAPtr, Option<T>)>,
+    nodes: HashMap<RGAPtr, FugueNode>,
+}
+
+
+/** IMPLEMENTATIONS **/
+impl<T> Fugue<T>
+    where T: Clone
+{
+    // Returns the position of the element visible at the given index, skipping the tombstones.
+    fn index_with_tombstones(&self, index: usize) -> Result<usize, CausalError> {
+        self.elements
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, value))| value.is_some())
+            .nth(index)
+            .map(|(offset, _)| offset)
+            .ok_or(CausalError::IndexOutOfBounds(index))
+    }
+
+    fn index_of_v_ptr(&self, v_ptr: &RGAPtr) -> Result<usize, CausalError> {
+        self.elements
+            .iter()
+            .position(|(inner_v_ptr, _)| v_ptr == inner_v_ptr)
+            .ok_or(CausalError::UnknownElement)
+    }
+
+    // Returns the first element of the subtree of the given one.
+    fn leftmost(&self, v_ptr: &RGAPtr) -> RGAPtr {
+        let mut current = v_ptr;
+        while let Some(child) = self.nodes[current].left.first() {
+            current = child;
+        }
+        current.clone()
+    }
+
+    // Returns the last element of the subtree of the given one.
+    fn rightmost(&self, v_ptr: &RGAPtr) -> RGAPtr {
+        let mut current = v_ptr;
+        while let Some(child) = self.nodes[current].right.last() {
+            current = child;
+        }
+        current.clone()
+    }
+}
+
+impl<T> Clone for Fugue<T>
+    where T: Clone
+{
+    fn clone(&self) -> Self {
+        Fugue {
+            sequencer: self.sequencer.clone(),
+            elements: self.elements.to_vec(),
+            nodes: self.nodes.clone(),
+        }
+    }
+}
+
+impl<T> CRDT<Vec<T>, FugueCommand<T>, FugueOperation<T>> for Fugue<T>
+    where T: Clone
+{
+    fn default(replica_id: Option<ReplicaId>) -> Self {
+        let replica_id = replica_id.expect("You must set a valid replica id for Fugue to work.");
+
+        Fugue {
+            sequencer: RGAPtr::new(replica_id),
+            // The root is the same across all the replicas, and it is never visible.
+            elements: vec![(RGAPtr::new(-1), None)],
+            nodes: HashMap::from([(RGAPtr::new(-1), FugueNode { left: vec![], right: vec![] })]),
+        }
+    }
+
+    fn query(&self) -> Vec<T> {
+        self.elements
+            .iter()
+            .filter_map(|(_, value)| value.clone())
+            .collect()
+    }
+
+    fn prepare(&self, command: &FugueCommand<T>) -> Result<FugueOperation<T>, CausalError> {
+        match command {
+            Insert(index, value) => {
+                // The element before the new one is the one visible right before the index, or the root.
+                let prev_index = match index {
+                    0 => 0,
+                    _ => self.index_with_tombstones(*index - 1).map_err(|_| CausalError::IndexOutOfBounds(*index))?,
+                };
+                let prev_v_ptr = &self.elements[prev_index].0;
+                let at_v_ptr = self.sequencer.next_seq_nr();
+
+                // If the element before has right children, the element after is the first of its right subtree.
+                let (parent, side) = if self.nodes[prev_v_ptr].right.is_empty() {
+                    (prev_v_ptr.clone(), Side::Right)
+                } else {
+                    (self.elements[prev_index + 1].0.clone(), Side::Left)
+                };
+
+                Ok(Inserted(at_v_ptr, parent, side, value.clone()))
+            }
+            Remove(index) => {
+                let index = self.index_with_tombstones(*index)?;
+                Ok(Removed(self.elements[index].0.clone()))
+            }
+        }
+    }
+
+    fn effect(&mut self, event: &Event<FugueOperation<T>>) -> Result<(), CausalError> {
+        match &event.data {
+            Inserted(at_v_ptr, parent, side, value) => {
+                let siblings = match side {
+                    Side::Left => &self.nodes.get(parent).ok_or(CausalError::UnknownElement)?.left,
+                    Side::Right => &self.nodes.get(parent).ok_or(CausalError::UnknownElement)?.right,
+                };
+                let sibling_index = siblings.partition_point(|sibling| sibling < at_v_ptr);
+
+                // The new element comes right after the subtree of the sibling before it. Without such a sibling, a
+                // left child starts the subtree of its parent, while a right child comes right after its parent.
+                let insert_index = match (sibling_index.checked_sub(1).map(|index| &siblings[index]), side) {
+                    (Some(prev_sibling), _) => self.index_of_v_ptr(&self.rightmost(prev_sibling))? + 1,
+                    (None, Side::Left) => self.index_of_v_ptr(&self.leftmost(parent))?,
+                    (None, Side::Right) => self.index_of_v_ptr(parent)? + 1,
+                };
+
+                self.elements.insert(insert_index, (at_v_ptr.clone(), Some(value.clone())));
+                self.nodes.insert(at_v_ptr.clone(), FugueNode { left: vec![], right: vec![] });
+                let node = self.nodes.get_mut(parent).expect("The parent has been found above.");
+                match side {
+                    Side::Left => node.left.insert(sibling_index, at_v_ptr.clone()),
+                    Side::Right => node.right.insert(sibling_index, at_v_ptr.clone()),
+                }
+                self.sequencer.update_highest_observed_seq_nr(at_v_ptr);
+            }
+            Removed(at_v_ptr) => {
+                let index = self.index_of_v_ptr(at_v_ptr)?;
+                self.elements[index].1 = None;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct FugueReceiver {
+    pub commands: Vec<FugueCommand<char>>,
+}
+
+impl FugueReceiver {
+    pub fn new() -> FugueReceiver {
+        FugueReceiver {
+            commands: vec![],
+        }
+    }
+}
+
+impl Default for FugueReceiver {
+    fn default() -> Self {
+        FugueReceiver::new()
+    }
+}
+
+impl InputReceiver for FugueReceiver {
+    fn insert_at(&mut self, position: usize, character: char) {
+        self.commands.push(Insert(position, character));
+    }
+
+    fn remove_at(&mut self, position: usize) {
+        self.commands.push(Remove(position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CRDT, ReplicaState};
+    use crate::causal_core::CausalError;
+    use crate::causal_fugue::{Fugue, FugueCommand, FugueOperation};
+    use crate::causal_utils::InMemory;
+
+    type FugueState = ReplicaState<Fugue<char>, Vec<char>, FugueCommand<char>, FugueOperation<char>>;
+    type FugueStore = InMemory<Fugue<char>, Vec<char>, FugueCommand<char>, FugueOperation<char>>;
+
+    fn replica(id: isize) -> (FugueState, FugueStore) {
+        (ReplicaState::create(id, Fugue::default(Some(id))), InMemory::create())
+    }
+
+    fn text(state: &FugueState) -> String {
+        state.process_query().into_iter().collect()
+    }
+
+    // Both replicas start from the same text, then type concurrently at the same index and exchange their events.
+    fn type_concurrently(text_0: &str, text_1: &str, forwards: bool) -> (String, String) {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+
+        state_0.process_command(&FugueCommand::Insert(0, '['), &mut store_0).unwrap();
+        state_0.process_command(&FugueCommand::Insert(1, ']'), &mut store_0).unwrap();
+        state_1.process_replicated(0, 2, store_0.events.clone(), &mut store_1).unwrap();
+
+        for (state, store, text) in [(&mut state_0, &mut store_0, text_0), (&mut state_1, &mut store_1, text_1)] {
+            if forwards {
+                for (offset, character) in text.chars().enumerate() {
+                    state.process_command(&FugueCommand::Insert(1 + offset, character), store).unwrap();
+                }
+            } else {
+                for character in text.chars().rev() {
+                    state.process_command(&FugueCommand::Insert(1, character), store).unwrap();
+                }
+            }
+        }
+
+        let events_0 = store_0.events[2..].to_vec();
+        let events_1 = store_1.events[2..].to_vec();
+        state_0.process_replicated(1, state_1.seq_nr, events_1, &mut store_0).unwrap();
+        state_1.process_replicated(0, state_0.seq_nr, events_0, &mut store_1).unwrap();
+
+        (text(&state_0), text(&state_1))
+    }
+
+    #[test]
+    fn test_no_forward_interleaving() {
+        let (text_0, text_1) = type_concurrently("abc", "XYZ", true);
+
+        assert_eq!(text_0, text_1);
+        assert!(text_0 == "[abcXYZ]" || text_0 == "[XYZabc]", "Interleaved text {}", text_0);
+    }
+
+    #[test]
+    fn test_no_backward_interleaving() {
+        let (text_0, text_1) = type_concurrently("abc", "XYZ", false);
+
+        assert_eq!(text_0, text_1);
+        assert!(text_0 == "[abcXYZ]" || text_0 == "[XYZabc]", "Interleaved text {}", text_0);
+    }
+
+    #[test]
+    fn test_insert_between_tombstones() {
+        let (mut state, mut store) = replica(0);
+        for (index, character) in "abcd".chars().enumerate() {
+            state.process_command(&FugueCommand::Insert(index, character), &mut store).unwrap();
+        }
+        state.process_command(&FugueCommand::Remove(1), &mut store).unwrap();
+        state.process_command(&FugueCommand::Remove(1), &mut store).unwrap();
+        state.process_command(&FugueCommand::Insert(1, 'x'), &mut store).unwrap();
+        state.process_command(&FugueCommand::Insert(0, 'y'), &mut store).unwrap();
+
+        assert_eq!(text(&state), "yaxd");
+        assert_eq!(state.process_command(&FugueCommand::Remove(4), &mut store).err(), Some(CausalError::IndexOutOfBounds(4)));
+    }
+}
@@ -0,0 +1,565 @@
+use std::collections::HashMap;
+
+use crate::{CRDT, Event, InputReceiver, ReplicaId};
+use crate::causal_core::{CausalError, VTime};
+use crate::causal_rga::{InsertionStability, RGACommand, RGAOperation, RGAPtr};
+use crate::causal_rga::RGACommand::{Insert, Remove};
+use crate::causal_rga::RGAOperation::{Inserted, Removed};
+use crate::causal_time::ClockComparison;
+
+/** TYPES **/
+// The index of a node in the arena of the tree.
+type NodeIndex = usize;
+
+
+/** DATA STRUCTURES **/
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Node<T>
+    where T: Clone
+{
+    v_ptr: RGAPtr,
+    value: Option<T>,
+    priority: u64,
+    parent: Option<NodeIndex>,
+    left: Option<NodeIndex>,
+    right: Option<NodeIndex>,
+    // The number of elements, and of visible ones, in the subtree of the node.
+    size: usize,
+    visible: usize,
+}
+
+// A treap ordered by the position of the elements, where every node knows the size of its subtree. It finds the
+// element at a position, visible or not, and the position of an element in logarithmic time.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct ElementTree<T>
+    where T: Clone
+{
+    nodes: Vec<Node<T>>,
+    root: Option<NodeIndex>,
+    // The nodes which have been removed, whose place in the arena can be reused.
+    free: Vec<NodeIndex>,
+    seed: u64,
+}
+
+// An RGA which stores its elements in a balanced tree instead of a vector, so that positional commands and remote
+// operations take logarithmic time in the size of the document. It uses the same operations as the RGA, therefore
+// both can be used by the replicas of the same document.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IndexedRGA<T>
+    where T: Clone
+{
+    sequencer: RGAPtr,
+    elements: ElementTree<T>,
+    // The node of every element in the tree.
+    nodes: HashMap<RGAPtr, NodeIndex>,
+    // The insertions that are not yet known to be delivered by every replica.
+    unstable: InsertionStability,
+    // Removed elements together with the version of their removal.
+    tombstones: Vec<(RGAPtr, VTime)>,
+}
+
+
+/** IMPLEMENTATIONS **/
+impl<T> Clone for Node<T>
+    where T: Clone
+{
+    fn clone(&self) -> Self {
+        Node {
+            v_ptr: self.v_ptr.clone(),
+            value: self.value.clone(),
+            priority: self.priority,
+            parent: self.parent,
+            left: self.left,
+            right: self.right,
+            size: self.size,
+            visible: self.visible,
+        }
+    }
+}
+
+impl<T> ElementTree<T>
+    where T: Clone
+{
+    fn new() -> ElementTree<T> {
+        ElementTree {
+            nodes: vec![],
+            root: None,
+            free: vec![],
+            seed: 0x2545F4914F6CDD1D,
+        }
+    }
+
+    fn node(&self, node: NodeIndex) -> &Node<T> {
+        &self.nodes[node]
+    }
+
+    // Returns the element at the position, counting the removed elements too.
+    fn select(&self, position: usize) -> Option<NodeIndex> {
+        let mut current = self.root;
+        let mut position = position;
+
+        while let Some(node) = current {
+            let left_size = self.size(self.nodes[node].left);
+            if position < left_size {
+                current = self.nodes[node].left;
+            } else if position == left_size {
+                return Some(node);
+            } else {
+                position -= left_size + 1;
+                current = self.nodes[node].right;
+            }
+        }
+
+        None
+    }
+
+    // Returns the element at the position, counting only the visible elements.
+    fn select_visible(&self, index: usize) -> Option<NodeIndex> {
+        let mut current = self.root;
+        let mut index = index;
+
+        while let Some(node) = current {
+            let left_visible = self.visible(self.nodes[node].left);
+            let own_visible = self.nodes[node].value.is_some() as usize;
+            if index < left_visible {
+                current = self.nodes[node].left;
+            } else if index < left_visible + own_visible {
+                return Some(node);
+            } else {
+                index -= left_visible + own_visible;
+                current = self.nodes[node].right;
+            }
+        }
+
+        None
+    }
+
+    // Returns the position of the element, counting the removed elements too.
+    fn rank(&self, node: NodeIndex) -> usize {
+        let mut rank = self.size(self.nodes[node].left);
+        let mut current = node;
+
+        while let Some(parent) = self.nodes[current].parent {
+            if self.nodes[parent].right == Some(current) {
+                rank += self.size(self.nodes[parent].left) + 1;
+            }
+            current = parent;
+        }
+
+        rank
+    }
+
+    fn insert(&mut self, position: usize, v_ptr: RGAPtr, value: Option<T>) -> NodeIndex {
+        let priority = self.next_priority();
+        let node = Node {
+            v_ptr,
+            value,
+            priority,
+            parent: None,
+            left: None,
+            right: None,
+            size: 0,
+            visible: 0,
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.update(index);
+
+        let (left, right) = self.split(self.root, position);
+        let left = self.merge(left, Some(index));
+        let root = self.merge(left, right);
+        self.set_root(root);
+
+        index
+    }
+
+    fn remove(&mut self, position: usize) {
+        let (left, right) = self.split(self.root, position);
+        let (removed, right) = self.split(right, 1);
+        let root = self.merge(left, right);
+        self.set_root(root);
+
+        self.free.extend(removed);
+    }
+
+    fn set_value(&mut self, node: NodeIndex, value: Option<T>) {
+        self.nodes[node].value = value;
+
+        // The count of visible elements changes in every ancestor of the node.
+        let mut current = Some(node);
+        while let Some(node) = current {
+            self.update(node);
+            current = self.nodes[node].parent;
+        }
+    }
+
+    fn values(&self) -> Vec<T> {
+        let mut values = Vec::with_capacity(self.visible(self.root));
+        let mut stack = vec![];
+        let mut current = self.root;
+
+        while current.is_some() || !stack.is_empty() {
+            while let Some(node) = current {
+                stack.push(node);
+                current = self.nodes[node].left;
+            }
+            if let Some(node) = stack.pop() {
+                if let Some(value) = &self.nodes[node].value {
+                    values.push(value.clone());
+                }
+                current = self.nodes[node].right;
+            }
+        }
+
+        values
+    }
+
+    fn size(&self, node: Option<NodeIndex>) -> usize {
+        node.map_or(0, |node| self.nodes[node].size)
+    }
+
+    fn visible(&self, node: Option<NodeIndex>) -> usize {
+        node.map_or(0, |node| self.nodes[node].visible)
+    }
+
+    fn update(&mut self, node: NodeIndex) {
+        let left = self.nodes[node].left;
+        let right = self.nodes[node].right;
+        let own_visible = self.nodes[node].value.is_some() as usize;
+
+        self.nodes[node].size = self.size(left) + 1 + self.size(right);
+        self.nodes[node].visible = self.visible(left) + own_visible + self.visible(right);
+    }
+
+    fn set_parent(&mut self, node: Option<NodeIndex>, parent: Option<NodeIndex>) {
+        if let Some(node) = node {
+            self.nodes[node].parent = parent;
+        }
+    }
+
+    fn set_root(&mut self, root: Option<NodeIndex>) {
+        self.root = root;
+        self.set_parent(root, None);
+    }
+
+    // Splits the subtree into the first elements up to the given count and the remaining ones.
+    fn split(&mut self, node: Option<NodeIndex>, count: usize) -> (Option<NodeIndex>, Option<NodeIndex>) {
+        let Some(node) = node else {
+            return (None, None);
+        };
+
+        let left_size = self.size(self.nodes[node].left);
+        if count <= left_size {
+            let (left, right) = self.split(self.nodes[node].left, count);
+            self.nodes[node].left = right;
+            self.set_parent(right, Some(node));
+            self.update(node);
+            (left, Some(node))
+        } else {
+            let (left, right) = self.split(self.nodes[node].right, count - left_size - 1);
+            self.nodes[node].right = left;
+            self.set_parent(left, Some(node));
+            self.update(node);
+            (Some(node), right)
+        }
+    }
+
+    // Merges two subtrees, where all the elements of the first one come before the ones of the second one.
+    fn merge(&mut self, left: Option<NodeIndex>, right: Option<NodeIndex>) -> Option<NodeIndex> {
+        match (left, right) {
+            (None, right) => right,
+            (left, None) => left,
+            (Some(left), Some(right)) => {
+                if self.nodes[left].priority > self.nodes[right].priority {
+                    let merged = self.merge(self.nodes[left].right, Some(right));
+                    self.nodes[left].right = merged;
+                    self.set_parent(merged, Some(left));
+                    self.update(left);
+                    Some(left)
+                } else {
+                    let merged = self.merge(Some(left), self.nodes[right].left);
+                    self.nodes[right].left = merged;
+                    self.set_parent(merged, Some(right));
+                    self.update(right);
+                    Some(right)
+                }
+            }
+        }
+    }
+
+    // The priorities only need to look random to keep the tree balanced, thus we use a simple xorshift.
+    fn next_priority(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+}
+
+impl<T> Clone for ElementTree<T>
+    where T: Clone
+{
+    fn clone(&self) -> Self {
+        ElementTree {
+            nodes: self.nodes.to_vec(),
+            root: self.root,
+            free: self.free.to_vec(),
+            seed: self.seed,
+        }
+    }
+}
+
+impl<T> IndexedRGA<T>
+    where T: Clone
+{
+    fn node_of_v_ptr(&self, v_ptr: &RGAPtr) -> Result<NodeIndex, CausalError> {
+        self.nodes
+            .get(v_ptr)
+            .copied()
+            .ok_or(CausalError::UnknownElement)
+    }
+
+    fn shift(&self, offset: usize, v_ptr: &RGAPtr) -> usize {
+        // If we append at the end, we don't need any shift.
+        let mut offset = offset;
+        while let Some(node) = self.elements.select(offset) {
+            if *v_ptr < self.elements.node(node).v_ptr {
+                offset += 1;
+            } else {
+                break;
+            }
+        }
+        offset
+    }
+}
+
+impl<T> Clone for IndexedRGA<T>
+    where T: Clone
+{
+    fn clone(&self) -> Self {
+        IndexedRGA {
+            sequencer: self.sequencer.clone(),
+            elements: self.elements.clone(),
+            nodes: self.nodes.clone(),
+            unstable: self.unstable.clone(),
+            tombstones: self.tombstones.to_vec(),
+        }
+    }
+}
+
+impl<T> CRDT<Vec<T>, RGACommand<T>, RGAOperation<T>> for IndexedRGA<T>
+    where T: Clone
+{
+    fn default(replica_id: Option<ReplicaId>) -> Self {
+        let replica_id = replica_id.expect("You must set a valid replica id for the indexed RGA to work.");
+
+        // The base pointer is the same as the one of the RGA.
+        let mut elements = ElementTree::new();
+        let head = elements.insert(0, RGAPtr::new(-1), None);
+
+        IndexedRGA {
+            sequencer: RGAPtr::new(replica_id),
+            elements,
+            nodes: HashMap::from([(RGAPtr::new(-1), head)]),
+            unstable: InsertionStability::new(),
+            tombstones: vec![],
+        }
+    }
+
+    fn query(&self) -> Vec<T> {
+        self.elements.values()
+    }
+
+    fn prepare(&self, command: &RGACommand<T>) -> Result<RGAOperation<T>, CausalError> {
+        match command {
+            Insert(index, value) => {
+                // We insert after the element visible right before the index, or after the head.
+                let prev_node = match index {
+                    0 => self.elements.select(0),
+                    _ => self.elements.select_visible(*index - 1),
+                };
+                let prev_v_ptr = prev_node
+                    .map(|node| self.elements.node(node).v_ptr.clone())
+                    .ok_or(CausalError::IndexOutOfBounds(*index))?;
+                let at_v_ptr = self.sequencer.next_seq_nr();
+
+                Ok(Inserted(prev_v_ptr, at_v_ptr, value.clone()))
+            }
+            Remove(index) => {
+                self.elements
+                    .select_visible(*index)
+                    .map(|node| Removed(self.elements.node(node).v_ptr.clone()))
+                    .ok_or(CausalError::IndexOutOfBounds(*index))
+            }
+        }
+    }
+
+    fn effect(&mut self, event: &Event<RGAOperation<T>>) -> Result<(), CausalError> {
+        match &event.data {
+            Inserted(prev_v_ptr, at_v_ptr, value) => {
+                let predecessor_index = self.elements.rank(self.node_of_v_ptr(prev_v_ptr)?);
+                let insert_index = self.shift(predecessor_index + 1, at_v_ptr);
+                self.sequencer.update_highest_observed_seq_nr(at_v_ptr);
+                let node = self.elements.insert(insert_index, at_v_ptr.clone(), Some(value.clone()));
+                self.nodes.insert(at_v_ptr.clone(), node);
+                self.unstable.insert(at_v_ptr, &event.version);
+            }
+            Removed(at_v_ptr) => {
+                let node = self.node_of_v_ptr(at_v_ptr)?;
+                self.elements.set_value(node, None);
+                self.tombstones.push((at_v_ptr.clone(), event.version.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stable(&mut self, frontier: &VTime) {
+        let is_stable = |version: &VTime| {
+            let comparison = version.compare(frontier);
+            comparison == ClockComparison::Less || comparison == ClockComparison::Equal
+        };
+
+        self.unstable.advance(frontier);
+
+        // As in the RGA, a stable tombstone is purged only once the insertion of its successor is stable too.
+        let mut stable_indexes = self.tombstones
+            .iter()
+            .filter(|(_, version)| is_stable(version))
+            .filter_map(|(v_ptr, _)| self.nodes.get(v_ptr).map(|node| self.elements.rank(*node)))
+            .collect::<Vec<usize>>();
+        stable_indexes.sort_unstable_by(|a, b| b.cmp(a));
+        stable_indexes.dedup();
+
+        // We purge from the end, so that the successor of each tombstone is already the final one.
+        for index in stable_indexes {
+            let purgeable = match self.elements.select(index + 1) {
+                Some(successor) => self.unstable.is_stable(&self.elements.node(successor).v_ptr),
+                None => true,
+            };
+
+            if purgeable {
+                let node = self.elements.select(index).expect("The tombstone is in the tree.");
+                self.nodes.remove(&self.elements.node(node).v_ptr);
+                self.elements.remove(index);
+            }
+        }
+
+        let nodes = &self.nodes;
+        self.tombstones.retain(|(v_ptr, _)| nodes.contains_key(v_ptr));
+    }
+}
+
+pub struct IndexedRGAReceiver {
+    pub commands: Vec<RGACommand<char>>,
+}
+
+impl IndexedRGAReceiver {
+    pub fn new() -> IndexedRGAReceiver {
+        IndexedRGAReceiver {
+            commands: vec![],
+        }
+    }
+}
+
+impl Default for IndexedRGAReceiver {
+    fn default() -> Self {
+        IndexedRGAReceiver::new()
+    }
+}
+
+impl InputReceiver for IndexedRGAReceiver {
+    fn insert_at(&mut self, position: usize, character: char) {
+        self.commands.push(Insert(position, character));
+    }
+
+    fn remove_at(&mut self, position: usize) {
+        self.commands.push(Remove(position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CRDT, ReplicaState};
+    use crate::causal_core::CausalError;
+    use crate::causal_indexed_rga::IndexedRGA;
+    use crate::causal_rga::{RGA, RGACommand, RGAOperation};
+    use crate::causal_utils::InMemory;
+
+    type IndexedRGAState = ReplicaState<IndexedRGA<char>, Vec<char>, RGACommand<char>, RGAOperation<char>>;
+    type IndexedRGAStore = InMemory<IndexedRGA<char>, Vec<char>, RGACommand<char>, RGAOperation<char>>;
+    type RGAState = ReplicaState<RGA<char>, Vec<char>, RGACommand<char>, RGAOperation<char>>;
+    type RGAStore = InMemory<RGA<char>, Vec<char>, RGACommand<char>, RGAOperation<char>>;
+
+    fn replica(id: isize) -> (IndexedRGAState, IndexedRGAStore) {
+        (ReplicaState::create(id, IndexedRGA::default(Some(id))), InMemory::create())
+    }
+
+    #[test]
+    fn test_matches_the_rga() {
+        let (mut state, mut store) = replica(0);
+        let mut rga_state: RGAState = ReplicaState::create(0, RGA::default(Some(0)));
+        let mut rga_store: RGAStore = InMemory::create();
+
+        // A pseudo random sequence of edits, which must give the same document as the RGA.
+        let mut seed = 7usize;
+        for step in 0..1000 {
+            seed = (seed * 1103515245 + 12345) % (1 << 31);
+            let len = state.process_query().len();
+            let command = if len > 0 && step % 3 == 0 {
+                RGACommand::Remove(seed % len)
+            } else {
+                RGACommand::Insert(seed % (len + 1), char::from(b'a' + (seed % 26) as u8))
+            };
+
+            state.process_command(&command, &mut store).unwrap();
+            rga_state.process_command(&command, &mut rga_store).unwrap();
+        }
+
+        assert_eq!(state.process_query(), rga_state.process_query());
+    }
+
+    #[test]
+    fn test_concurrent_inserts_converge_with_the_rga() {
+        let (mut state_0, mut store_0) = replica(0);
+        let mut state_1: RGAState = ReplicaState::create(1, RGA::default(Some(1)));
+        let mut store_1: RGAStore = InMemory::create();
+
+        state_0.process_command(&RGACommand::Insert(0, 'a'), &mut store_0).unwrap();
+        state_1.process_replicated(0, 1, store_0.events.clone(), &mut store_1).unwrap();
+        state_0.process_command(&RGACommand::Insert(1, 'b'), &mut store_0).unwrap();
+        state_0.process_command(&RGACommand::Remove(0), &mut store_0).unwrap();
+        state_1.process_command(&RGACommand::Insert(1, 'c'), &mut store_1).unwrap();
+        state_0.process_replicated(1, 2, store_1.events[1..].to_vec(), &mut store_0).unwrap();
+        state_1.process_replicated(0, 3, store_0.events[1..3].to_vec(), &mut store_1).unwrap();
+
+        assert_eq!(state_0.process_query(), state_1.process_query());
+    }
+
+    #[test]
+    fn test_stable_tombstones_are_purged() {
+        let (mut state, mut store) = replica(0);
+        state.process_command(&RGACommand::Insert(0, 'a'), &mut store).unwrap();
+        state.process_command(&RGACommand::Insert(1, 'b'), &mut store).unwrap();
+        state.process_command(&RGACommand::Remove(0), &mut store).unwrap();
+
+        let frontier = state.version.clone();
+        state.crdt.stable(&frontier);
+
+        assert_eq!(state.crdt.nodes.len(), 2);
+        assert!(state.crdt.tombstones.is_empty());
+        assert_eq!(state.process_query(), vec!['b']);
+
+        state.process_command(&RGACommand::Insert(0, 'a'), &mut store).unwrap();
+        assert_eq!(state.process_query(), vec!['a', 'b']);
+        assert_eq!(state.process_command(&RGACommand::Remove(2), &mut store).err(), Some(CausalError::IndexOutOfBounds(2)));
+    }
+}
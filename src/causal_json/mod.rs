@@ -0,0 +1,648 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+
+use crate::{CRDT, Event, ReplicaId};
+use crate::causal_core::{CausalError, VTime};
+use crate::causal_json::JsonCommand::{Insert, Remove, Set};
+use crate::causal_json::JsonOperation::{Assigned, Element, Field, Item};
+use crate::causal_or_map::{MapCommand, MapOperation, ORMap};
+use crate::causal_register::{MVOperation, MVRegister, RegisterCommand};
+use crate::causal_rga::{RGA, RGACommand, RGAOperation, RGAPtr};
+
+/** TYPES **/
+pub type Path = Vec<PathSegment>;
+
+type JsonObject = ORMap<String, JsonNode, JsonValue, JsonCommand, JsonOperation>;
+
+// Builds a path out of keys and indexes, e.g. json_path!["todos", 3, "done"].
+#[macro_export]
+macro_rules! json_path {
+    ($($segment:expr),* $(,)?) => {
+        vec![$($crate::causal_json::PathSegment::from($segment)),*]
+    };
+}
+
+
+/** DATA STRUCTURES **/
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+// The value of a document, as returned by the queries.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(BTreeMap<String, JsonValue>),
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum JsonCommand {
+    // Assigns the value to the key of an object or to an existing element of an array.
+    Set(Path, JsonValue),
+    // Inserts the value in an array, before the element at the index of the path.
+    Insert(Path, JsonValue),
+    // Removes the key of an object or the element of an array.
+    Remove(Path),
+}
+
+// What has been assigned to a node, the content of objects and arrays is kept in their own CRDTs.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum JsonKind {
+    Leaf(JsonValue),
+    Object,
+    Array,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum JsonOperation {
+    // Assigns the node, tagged with the replica which made the assignment, then runs the operations which remove the
+    // children observed so far and build the new ones.
+    Assigned(MVOperation<(ReplicaId, JsonKind)>, Vec<JsonOperation>),
+    // Runs an operation on the node of a key, or removes the key.
+    Field(Box<MapOperation<String, JsonOperation>>),
+    // Inserts or removes an element, an insertion carries the operation which builds the node of the element.
+    Element(RGAOperation<()>, Option<Box<JsonOperation>>),
+    // Runs an operation on the node of an element.
+    Item(RGAPtr, Box<JsonOperation>),
+}
+
+// Every node is a multi-value register of what has been assigned to it, its fields are an OR-Map of nodes and its
+// elements are an RGA whose identifiers point to nodes. The fields and the elements are kept whatever the node is, so
+// that the operations concurrent to an assignment can always be applied.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct JsonNode {
+    replica_id: ReplicaId,
+    register: MVRegister<(ReplicaId, JsonKind)>,
+    fields: JsonObject,
+    elements: RGA<()>,
+    items: HashMap<RGAPtr, JsonNode>,
+}
+
+// A JSON document whose objects behave like OR-Maps, whose arrays behave like RGAs and whose leaves are
+// multi-value registers. Concurrent assignments of the same node are all kept: the query shows the one made by the
+// replica with the greatest id, while values returns all of them.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JsonCRDT {
+    root: JsonNode,
+}
+
+
+/** IMPLEMENTATIONS **/
+impl From<&str> for PathSegment {
+    fn from(key: &str) -> Self {
+        PathSegment::Key(String::from(key))
+    }
+}
+
+impl From<String> for PathSegment {
+    fn from(key: String) -> Self {
+        PathSegment::Key(key)
+    }
+}
+
+impl From<usize> for PathSegment {
+    fn from(index: usize) -> Self {
+        PathSegment::Index(index)
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self {
+        JsonValue::Bool(value)
+    }
+}
+
+impl From<f64> for JsonValue {
+    fn from(value: f64) -> Self {
+        JsonValue::Number(value)
+    }
+}
+
+impl From<i64> for JsonValue {
+    fn from(value: i64) -> Self {
+        JsonValue::Number(value as f64)
+    }
+}
+
+impl From<&str> for JsonValue {
+    fn from(value: &str) -> Self {
+        JsonValue::String(String::from(value))
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(value: String) -> Self {
+        JsonValue::String(value)
+    }
+}
+
+impl Display for JsonValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonValue::Null => write!(f, "null"),
+            JsonValue::Bool(value) => write!(f, "{}", value),
+            JsonValue::Number(value) => write!(f, "{}", value),
+            JsonValue::String(value) => write_string(f, value),
+            JsonValue::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            JsonValue::Object(fields) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl JsonNode {
+    // The concurrent assignments of the node, starting from the one made by the replica with the greatest id.
+    fn kinds(&self) -> Vec<JsonKind> {
+        let mut kinds = self.register.query();
+        kinds.sort_by_key(|(origin, _)| Reverse(*origin));
+        kinds.into_iter().map(|(_, kind)| kind).collect()
+    }
+
+    fn kind(&self) -> Option<JsonKind> {
+        self.kinds().into_iter().next()
+    }
+
+    fn visible_elements(&self) -> impl Iterator<Item = &RGAPtr> {
+        self.elements
+            .elements()
+            .iter()
+            .filter(|(_, value)| value.is_some())
+            .map(|(v_ptr, _)| v_ptr)
+    }
+
+    fn element(&self, index: usize) -> Result<(&RGAPtr, &JsonNode), CausalError> {
+        self.visible_elements()
+            .nth(index)
+            .and_then(|v_ptr| Some((v_ptr, self.items.get(v_ptr)?)))
+            .ok_or(CausalError::IndexOutOfBounds(index))
+    }
+
+    // Resolves the path to a node, every node along the path must be visible.
+    fn resolve(&self, path: &[PathSegment]) -> Result<&JsonNode, CausalError> {
+        let mut node = self;
+        for segment in path {
+            node = match (node.kind(), segment) {
+                (Some(JsonKind::Object), PathSegment::Key(key)) => node.fields.get(key).ok_or(CausalError::InvalidPath)?,
+                (Some(JsonKind::Array), PathSegment::Index(index)) => node.element(*index)?.1,
+                _ => return Err(CausalError::InvalidPath),
+            };
+        }
+
+        Ok(node)
+    }
+
+    fn prepare_assign(&self, value: &JsonValue) -> Result<JsonOperation, CausalError> {
+        let kind = match value {
+            JsonValue::Object(_) => JsonKind::Object,
+            JsonValue::Array(_) => JsonKind::Array,
+            _ => JsonKind::Leaf(value.clone()),
+        };
+        let assignment = self.register.prepare(&RegisterCommand::Assign((self.replica_id, kind)))?;
+
+        // The new value replaces the children observed so far.
+        let mut operations = vec![];
+        for key in self.fields.keys() {
+            operations.push(Field(Box::new(self.fields.prepare(&MapCommand::Remove(key.clone()))?)));
+        }
+        // We prepare the elements on a copy of the array, so that each one is inserted after the previous one.
+        let mut elements = self.elements.clone();
+        for v_ptr in self.visible_elements() {
+            let operation = RGAOperation::Removed(v_ptr.clone());
+            elements.effect(&local_event(self.replica_id, operation.clone()))?;
+            operations.push(Element(operation, None));
+        }
+
+        match value {
+            JsonValue::Object(fields) => {
+                for (key, value) in fields {
+                    let command = MapCommand::Update(key.clone(), Set(vec![], value.clone()));
+                    operations.push(Field(Box::new(self.fields.prepare(&command)?)));
+                }
+            }
+            JsonValue::Array(values) => {
+                for (index, value) in values.iter().enumerate() {
+                    let operation = elements.prepare(&RGACommand::Insert(index, ()))?;
+                    elements.effect(&local_event(self.replica_id, operation.clone()))?;
+                    let item = JsonNode::default(Some(self.replica_id)).prepare_assign(value)?;
+                    operations.push(Element(operation, Some(Box::new(item))));
+                }
+            }
+            _ => {}
+        }
+
+        Ok(Assigned(assignment, operations))
+    }
+
+    // Prepares the command for a child of the node, which must be visible unless the command assigns it.
+    fn prepare_nested(&self, segment: &PathSegment, command: JsonCommand) -> Result<JsonOperation, CausalError> {
+        let assigns_child = matches!(&command, Set(path, _) if path.is_empty());
+
+        match (self.kind(), segment) {
+            (Some(JsonKind::Object), PathSegment::Key(key)) => {
+                if !assigns_child && self.fields.get(key).is_none() {
+                    return Err(CausalError::InvalidPath);
+                }
+
+                Ok(Field(Box::new(self.fields.prepare(&MapCommand::Update(key.clone(), command))?)))
+            }
+            (Some(JsonKind::Array), PathSegment::Index(index)) => {
+                let (v_ptr, item) = self.element(*index)?;
+
+                Ok(Item(v_ptr.clone(), Box::new(item.prepare(&command)?)))
+            }
+            _ => Err(CausalError::InvalidPath),
+        }
+    }
+
+    fn render(&self, kind: &JsonKind) -> JsonValue {
+        match kind {
+            JsonKind::Leaf(value) => value.clone(),
+            JsonKind::Object => JsonValue::Object(self.fields.query().into_iter().collect()),
+            JsonKind::Array => JsonValue::Array(self.visible_elements()
+                .filter_map(|v_ptr| self.items.get(v_ptr))
+                .map(|item| item.query())
+                .collect()),
+        }
+    }
+}
+
+impl Clone for JsonNode {
+    fn clone(&self) -> Self {
+        JsonNode {
+            replica_id: self.replica_id,
+            register: self.register.clone(),
+            fields: self.fields.clone(),
+            elements: self.elements.clone(),
+            items: self.items.clone(),
+        }
+    }
+}
+
+impl CRDT<JsonValue, JsonCommand, JsonOperation> for JsonNode {
+    fn default(replica_id: Option<ReplicaId>) -> Self {
+        JsonNode {
+            replica_id: replica_id.expect("You must set a valid replica id for the JSON CRDT to work."),
+            register: MVRegister::default(replica_id),
+            fields: ORMap::default(replica_id),
+            elements: RGA::default(replica_id),
+            items: HashMap::new(),
+        }
+    }
+
+    fn query(&self) -> JsonValue {
+        match self.kind() {
+            Some(kind) => self.render(&kind),
+            None => JsonValue::Null,
+        }
+    }
+
+    // The paths of the commands are relative to the node.
+    fn prepare(&self, command: &JsonCommand) -> Result<JsonOperation, CausalError> {
+        let is_object = matches!(self.kind(), Some(JsonKind::Object));
+        let is_array = matches!(self.kind(), Some(JsonKind::Array));
+
+        match command {
+            Set(path, value) => match path.split_first() {
+                None => self.prepare_assign(value),
+                Some((segment, rest)) => self.prepare_nested(segment, Set(rest.to_vec(), value.clone())),
+            },
+            Insert(path, value) => match path.split_first() {
+                Some((PathSegment::Index(index), [])) if is_array => {
+                    let operation = self.elements.prepare(&RGACommand::Insert(*index, ()))?;
+                    let item = JsonNode::default(Some(self.replica_id)).prepare_assign(value)?;
+
+                    Ok(Element(operation, Some(Box::new(item))))
+                }
+                Some((segment, rest)) if !rest.is_empty() => {
+                    self.prepare_nested(segment, Insert(rest.to_vec(), value.clone()))
+                }
+                _ => Err(CausalError::InvalidPath),
+            },
+            Remove(path) => match path.split_first() {
+                Some((PathSegment::Key(key), [])) if is_object => {
+                    self.fields.get(key).ok_or(CausalError::InvalidPath)?;
+
+                    Ok(Field(Box::new(self.fields.prepare(&MapCommand::Remove(key.clone()))?)))
+                }
+                Some((PathSegment::Index(index), [])) if is_array => {
+                    Ok(Element(self.elements.prepare(&RGACommand::Remove(*index))?, None))
+                }
+                Some((segment, rest)) if !rest.is_empty() => self.prepare_nested(segment, Remove(rest.to_vec())),
+                _ => Err(CausalError::InvalidPath),
+            },
+        }
+    }
+
+    fn effect(&mut self, event: &Event<JsonOperation>) -> Result<(), CausalError> {
+        match &event.data {
+            Assigned(assignment, operations) => {
+                // We apply the assignment to a copy of the node, so that a failure leaves the node untouched.
+                let mut node = self.clone();
+                node.register.effect(&event.nested(assignment.clone()))?;
+                for operation in operations {
+                    node.effect(&event.nested(operation.clone()))?;
+                }
+                *self = node;
+            }
+            Field(operation) => {
+                self.fields.effect(&event.nested((**operation).clone()))?;
+            }
+            Element(operation, item_operation) => {
+                let item = match item_operation {
+                    Some(item_operation) => {
+                        let mut item = JsonNode::default(Some(self.replica_id));
+                        item.effect(&event.nested((**item_operation).clone()))?;
+                        Some(item)
+                    }
+                    None => None,
+                };
+
+                self.elements.effect(&event.nested(operation.clone()))?;
+                if let (RGAOperation::Inserted(_, at_v_ptr, _), Some(item)) = (operation, item) {
+                    self.items.insert(at_v_ptr.clone(), item);
+                }
+            }
+            Item(v_ptr, operation) => {
+                // The node is missing only if the removal of the element was stable and it has been purged, then the
+                // operation is concurrent to the removal and its result would be hidden anyway.
+                if let Some(item) = self.items.get_mut(v_ptr) {
+                    item.effect(&event.nested((**operation).clone()))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stable(&mut self, frontier: &VTime) {
+        self.fields.stable(frontier);
+        self.elements.stable(frontier);
+
+        // The nodes of the elements purged from the array are dropped as well.
+        let elements = &self.elements;
+        self.items.retain(|v_ptr, _| elements.elements().iter().any(|(inner_v_ptr, _)| inner_v_ptr == v_ptr));
+        for item in self.items.values_mut() {
+            item.stable(frontier);
+        }
+    }
+}
+
+impl JsonCRDT {
+    // Returns all the values assigned concurrently to the node at the path, starting from the one shown by the query.
+    pub fn values(&self, path: &[PathSegment]) -> Result<Vec<JsonValue>, CausalError> {
+        let node = self.root.resolve(path)?;
+
+        Ok(node.kinds().iter().map(|kind| node.render(kind)).collect())
+    }
+}
+
+impl Clone for JsonCRDT {
+    fn clone(&self) -> Self {
+        JsonCRDT {
+            root: self.root.clone(),
+        }
+    }
+}
+
+impl CRDT<JsonValue, JsonCommand, JsonOperation> for JsonCRDT {
+    fn default(replica_id: Option<ReplicaId>) -> Self {
+        // The root is always an object, as if it had been assigned before any other event.
+        let mut root = JsonNode::default(replica_id);
+        let assignment = MVOperation::Assigned((ReplicaId::MIN, JsonKind::Object));
+        root.register
+            .effect(&local_event(ReplicaId::MIN, assignment))
+            .expect("The assignment of a register never fails.");
+
+        JsonCRDT {
+            root,
+        }
+    }
+
+    fn query(&self) -> JsonValue {
+        self.root.query()
+    }
+
+    fn prepare(&self, command: &JsonCommand) -> Result<JsonOperation, CausalError> {
+        match command {
+            Set(path, _) if path.is_empty() => Err(CausalError::InvalidPath),
+            _ => self.root.prepare(command),
+        }
+    }
+
+    fn effect(&mut self, event: &Event<JsonOperation>) -> Result<(), CausalError> {
+        self.root.effect(event)
+    }
+
+    fn stable(&mut self, frontier: &VTime) {
+        self.root.stable(frontier);
+    }
+}
+
+
+/** UTILS **/
+// An event which is only applied locally, while preparing an operation made of several ones.
+fn local_event<EVENT>(replica_id: ReplicaId, data: EVENT) -> Event<EVENT>
+    where EVENT: Clone
+{
+    Event {
+        origin: replica_id,
+        origin_seq_nr: 0,
+        local_seq_nr: 0,
+        version: VTime::init(),
+        data,
+    }
+}
+
+fn write_string(f: &mut Formatter<'_>, value: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for character in value.chars() {
+        match character {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            character if (character as u32) < 0x20 => write!(f, "\\u{:04x}", character as u32)?,
+            character => write!(f, "{}", character)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{CRDT, ReplicaState};
+    use crate::causal_core::CausalError;
+    use crate::causal_json::{JsonCommand, JsonCRDT, JsonOperation, JsonValue, PathSegment};
+    use crate::causal_utils::InMemory;
+
+    type JsonState = ReplicaState<JsonCRDT, JsonValue, JsonCommand, JsonOperation>;
+    type JsonStore = InMemory<JsonCRDT, JsonValue, JsonCommand, JsonOperation>;
+
+    fn replica(id: isize) -> (JsonState, JsonStore) {
+        (ReplicaState::create(id, JsonCRDT::default(Some(id))), InMemory::create())
+    }
+
+    fn todo(title: &str) -> JsonValue {
+        JsonValue::Object(BTreeMap::from([
+            (String::from("title"), JsonValue::from(title)),
+            (String::from("done"), JsonValue::from(false)),
+        ]))
+    }
+
+    #[test]
+    fn test_nested_values() {
+        let (mut state, mut store) = replica(0);
+
+        state.process_command(&JsonCommand::Set(json_path!["todos"], JsonValue::Array(vec![todo("milk")])), &mut store).unwrap();
+        state.process_command(&JsonCommand::Insert(json_path!["todos", 1], todo("eggs")), &mut store).unwrap();
+        state.process_command(&JsonCommand::Set(json_path!["todos", 0, "done"], JsonValue::from(true)), &mut store).unwrap();
+        state.process_command(&JsonCommand::Set(json_path!["owner"], JsonValue::from("a \"quoted\" name")), &mut store).unwrap();
+
+        assert_eq!(
+            state.process_query().to_string(),
+            r#"{"owner":"a \"quoted\" name","todos":[{"done":true,"title":"milk"},{"done":false,"title":"eggs"}]}"#
+        );
+
+        state.process_command(&JsonCommand::Remove(json_path!["todos", 0]), &mut store).unwrap();
+        assert_eq!(
+            state.process_query().to_string(),
+            r#"{"owner":"a \"quoted\" name","todos":[{"done":false,"title":"eggs"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_concurrent_inserts_converge() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+
+        state_0.process_command(&JsonCommand::Set(json_path!["list"], JsonValue::Array(vec![])), &mut store_0).unwrap();
+        state_1.process_replicated(0, 1, store_0.events.clone(), &mut store_1).unwrap();
+
+        state_0.process_command(&JsonCommand::Insert(json_path!["list", 0], JsonValue::from("a")), &mut store_0).unwrap();
+        state_1.process_command(&JsonCommand::Insert(json_path!["list", 0], JsonValue::from("b")), &mut store_1).unwrap();
+        state_0.process_replicated(1, 2, store_1.events[1..].to_vec(), &mut store_0).unwrap();
+        state_1.process_replicated(0, 2, store_0.events[1..2].to_vec(), &mut store_1).unwrap();
+
+        assert_eq!(state_0.process_query(), state_1.process_query());
+        assert_eq!(state_0.process_query().to_string().len(), r#"{"list":["a","b"]}"#.len());
+    }
+
+    #[test]
+    fn test_concurrent_assignments_are_resolved_deterministically() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+
+        state_0.process_command(&JsonCommand::Set(json_path!["title"], JsonValue::from("zero")), &mut store_0).unwrap();
+        state_1.process_command(&JsonCommand::Set(json_path!["title"], JsonValue::from("one")), &mut store_1).unwrap();
+        state_0.process_replicated(1, 1, store_1.events.clone(), &mut store_0).unwrap();
+        state_1.process_replicated(0, 1, store_0.events[..1].to_vec(), &mut store_1).unwrap();
+
+        assert_eq!(state_0.process_query().to_string(), r#"{"title":"one"}"#);
+        assert_eq!(state_1.process_query().to_string(), r#"{"title":"one"}"#);
+        let values = vec![JsonValue::from("one"), JsonValue::from("zero")];
+        assert_eq!(state_0.crdt.values(&[PathSegment::from("title")]), Ok(values.clone()));
+        assert_eq!(state_1.crdt.values(&[PathSegment::from("title")]), Ok(values));
+
+        // A later assignment replaces both of the concurrent values.
+        state_0.process_command(&JsonCommand::Set(json_path!["title"], JsonValue::from("zero")), &mut store_0).unwrap();
+        assert_eq!(state_0.process_query().to_string(), r#"{"title":"zero"}"#);
+        assert_eq!(state_0.crdt.values(&[PathSegment::from("title")]), Ok(vec![JsonValue::from("zero")]));
+    }
+
+    #[test]
+    fn test_concurrent_assignment_keeps_removed_key() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+
+        state_0.process_command(&JsonCommand::Set(json_path!["todo"], todo("milk")), &mut store_0).unwrap();
+        state_1.process_replicated(0, 1, store_0.events.clone(), &mut store_1).unwrap();
+
+        state_0.process_command(&JsonCommand::Remove(json_path!["todo"]), &mut store_0).unwrap();
+        assert_eq!(
+            state_0.process_command(&JsonCommand::Set(json_path!["todo", "done"], JsonValue::from(true)), &mut store_0).err(),
+            Some(CausalError::InvalidPath)
+        );
+
+        state_1.process_command(&JsonCommand::Set(json_path!["todo", "done"], JsonValue::from(true)), &mut store_1).unwrap();
+        state_0.process_replicated(1, 2, store_1.events[1..].to_vec(), &mut store_0).unwrap();
+        state_1.process_replicated(0, 2, store_0.events[1..2].to_vec(), &mut store_1).unwrap();
+
+        // Objects are OR-Maps, thus the concurrent assignment of a field keeps the key, but the removal resets its node.
+        assert_eq!(state_0.process_query().to_string(), r#"{"todo":null}"#);
+        assert_eq!(state_1.process_query().to_string(), r#"{"todo":null}"#);
+        state_0.process_command(&JsonCommand::Set(json_path!["todo"], todo("eggs")), &mut store_0).unwrap();
+        assert_eq!(state_0.process_query().to_string(), r#"{"todo":{"done":false,"title":"eggs"}}"#);
+    }
+
+    #[test]
+    fn test_stable_removals_purge_elements() {
+        let (mut state, mut store) = replica(0);
+        state.process_command(&JsonCommand::Set(json_path!["list"], JsonValue::Array(vec![todo("milk"), todo("eggs")])), &mut store).unwrap();
+        state.process_command(&JsonCommand::Remove(json_path!["list", 0]), &mut store).unwrap();
+
+        let frontier = state.version.clone();
+        state.crdt.stable(&frontier);
+
+        let list = state.crdt.root.fields.get(&String::from("list")).unwrap();
+        assert_eq!(list.items.len(), 1);
+        assert_eq!(state.process_query().to_string(), r#"{"list":[{"done":false,"title":"eggs"}]}"#);
+
+        state.process_command(&JsonCommand::Insert(json_path!["list", 1], todo("bread")), &mut store).unwrap();
+        assert_eq!(
+            state.process_query().to_string(),
+            r#"{"list":[{"done":false,"title":"eggs"},{"done":false,"title":"bread"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_invalid_paths_are_rejected() {
+        let (mut state, mut store) = replica(0);
+        state.process_command(&JsonCommand::Set(json_path!["list"], JsonValue::Array(vec![])), &mut store).unwrap();
+
+        assert_eq!(
+            state.process_command(&JsonCommand::Set(json_path!["list", "key"], JsonValue::Null), &mut store).err(),
+            Some(CausalError::InvalidPath)
+        );
+        assert_eq!(
+            state.process_command(&JsonCommand::Insert(json_path!["list", 1], JsonValue::Null), &mut store).err(),
+            Some(CausalError::IndexOutOfBounds(1))
+        );
+        assert_eq!(
+            state.process_command(&JsonCommand::Set(json_path!["missing", "key"], JsonValue::Null), &mut store).err(),
+            Some(CausalError::InvalidPath)
+        );
+        assert_eq!(state.process_query().to_string(), r#"{"list":[]}"#);
+    }
+}
@@ -1,42 +1,80 @@
 use std::cmp;
 use std::cmp::Ordering;
 use std::cmp::Ordering::{Greater, Less};
+use std::iter;
 
-use crate::{CRDT, Event, EventStore, InMemory, InputReceiver, ReplicaId, ReplicaState};
-use crate::causal_core::SeqNr;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::{CRDT, Event, InputReceiver, ReplicaId};
+use crate::causal_core::CausalError;
 use crate::causal_lseq::LSeqCommand::{Insert, Remove};
 use crate::causal_lseq::LSeqOperation::{Inserted, Removed};
 
-type Sequence = Vec<u8>;
+type Sequence = Vec<u32>;
+
+// The base of the first level of the identifiers is 2^4, and it doubles at every level, until 2^31.
+const INITIAL_BASE_BITS: u32 = 4;
+const MAX_BASE_BITS: u32 = 31;
+// The maximum distance of a new digit from the bound it is allocated from.
+const BOUNDARY: u32 = 10;
+
+// How the digit of a new identifier is picked, once the level with enough room is found.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AllocationStrategy {
+    // Close to the lower bound, which leaves room for the values appended later.
+    BoundaryPlus,
+    // Close to the upper bound, which leaves room for the values prepended later.
+    BoundaryMinus,
+    // Boundary+ on even levels and boundary- on odd levels.
+    Alternating,
+    // Boundary+ or boundary- picked at random for every level, the same on every replica, as in LSEQ.
+    Random,
+}
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LSeqPtr {
     sequence: Sequence,
     replica_id: ReplicaId,
 }
 
 impl LSeqPtr {
-    fn from(replica_id: ReplicaId, low: &Sequence, high: &Sequence) -> LSeqPtr {
+    fn from(replica_id: ReplicaId, low: &Sequence, high: &Sequence, boundary_plus: impl Fn(usize) -> bool, rng: &mut impl Rng) -> LSeqPtr {
         LSeqPtr {
-            sequence: LSeqPtr::generate_seq(low, high),
+            sequence: LSeqPtr::generate_seq(low, high, boundary_plus, rng),
             replica_id,
         }
     }
 
-    fn generate_seq(low: &Sequence, high: &Sequence) -> Sequence {
-        fn generate(mut mid: Sequence, low: &Sequence, high: &Sequence, i: usize) -> Sequence {
-            let min = if i >= low.len() { 0 } else { low[i] };
-            let max = if i >= high.len() { 255 } else { high[i] };
+    // The number of levels of the identifier.
+    pub fn depth(&self) -> usize {
+        self.sequence.len()
+    }
+
+    // Generates a sequence between the two, where an empty high sequence stands for the end of the document.
+    fn generate_seq(low: &Sequence, high: &Sequence, boundary_plus: impl Fn(usize) -> bool, rng: &mut impl Rng) -> Sequence {
+        let mut sequence = vec![];
+        // As long as the new sequence is a prefix of the high one, the high one bounds the next digit too.
+        let mut bounded = !high.is_empty();
+
+        for depth in 0.. {
+            let base = base(depth);
+            let min = low.get(depth).copied().unwrap_or(0);
+            let max = if bounded { high.get(depth).copied().unwrap_or(base) } else { base };
 
             if min + 1 < max {
-                mid.insert(i, min + 1);
-                mid
-            } else {
-                mid.insert(i, min);
-                generate(mid, low, high, i + 1)
+                let step = cmp::min(BOUNDARY, max - min - 1);
+                let offset = rng.gen_range(1..=step);
+                sequence.push(if boundary_plus(depth) { min + offset } else { max - offset });
+                break;
             }
+
+            sequence.push(min);
+            bounded = bounded && min == max;
         }
 
-        generate(vec![], low, high, 0)
+        sequence
     }
 }
 
@@ -44,7 +82,7 @@ impl Clone for LSeqPtr {
     fn clone(&self) -> Self {
         LSeqPtr {
             sequence: self.sequence.clone(),
-            replica_id: self.replica_id.clone(),
+            replica_id: self.replica_id,
         }
     }
 }
@@ -71,7 +109,7 @@ impl PartialOrd for LSeqPtr {
             }
         }
 
-        return if self.sequence.len() < other.sequence.len() {
+        if self.sequence.len() < other.sequence.len() {
             Some(Less)
         } else if self.sequence.len() > other.sequence.len() {
             Some(Greater)
@@ -81,10 +119,11 @@ impl PartialOrd for LSeqPtr {
             } else {
                 Some(Greater)
             }
-        };
+        }
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LSeqCommand<T>
     where T: Clone
 {
@@ -93,6 +132,7 @@ pub enum LSeqCommand<T>
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LSeqOperation<T>
     where T: Clone
 {
@@ -100,10 +140,51 @@ pub enum LSeqOperation<T>
     Removed(LSeqPtr),
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LSeq<T>
     where T: Clone
 {
     elements: Vec<(LSeqPtr, T)>,
+    strategy: AllocationStrategy,
+    // The digits of the identifiers are drawn from generators derived from the seed.
+    seed: u64,
+    // The number of insertions applied so far, which changes after every allocation.
+    insertions: u64,
+}
+
+impl<T> LSeq<T>
+    where T: Clone
+{
+    // Replicas created with the same seed allocate the same identifiers for the same commands, thus the seed should
+    // differ between replicas to make concurrent identifiers unlikely to collide.
+    pub fn create(strategy: AllocationStrategy, seed: u64) -> LSeq<T> {
+        LSeq {
+            elements: vec![],
+            strategy,
+            seed: mix(seed),
+            insertions: 0,
+        }
+    }
+
+    // The generator of the digits of a new identifier depends on the number of insertions too, otherwise an element
+    // inserted where another one has been removed would get the identifier of the removed one.
+    fn rng(&self, low: &Sequence, high: &Sequence) -> StdRng {
+        let seed = low.iter()
+            .chain(iter::once(&u32::MAX))
+            .chain(high.iter())
+            .fold(mix(self.seed ^ self.insertions), |seed, digit| mix(seed ^ *digit as u64));
+
+        StdRng::seed_from_u64(seed)
+    }
+
+    fn boundary_plus(&self, depth: usize) -> bool {
+        match self.strategy {
+            AllocationStrategy::BoundaryPlus => true,
+            AllocationStrategy::BoundaryMinus => false,
+            AllocationStrategy::Alternating => depth.is_multiple_of(2),
+            AllocationStrategy::Random => mix(depth as u64) & 1 == 1,
+        }
+    }
 }
 
 impl<T> Clone for LSeq<T>
@@ -111,7 +192,10 @@ impl<T> Clone for LSeq<T>
 {
     fn clone(&self) -> Self {
         LSeq {
-            elements: self.elements.iter().cloned().collect()
+            elements: self.elements.to_vec(),
+            strategy: self.strategy,
+            seed: self.seed,
+            insertions: self.insertions,
         }
     }
 }
@@ -119,10 +203,8 @@ impl<T> Clone for LSeq<T>
 impl<T> CRDT<Vec<T>, LSeqCommand<T>, LSeqOperation<T>> for LSeq<T>
     where T: Clone
 {
-    fn default(_: Option<ReplicaId>) -> Self {
-        LSeq {
-            elements: vec![]
-        }
+    fn default(replica_id: Option<ReplicaId>) -> Self {
+        LSeq::create(AllocationStrategy::Random, replica_id.unwrap_or(0) as u64)
     }
 
     fn query(&self) -> Vec<T> {
@@ -133,73 +215,51 @@ impl<T> CRDT<Vec<T>, LSeqCommand<T>, LSeqOperation<T>> for LSeq<T>
             .collect()
     }
 
-    fn prepare(&self, command: &LSeqCommand<T>) -> LSeqOperation<T> {
+    fn prepare(&self, command: &LSeqCommand<T>) -> Result<LSeqOperation<T>, CausalError> {
         match command {
             Insert(index, replica_id, value) => {
+                if *index > self.elements.len() {
+                    return Err(CausalError::IndexOutOfBounds(*index));
+                }
+
                 let empty_v_ptr = vec![];
 
                 let left = if *index == 0 { &empty_v_ptr } else { &self.elements[*index - 1].0.sequence };
                 let right = if *index >= self.elements.len() { &empty_v_ptr } else { &self.elements[*index].0.sequence };
 
-                println!("Inserting at pos {}", index);
+                let v_ptr = LSeqPtr::from(*replica_id, left, right, |depth| self.boundary_plus(depth), &mut self.rng(left, right));
 
-                Inserted(LSeqPtr::from(replica_id.clone(), &left, &right), value.clone())
+                Ok(Inserted(v_ptr, value.clone()))
             }
             Remove(index) => {
-                Removed(self.elements[*index].0.clone())
+                self.elements
+                    .get(*index)
+                    .map(|(v_ptr, _)| Removed(v_ptr.clone()))
+                    .ok_or(CausalError::IndexOutOfBounds(*index))
             }
         }
     }
 
-    fn effect(&mut self, event: &Event<LSeqOperation<T>>) {
+    fn effect(&mut self, event: &Event<LSeqOperation<T>>) -> Result<(), CausalError> {
         match &event.data {
             Inserted(ins_v_ptr, value) => {
                 let index = self.elements
                     .iter()
                     .position(|(v_ptr, _)| ins_v_ptr <= v_ptr)
-                    .or(Some(self.elements.len()))
-                    .unwrap();
+                    .unwrap_or(self.elements.len());
 
                 self.elements.insert(index, (ins_v_ptr.clone(), value.clone()));
+                self.insertions += 1;
             }
             Removed(rem_v_ptr) => {
-                let index = self.elements
-                    .iter()
-                    .position(|(v_ptr, _)| rem_v_ptr == v_ptr)
-                    .expect("Couldn't find position of the character to delete.");
-
-                self.elements.remove(index);
+                // The element might have already been removed by a concurrent removal.
+                if let Some(index) = self.elements.iter().position(|(v_ptr, _)| rem_v_ptr == v_ptr) {
+                    self.elements.remove(index);
+                }
             }
         }
-    }
-}
 
-impl<T> EventStore<LSeq<T>, Vec<T>, LSeqCommand<T>, LSeqOperation<T>> for InMemory<LSeq<T>, Vec<T>, LSeqCommand<T>, LSeqOperation<T>>
-    where T: Clone
-{
-    fn save_snapshot(&mut self, state: &ReplicaState<LSeq<T>, Vec<T>, LSeqCommand<T>, LSeqOperation<T>>) {
-        self.last_snapshot = Some(state.clone())
-    }
-
-    fn load_snapshot(&self) -> Option<ReplicaState<LSeq<T>, Vec<T>, LSeqCommand<T>, LSeqOperation<T>>> {
-        match &self.last_snapshot {
-            Some(last_snapshot) => Some(last_snapshot.clone()),
-            _ => None
-        }
-    }
-
-    fn save_events(&mut self, events: Vec<Event<LSeqOperation<T>>>) {
-        events.iter().for_each(|event| self.events.push(event.clone()));
-    }
-
-    fn load_events(&self, start_seq_nr: SeqNr) -> Vec<Event<LSeqOperation<T>>> {
-        self.events
-            .clone()
-            .into_iter()
-            .filter(|event| {
-                event.local_seq_nr >= start_seq_nr
-            })
-            .collect()
+        Ok(())
     }
 }
 
@@ -228,56 +288,101 @@ impl InputReceiver for LSeqReceiver {
     }
 }
 
+/** UTILS **/
+fn base(depth: usize) -> u32 {
+    1 << cmp::min(INITIAL_BASE_BITS + depth as u32, MAX_BASE_BITS)
+}
+
+// The finalizer of splitmix64, which spreads the bits of close seeds, e.g. consecutive replica ids.
+fn mix(seed: u64) -> u64 {
+    let mut seed = seed.wrapping_add(0x9E3779B97F4A7C15);
+    seed = (seed ^ (seed >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    seed = (seed ^ (seed >> 27)).wrapping_mul(0x94D049BB133111EB);
+    seed ^ (seed >> 31)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::causal_lseq::{LSeqPtr, Sequence};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
-    #[test]
-    fn test_generate_seq_empty() {
-        let low = LSeqPtr::new(0);
-        let high = LSeqPtr::new(1);
+    use crate::{CRDT, ReplicaId, ReplicaState};
+    use crate::causal_lseq::{LSeq, LSeqCommand, LSeqOperation, LSeqPtr, Sequence};
+    use crate::causal_utils::InMemory;
 
-        let expected_sequence: Sequence = vec![1];
-        let sequence = LSeqPtr::generate_seq(&low.sequence, &high.sequence);
+    type LSeqState = ReplicaState<LSeq<char>, Vec<char>, LSeqCommand<char>, LSeqOperation<char>>;
+    type LSeqStore = InMemory<LSeq<char>, Vec<char>, LSeqCommand<char>, LSeqOperation<char>>;
 
-        assert_eq!(expected_sequence, sequence);
+    impl LSeqPtr {
+        fn new(replica_id: ReplicaId) -> LSeqPtr {
+            LSeqPtr {
+                sequence: vec![],
+                replica_id,
+            }
+        }
+    }
+
+    fn replica(id: ReplicaId) -> (LSeqState, LSeqStore) {
+        (ReplicaState::create(id, LSeq::default(Some(id))), InMemory::create())
+    }
+
+    fn generate(low: &Sequence, high: &Sequence, boundary_plus: bool) -> Sequence {
+        LSeqPtr::generate_seq(low, high, |_| boundary_plus, &mut StdRng::seed_from_u64(0))
     }
 
     #[test]
-    fn test_generate_seq_start() {
-        let low = LSeqPtr::new(0);
-        let mut high = LSeqPtr::new(1);
-        high.sequence = vec![1];
+    fn test_generate_seq_empty() {
+        let plus = generate(&vec![], &vec![], true);
+        let minus = generate(&vec![], &vec![], false);
+
+        // The first level has base 16, and the digit is within the boundary from the bound of the strategy.
+        assert_eq!(plus.len(), 1);
+        assert!((1..=10).contains(&plus[0]));
+        assert_eq!(minus.len(), 1);
+        assert!((6..=15).contains(&minus[0]));
+    }
 
-        let expected_sequence: Sequence = vec![0, 1];
-        let sequence = LSeqPtr::generate_seq(&low.sequence, &high.sequence);
+    #[test]
+    fn test_generate_seq_start() {
+        let sequence = generate(&vec![], &vec![1], true);
 
-        assert_eq!(expected_sequence, sequence);
+        assert_eq!(sequence.len(), 2);
+        assert_eq!(sequence[0], 0);
+        assert!((1..=10).contains(&sequence[1]));
     }
 
     #[test]
     fn test_generate_seq_end() {
-        let mut low = LSeqPtr::new(0);
-        low.sequence = vec![1];
-        let high = LSeqPtr::new(1);
-
-        let expected_sequence: Sequence = vec![2];
-        let sequence = LSeqPtr::generate_seq(&low.sequence, &high.sequence);
+        let sequence = generate(&vec![15], &vec![], true);
 
-        assert_eq!(expected_sequence, sequence);
+        // There is no room left on the first level, while the second one has base 32.
+        assert_eq!(sequence.len(), 2);
+        assert_eq!(sequence[0], 15);
+        assert!((1..=10).contains(&sequence[1]));
     }
 
     #[test]
     fn test_generate_seq_middle() {
-        let mut low = LSeqPtr::new(0);
-        low.sequence = vec![1];
-        let mut high = LSeqPtr::new(1);
-        high.sequence = vec![2];
+        let sequence = generate(&vec![1, 31], &vec![2], false);
 
-        let expected_sequence: Sequence = vec![1, 1];
-        let sequence = LSeqPtr::generate_seq(&low.sequence, &high.sequence);
+        assert_eq!(sequence.len(), 3);
+        assert_eq!(&sequence[..2], &[1, 31]);
+        assert!((54..=63).contains(&sequence[2]));
+    }
 
-        assert_eq!(expected_sequence, sequence);
+    #[test]
+    fn test_generated_seq_is_between_bounds() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut low = LSeqPtr::new(0);
+        let high = LSeqPtr::new(0);
+
+        // Appending always stays between the last identifier and the end of the document.
+        for depth in 0..200 {
+            let mut next = LSeqPtr::new(0);
+            next.sequence = LSeqPtr::generate_seq(&low.sequence, &high.sequence, |level| (level + depth) % 3 != 0, &mut rng);
+            assert!(low < next);
+            low = next;
+        }
     }
 
     #[test]
@@ -319,4 +424,24 @@ mod tests {
 
         assert!(low < high);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_reinsertion_after_concurrent_removal_converges() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+
+        state_0.process_command(&LSeqCommand::Insert(0, 0, 'a'), &mut store_0).unwrap();
+        state_1.process_replicated(0, 1, store_0.events.clone(), &mut store_1).unwrap();
+
+        // Replica 0 replaces the element at the same spot while replica 1 concurrently removes it.
+        state_0.process_command(&LSeqCommand::Remove(0), &mut store_0).unwrap();
+        state_0.process_command(&LSeqCommand::Insert(0, 0, 'b'), &mut store_0).unwrap();
+        state_1.process_command(&LSeqCommand::Remove(0), &mut store_1).unwrap();
+
+        state_0.process_replicated(1, 1, store_1.events.clone(), &mut store_0).unwrap();
+        state_1.process_replicated(0, 3, store_0.events[1..].to_vec(), &mut store_1).unwrap();
+
+        assert_eq!(state_0.process_query(), vec!['b']);
+        assert_eq!(state_1.process_query(), vec!['b']);
+    }
+}
@@ -0,0 +1,408 @@
+use std::io;
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use actix::{Actor, ActorContext, ActorFutureExt, AsyncContext, Context, Handler, Running, WrapFuture};
+use actix::io::{FramedWrite, WriteHandler};
+use bytes::{Buf, BufMut, BytesMut};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio_util::codec::{Decoder, Encoder, FramedRead};
+
+use crate::{Event, ReplicaId, VoidCausalMessage};
+use crate::causal_actix::VoidCausalRecipient;
+use crate::causal_core::{CausalError, SeqNr, VTime};
+use crate::causal_serde::Envelope;
+
+/** TYPES **/
+// Every frame is prefixed by the length of its envelope.
+const FRAME_HEADER_SIZE: usize = 4;
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+
+/** MESSAGES **/
+// The subset of the messages exchanged between replicas that can be sent over the network.
+#[derive(Serialize, Deserialize)]
+pub enum WireMessage<EVENT>
+    where EVENT: Clone
+{
+    Replicate(ReplicaId, SeqNr, VTime, usize),
+    Replicated(ReplicaId, SeqNr, Vec<Event<EVENT>>, Option<SeqNr>),
+    Pushed(ReplicaId, Vec<Event<EVENT>>),
+}
+
+
+/** CODECS **/
+pub struct ReplicationCodec<EVENT> {
+    _1: PhantomData<EVENT>,
+}
+
+
+/** ACTORS **/
+// Actor which stands in for a replica living in another process. The replication messages it receives are sent to
+// the listener of the remote replica.
+pub struct RemoteReplica<CMD: 'static, EVENT: 'static>
+    where CMD: Send + Unpin,
+          EVENT: Serialize + DeserializeOwned + Send + Clone + Unpin
+{
+    address: SocketAddr,
+    writer: Option<FramedWrite<WireMessage<EVENT>, OwnedWriteHalf, ReplicationCodec<EVENT>>>,
+    _1: PhantomData<CMD>,
+}
+
+// Actor which accepts the connections of remote replicas and forwards their messages to the local replica.
+pub struct ReplicaListener<CMD: 'static, EVENT: 'static>
+    where CMD: Send + Unpin,
+          EVENT: Serialize + DeserializeOwned + Send + Clone + Unpin
+{
+    listener: Option<std::net::TcpListener>,
+    replica: VoidCausalRecipient<CMD, EVENT>,
+}
+
+
+/** IMPLEMENTATIONS **/
+impl<CMD, EVENT> From<WireMessage<EVENT>> for VoidCausalMessage<CMD, EVENT>
+    where CMD: Send + Unpin,
+          EVENT: Send + Clone
+{
+    fn from(message: WireMessage<EVENT>) -> Self {
+        match message {
+            WireMessage::Replicate(sender, seq_nr, version, batch_size) => {
+                VoidCausalMessage::Replicate(sender, seq_nr, version, batch_size)
+            }
+            WireMessage::Replicated(sender, last_seq_nr, events, continuation) => {
+                VoidCausalMessage::Replicated(sender, last_seq_nr, events, continuation)
+            }
+            WireMessage::Pushed(sender, events) => VoidCausalMessage::Pushed(sender, events),
+        }
+    }
+}
+
+impl<EVENT> ReplicationCodec<EVENT> {
+    pub fn new() -> ReplicationCodec<EVENT> {
+        ReplicationCodec {
+            _1: PhantomData,
+        }
+    }
+}
+
+impl<EVENT> Default for ReplicationCodec<EVENT> {
+    fn default() -> Self {
+        ReplicationCodec::new()
+    }
+}
+
+impl<EVENT> Encoder<WireMessage<EVENT>> for ReplicationCodec<EVENT>
+    where EVENT: Serialize + DeserializeOwned + Clone
+{
+    type Error = io::Error;
+
+    fn encode(&mut self, message: WireMessage<EVENT>, destination: &mut BytesMut) -> Result<(), Self::Error> {
+        let envelope = Envelope::new(message).encode();
+        if envelope.len() > MAX_FRAME_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "The message exceeds the maximum frame size."));
+        }
+
+        destination.reserve(FRAME_HEADER_SIZE + envelope.len());
+        destination.put_u32(envelope.len() as u32);
+        destination.put_slice(&envelope);
+
+        Ok(())
+    }
+}
+
+impl<EVENT> Decoder for ReplicationCodec<EVENT>
+    where EVENT: Serialize + DeserializeOwned + Clone
+{
+    type Item = WireMessage<EVENT>;
+    type Error = io::Error;
+
+    fn decode(&mut self, source: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if source.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let length = u32::from_be_bytes(source[..FRAME_HEADER_SIZE].try_into().unwrap()) as usize;
+        if length > MAX_FRAME_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidData, "The frame exceeds the maximum frame size."));
+        }
+
+        // We wait until the whole frame has been received.
+        if source.len() < FRAME_HEADER_SIZE + length {
+            source.reserve(FRAME_HEADER_SIZE + length - source.len());
+            return Ok(None);
+        }
+
+        source.advance(FRAME_HEADER_SIZE);
+        let frame = source.split_to(length);
+
+        Envelope::decode(&frame).map(|envelope| Some(envelope.payload))
+    }
+}
+
+impl<CMD, EVENT> RemoteReplica<CMD, EVENT>
+    where CMD: Send + Unpin,
+          EVENT: Serialize + DeserializeOwned + Send + Clone + Unpin
+{
+    pub fn create(address: SocketAddr) -> RemoteReplica<CMD, EVENT> {
+        RemoteReplica {
+            address,
+            writer: None,
+            _1: PhantomData,
+        }
+    }
+
+    fn connect(&mut self, ctx: &mut Context<Self>) {
+        let connection = TcpStream::connect(self.address)
+            .into_actor(self)
+            .map(|result, actor, ctx| {
+                match result {
+                    Ok(stream) => {
+                        println!("Connected to the remote replica at {}", actor.address);
+                        // The remote replica never writes on this connection, its answers come from its own connector.
+                        let (_, write_half) = stream.into_split();
+                        actor.writer = Some(FramedWrite::new(write_half, ReplicationCodec::new(), ctx));
+                    }
+                    Err(err) => {
+                        println!("Error while connecting to the remote replica at {}: {}", actor.address, err);
+                        actor.reconnect(ctx);
+                    }
+                }
+            });
+
+        // We stop processing messages until the connection attempt completes.
+        ctx.wait(connection);
+    }
+
+    fn reconnect(&mut self, ctx: &mut Context<Self>) {
+        self.writer = None;
+        ctx.run_later(RECONNECT_DELAY, |actor, ctx| actor.connect(ctx));
+    }
+}
+
+impl<CMD, EVENT> Actor for RemoteReplica<CMD, EVENT>
+    where CMD: Send + Unpin,
+          EVENT: Serialize + DeserializeOwned + Send + Clone + Unpin
+{
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.connect(ctx);
+    }
+}
+
+impl<CMD, EVENT> WriteHandler<io::Error> for RemoteReplica<CMD, EVENT>
+    where CMD: Send + Unpin,
+          EVENT: Serialize + DeserializeOwned + Send + Clone + Unpin
+{
+    fn error(&mut self, err: io::Error, ctx: &mut Self::Context) -> Running {
+        println!("Error while writing to the remote replica at {}: {}", self.address, err);
+        self.reconnect(ctx);
+        Running::Continue
+    }
+
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        self.reconnect(ctx);
+    }
+}
+
+impl<CMD, EVENT> Handler<VoidCausalMessage<CMD, EVENT>> for RemoteReplica<CMD, EVENT>
+    where CMD: Send + Unpin,
+          EVENT: Serialize + DeserializeOwned + Send + Clone + Unpin
+{
+    type Result = Result<(), CausalError>;
+
+    fn handle(&mut self, msg: VoidCausalMessage<CMD, EVENT>, _: &mut Self::Context) -> Self::Result {
+        let message = match msg {
+            VoidCausalMessage::Replicate(sender, seq_nr, version, batch_size) => {
+                WireMessage::Replicate(sender, seq_nr, version, batch_size)
+            }
+            VoidCausalMessage::Replicated(sender, last_seq_nr, events, continuation) => {
+                WireMessage::Replicated(sender, last_seq_nr, events, continuation)
+            }
+            VoidCausalMessage::Pushed(sender, events) => WireMessage::Pushed(sender, events),
+            _ => {
+                println!("Only [REPLICATE], [REPLICATED] and [PUSHED] can be sent to the remote replica at {}", self.address);
+                return Ok(());
+            }
+        };
+
+        // Messages sent while disconnected are dropped, the next sync will request the missing events again.
+        match &mut self.writer {
+            Some(writer) => writer.write(message),
+            None => println!("Dropping message to the disconnected remote replica at {}", self.address),
+        }
+
+        Ok(())
+    }
+}
+
+impl<CMD, EVENT> ReplicaListener<CMD, EVENT>
+    where CMD: Send + Unpin,
+          EVENT: Serialize + DeserializeOwned + Send + Clone + Unpin
+{
+    pub fn bind(
+        address: impl ToSocketAddrs,
+        replica: VoidCausalRecipient<CMD, EVENT>,
+    ) -> io::Result<ReplicaListener<CMD, EVENT>> {
+        let listener = std::net::TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(ReplicaListener {
+            listener: Some(listener),
+            replica,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match &self.listener {
+            Some(listener) => listener.local_addr(),
+            None => Err(io::Error::new(ErrorKind::NotConnected, "The listener has already been started.")),
+        }
+    }
+}
+
+impl<CMD, EVENT> Actor for ReplicaListener<CMD, EVENT>
+    where CMD: Send + Unpin,
+          EVENT: Serialize + DeserializeOwned + Send + Clone + Unpin
+{
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let listener = match TcpListener::from_std(self.listener.take().unwrap()) {
+            Ok(listener) => listener,
+            Err(err) => {
+                println!("Error while starting the listener: {}", err);
+                ctx.stop();
+                return;
+            }
+        };
+        let replica = self.replica.clone();
+
+        ctx.spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, address)) => {
+                        println!("Accepted connection from the remote replica at {}", address);
+                        actix::spawn(forward(stream, replica.clone()));
+                    }
+                    Err(err) => println!("Error while accepting a connection: {}", err),
+                }
+            }
+        }.into_actor(self));
+    }
+}
+
+
+/** UTILS **/
+async fn forward<CMD, EVENT>(stream: TcpStream, replica: VoidCausalRecipient<CMD, EVENT>)
+    where CMD: Send + Unpin + 'static,
+          EVENT: Serialize + DeserializeOwned + Send + Clone + Unpin + 'static
+{
+    let mut frames = FramedRead::new(stream, ReplicationCodec::<EVENT>::new());
+
+    while let Some(frame) = frames.next().await {
+        match frame {
+            Ok(message) => replica.do_send(message.into()),
+            Err(err) => {
+                println!("Error while reading from a remote replica: {}", err);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+    use std::time::{Duration, Instant};
+
+    use actix::{Actor, System};
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use crate::{CRDT, Event, VectorClock};
+    use crate::causal_actix::{Replica, ValuedCausalMessage, VoidCausalMessage};
+    use crate::causal_net::{RemoteReplica, ReplicaListener, ReplicationCodec, WireMessage};
+    use crate::causal_rga::{RGA, RGACommand};
+    use crate::causal_utils::InMemory;
+
+    #[test]
+    fn test_codec_roundtrip_with_partial_frames() {
+        let mut codec = ReplicationCodec::<u64>::new();
+        let mut version = VectorClock::init();
+        version.increment(1);
+        let event = Event {
+            origin: 1,
+            origin_seq_nr: 1,
+            local_seq_nr: 1,
+            version: version.clone(),
+            data: 42,
+        };
+
+        let mut bytes = BytesMut::new();
+        codec.encode(WireMessage::Replicate(0, 1, version, 10), &mut bytes).unwrap();
+        codec.encode(WireMessage::Replicated(1, 1, vec![event], None), &mut bytes).unwrap();
+
+        // We feed the bytes one at a time, the decoder must only yield complete frames.
+        let mut source = BytesMut::new();
+        let mut messages = vec![];
+        for byte in bytes {
+            source.extend_from_slice(&[byte]);
+            if let Some(message) = codec.decode(&mut source).unwrap() {
+                messages.push(message);
+            }
+        }
+
+        assert_eq!(messages.len(), 2);
+        assert!(matches!(messages[0], WireMessage::Replicate(0, 1, _, 10)));
+        match &messages[1] {
+            WireMessage::Replicated(1, 1, events, None) => assert_eq!(events[0].data, 42),
+            _ => panic!("Unexpected message"),
+        }
+    }
+
+    #[test]
+    fn test_replicas_sync_over_tcp() {
+        let result = System::new().block_on(async {
+            let replica_0 = Replica::create(0, RGA::default(Some(0)), InMemory::create()).start();
+            let replica_1 = Replica::create(1, RGA::default(Some(1)), InMemory::create()).start();
+
+            let listener_0 = ReplicaListener::bind("127.0.0.1:0", replica_0.clone().recipient()).unwrap();
+            let listener_1 = ReplicaListener::bind("127.0.0.1:0", replica_1.clone().recipient()).unwrap();
+            let remote_0 = RemoteReplica::create(listener_0.local_addr().unwrap()).start();
+            let remote_1 = RemoteReplica::create(listener_1.local_addr().unwrap()).start();
+            listener_0.start();
+            listener_1.start();
+
+            replica_0.do_send(VoidCausalMessage::Connect(1, remote_1.recipient()));
+            replica_1.do_send(VoidCausalMessage::Connect(0, remote_0.recipient()));
+            replica_0.do_send(VoidCausalMessage::Command(RGACommand::Insert(0, 'a')));
+            replica_1.do_send(VoidCausalMessage::Command(RGACommand::Insert(0, 'b')));
+
+            // The messages sent before a connector is connected are dropped, thus we keep syncing until both replicas
+            // have received the event of the other one.
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                replica_0.do_send(VoidCausalMessage::Sync);
+                replica_1.do_send(VoidCausalMessage::Sync);
+                actix_rt::time::sleep(Duration::from_millis(10)).await;
+
+                let state_0 = replica_0.send(ValuedCausalMessage::Query(PhantomData)).await.unwrap().0;
+                let state_1 = replica_1.send(ValuedCausalMessage::Query(PhantomData)).await.unwrap().0;
+                if (state_0.len() == 2 && state_1.len() == 2) || Instant::now() > deadline {
+                    break (state_0, state_1);
+                }
+            }
+        });
+
+        assert_eq!(result.0.len(), 2);
+        assert_eq!(result.0, result.1);
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use crate::{CRDT, Event, ReplicaId, VectorClock};
+use crate::causal_core::{CausalError, VTime};
+use crate::causal_or_map::MapCommand::{Remove, Update};
+use crate::causal_or_map::MapOperation::{Removed, Updated};
+use crate::causal_or_set::{compact, is_observed};
+use crate::causal_time::ClockComparison;
+
+/** DATA STRUCTURES **/
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MapCommand<K, CMD> {
+    // Runs the command on the CRDT of the key, which is created if the key is not in the map.
+    Update(K, CMD),
+    Remove(K),
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MapOperation<K, EVENT>
+    where K: Clone,
+          EVENT: Clone
+{
+    Updated(K, EVENT),
+    Removed(K, HashSet<VTime>),
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct MapEntry<C> {
+    crdt: C,
+    // The versions of the updates observed for the key, the key is in the map as long as there is at least one.
+    tags: HashSet<VTime>,
+    // The merged versions of the removals which reset the CRDT of the key, until they are stable.
+    removed: Option<VTime>,
+}
+
+// A map whose keys have observed-remove semantics, like the elements of an ORSet, and whose values are CRDTs.
+// Removing a key resets its CRDT, so that a key which is updated after a removal starts from the default state. The
+// updates concurrent to a removal keep the key in the map, but they were made on the removed state, thus their effects
+// are discarded on every replica.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "K: serde::Serialize, C: serde::Serialize",
+    deserialize = "K: serde::Deserialize<'de> + Eq + Hash, C: serde::Deserialize<'de>",
+)))]
+pub struct ORMap<K, C, STATE, CMD, EVENT>
+    where K: Clone + Eq + Hash,
+          C: CRDT<STATE, CMD, EVENT> + Clone,
+          EVENT: Clone
+{
+    replica_id: Option<ReplicaId>,
+    entries: HashMap<K, MapEntry<C>>,
+    _1: PhantomData<STATE>,
+    _2: PhantomData<CMD>,
+    _3: PhantomData<EVENT>,
+}
+
+
+/** IMPLEMENTATIONS **/
+impl<C> Clone for MapEntry<C>
+    where C: Clone
+{
+    fn clone(&self) -> Self {
+        MapEntry {
+            crdt: self.crdt.clone(),
+            tags: self.tags.clone(),
+            removed: self.removed.clone(),
+        }
+    }
+}
+
+impl<K, C, STATE, CMD, EVENT> ORMap<K, C, STATE, CMD, EVENT>
+    where K: Clone + Eq + Hash,
+          C: CRDT<STATE, CMD, EVENT> + Clone,
+          EVENT: Clone
+{
+    // Returns the CRDT of the key, if the key is in the map.
+    pub fn get(&self, key: &K) -> Option<&C> {
+        self.entries
+            .get(key)
+            .filter(|entry| !entry.tags.is_empty())
+            .map(|entry| &entry.crdt)
+    }
+
+    // Returns the keys in the map.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| !entry.tags.is_empty())
+            .map(|(key, _)| key)
+    }
+
+    fn entry(&mut self, key: &K) -> &mut MapEntry<C> {
+        let replica_id = self.replica_id;
+        self.entries
+            .entry(key.clone())
+            .or_insert_with(|| MapEntry {
+                crdt: C::default(replica_id),
+                tags: HashSet::new(),
+                removed: None,
+            })
+    }
+}
+
+impl<K, C, STATE, CMD, EVENT> Clone for ORMap<K, C, STATE, CMD, EVENT>
+    where K: Clone + Eq + Hash,
+          C: CRDT<STATE, CMD, EVENT> + Clone,
+          EVENT: Clone
+{
+    fn clone(&self) -> Self {
+        ORMap {
+            replica_id: self.replica_id,
+            entries: self.entries.clone(),
+            _1: PhantomData,
+            _2: PhantomData,
+            _3: PhantomData,
+        }
+    }
+}
+
+impl<K, C, STATE, CMD, EVENT> CRDT<HashMap<K, STATE>, MapCommand<K, CMD>, MapOperation<K, EVENT>> for ORMap<K, C, STATE, CMD, EVENT>
+    where K: Clone + Eq + Hash,
+          C: CRDT<STATE, CMD, EVENT> + Clone,
+          EVENT: Clone
+{
+    fn default(replica_id: Option<ReplicaId>) -> Self {
+        ORMap {
+            replica_id,
+            entries: HashMap::new(),
+            _1: PhantomData,
+            _2: PhantomData,
+            _3: PhantomData,
+        }
+    }
+
+    fn query(&self) -> HashMap<K, STATE> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| !entry.tags.is_empty())
+            .map(|(key, entry)| (key.clone(), entry.crdt.query()))
+            .collect()
+    }
+
+    fn prepare(&self, command: &MapCommand<K, CMD>) -> Result<MapOperation<K, EVENT>, CausalError> {
+        match command {
+            Update(key, command) => {
+                let event = match self.entries.get(key) {
+                    Some(entry) => entry.crdt.prepare(command)?,
+                    None => C::default(self.replica_id).prepare(command)?,
+                };
+
+                Ok(Updated(key.clone(), event))
+            }
+            Remove(key) => {
+                let tags = self.entries
+                    .get(key)
+                    .map(|entry| entry.tags.clone())
+                    .unwrap_or_default();
+
+                Ok(Removed(key.clone(), tags))
+            }
+        }
+    }
+
+    fn effect(&mut self, event: &Event<MapOperation<K, EVENT>>) -> Result<(), CausalError> {
+        match &event.data {
+            Updated(key, data) => {
+                // We don't leave behind the entry created for the update if the update fails.
+                let created = !self.entries.contains_key(key);
+                let entry = self.entry(key);
+                let observed_removals = entry.removed
+                    .as_ref()
+                    .is_none_or(|removed| is_before_or_equal(removed, &event.version));
+                if observed_removals {
+                    if let Err(error) = entry.crdt.effect(&event.nested(data.clone())) {
+                        if created {
+                            self.entries.remove(key);
+                        }
+                        return Err(error);
+                    }
+                }
+                self.entry(key).tags.insert(event.version.clone());
+            }
+            Removed(key, tags) => {
+                // A removal which didn't observe the key leaves it untouched.
+                let replica_id = self.replica_id;
+                if let Some(entry) = self.entries.get_mut(key).filter(|_| !tags.is_empty()) {
+                    entry.tags.retain(|tag| !is_observed(tag, tags));
+                    entry.crdt = C::default(replica_id);
+                    let removed = entry.removed.get_or_insert_with(VectorClock::init);
+                    removed.merge(event.origin, &event.version);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stable(&mut self, frontier: &VTime) {
+        // Once the removals of a key are stable every later update has observed them, thus they are forgotten and the
+        // emptied entries are purged.
+        self.entries.retain(|_, entry| {
+            if entry.removed.as_ref().is_some_and(|removed| is_before_or_equal(removed, frontier)) {
+                entry.removed = None;
+            }
+            !entry.tags.is_empty() || entry.removed.is_some()
+        });
+        for entry in self.entries.values_mut() {
+            entry.tags = entry.tags
+                .iter()
+                .map(|tag| compact(tag, frontier))
+                .collect();
+            entry.crdt.stable(frontier);
+        }
+    }
+}
+
+
+/** UTILS **/
+// Whether the version has been observed by the other version.
+fn is_before_or_equal(version: &VTime, other_version: &VTime) -> bool {
+    let comparison = version.compare(other_version);
+    comparison == ClockComparison::Less || comparison == ClockComparison::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{CRDT, ReplicaState};
+    use crate::causal_counter::{PNCounter, PNCounterCommand, PNCounterOperation};
+    use crate::causal_core::CausalError;
+    use crate::causal_or_map::{MapCommand, MapOperation, ORMap};
+    use crate::causal_rga::{RGA, RGACommand, RGAOperation};
+    use crate::causal_utils::InMemory;
+
+    type CounterMap = ORMap<String, PNCounter, i64, PNCounterCommand, PNCounterOperation>;
+    type CounterMapState = ReplicaState<CounterMap, HashMap<String, i64>, MapCommand<String, PNCounterCommand>, MapOperation<String, PNCounterOperation>>;
+    type CounterMapStore = InMemory<CounterMap, HashMap<String, i64>, MapCommand<String, PNCounterCommand>, MapOperation<String, PNCounterOperation>>;
+
+    type TextMap = ORMap<&'static str, RGA<char>, Vec<char>, RGACommand<char>, RGAOperation<char>>;
+
+    fn replica(id: isize) -> (CounterMapState, CounterMapStore) {
+        (ReplicaState::create(id, ORMap::default(Some(id))), InMemory::create())
+    }
+
+    fn increment(key: &str, value: u64) -> MapCommand<String, PNCounterCommand> {
+        MapCommand::Update(String::from(key), PNCounterCommand::Increment(value))
+    }
+
+    #[test]
+    fn test_updates_are_routed_to_the_key() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+
+        state_0.process_command(&increment("likes", 2), &mut store_0).unwrap();
+        state_1.process_command(&increment("likes", 3), &mut store_1).unwrap();
+        state_1.process_command(&increment("views", 1), &mut store_1).unwrap();
+        state_0.process_replicated(1, 2, store_1.events.clone(), &mut store_0).unwrap();
+        state_1.process_replicated(0, 1, store_0.events[..1].to_vec(), &mut store_1).unwrap();
+
+        let expected = HashMap::from([(String::from("likes"), 5), (String::from("views"), 1)]);
+        assert_eq!(state_0.process_query(), expected);
+        assert_eq!(state_1.process_query(), expected);
+    }
+
+    #[test]
+    fn test_concurrent_update_keeps_the_removed_key() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+
+        state_0.process_command(&increment("likes", 1), &mut store_0).unwrap();
+        state_1.process_replicated(0, 1, store_0.events.clone(), &mut store_1).unwrap();
+
+        // Replica 0 removes the key while replica 1 concurrently updates it.
+        state_0.process_command(&MapCommand::Remove(String::from("likes")), &mut store_0).unwrap();
+        assert!(state_0.process_query().is_empty());
+
+        state_1.process_command(&increment("likes", 1), &mut store_1).unwrap();
+        state_0.process_replicated(1, 2, store_1.events[1..].to_vec(), &mut store_0).unwrap();
+        state_1.process_replicated(0, 2, store_0.events[1..2].to_vec(), &mut store_1).unwrap();
+
+        let expected = HashMap::from([(String::from("likes"), 0)]);
+        assert_eq!(state_0.process_query(), expected);
+        assert_eq!(state_1.process_query(), expected);
+    }
+
+    #[test]
+    fn test_readded_key_starts_from_the_default() {
+        let (mut state, mut store) = replica(0);
+        state.process_command(&increment("likes", 5), &mut store).unwrap();
+        state.process_command(&MapCommand::Remove(String::from("likes")), &mut store).unwrap();
+        state.process_command(&increment("likes", 1), &mut store).unwrap();
+
+        assert_eq!(state.process_query(), HashMap::from([(String::from("likes"), 1)]));
+    }
+
+    #[test]
+    fn test_stable_removal_purges_the_entry() {
+        let (mut state, mut store) = replica(0);
+        state.process_command(&increment("likes", 5), &mut store).unwrap();
+        state.process_command(&MapCommand::Remove(String::from("likes")), &mut store).unwrap();
+        assert_eq!(state.crdt.entries.len(), 1);
+
+        let frontier = state.version.clone();
+        state.crdt.stable(&frontier);
+
+        assert!(state.crdt.entries.is_empty());
+    }
+
+    #[test]
+    fn test_removal_after_compaction() {
+        let (mut state, mut store) = replica(0);
+        state.process_command(&increment("likes", 1), &mut store).unwrap();
+        state.process_command(&increment("likes", 1), &mut store).unwrap();
+
+        let frontier = state.version.clone();
+        state.crdt.stable(&frontier);
+        state.process_command(&MapCommand::Remove(String::from("likes")), &mut store).unwrap();
+
+        assert!(state.process_query().is_empty());
+        assert!(state.crdt.get(&String::from("likes")).is_none());
+    }
+
+    #[test]
+    fn test_nested_errors_are_returned() {
+        let mut store = InMemory::create();
+        let mut state = ReplicaState::create(0, TextMap::default(Some(0)));
+
+        state.process_command(&MapCommand::Update("title", RGACommand::Insert(0, 'a')), &mut store).unwrap();
+        let result = state.process_command(&MapCommand::Update("title", RGACommand::Remove(4)), &mut store);
+
+        assert_eq!(result.err(), Some(CausalError::IndexOutOfBounds(4)));
+        assert_eq!(state.process_query().get("title"), Some(&vec!['a']));
+    }
+
+    #[test]
+    fn test_failed_update_leaves_no_entry() {
+        let mut store = InMemory::create();
+        let mut state = ReplicaState::create(0, TextMap::default(Some(0)));
+        state.process_command(&MapCommand::Update("title", RGACommand::Insert(0, 'a')), &mut store).unwrap();
+        state.process_command(&MapCommand::Update("title", RGACommand::Remove(0)), &mut store).unwrap();
+
+        // A map which has not received the insertion doesn't know the removed element.
+        let mut map = TextMap::default(Some(1));
+        let result = map.effect(&store.events[1]);
+
+        assert_eq!(result.err(), Some(CausalError::UnknownElement));
+        assert!(map.entries.is_empty());
+    }
+}
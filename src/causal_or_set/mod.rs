@@ -2,13 +2,14 @@ use std::collections::HashSet;
 use std::fmt::{Display, Formatter};
 use std::hash::Hash;
 
-use crate::{CRDT, Event, EventStore, ReplicaId, ReplicaState};
-use crate::causal_core::{SeqNr, VTime};
+use crate::{CRDT, Event, ReplicaId};
+use crate::causal_core::{CausalError, VTime};
 use crate::causal_or_set::SetCommand::{Add, Remove};
 use crate::causal_or_set::SetOperation::{Added, Removed};
-use crate::causal_utils::InMemory;
+use crate::causal_time::ClockComparison::{Equal, Less};
 
 #[allow(dead_code)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SetCommand<T>
     where T: Clone + Eq + PartialEq + Hash + Display
 {
@@ -17,13 +18,16 @@ pub enum SetCommand<T>
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SetOperation<T>
     where T: Clone + Eq + PartialEq + Hash + Display
 {
     Added(T),
-    Removed(HashSet<VTime>),
+    Removed(T, HashSet<VTime>),
 }
 
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BinarySet<T>(HashSet<(T, VTime)>) where T: Clone + Eq + PartialEq + Hash + Display;
 
 impl<T> Display for BinarySet<T>
@@ -32,19 +36,20 @@ impl<T> Display for BinarySet<T>
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let mut output = String::from("");
 
-        output.push_str("{");
+        output.push('{');
         for value in &self.0 {
             output.push_str(&format!("{},", value.0));
         }
         if !self.0.is_empty() {
             output.pop();
         }
-        output.push_str("}");
+        output.push('}');
 
         write!(f, "{}", output)
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ORSet<T>
     where T: Clone + Eq + PartialEq + Hash + Display
 {
@@ -77,11 +82,11 @@ impl<T> CRDT<BinarySet<T>, SetCommand<T>, SetOperation<T>> for ORSet<T>
             .collect())
     }
 
-    fn prepare(&self, command: &SetCommand<T>) -> SetOperation<T> {
-        match command {
+    fn prepare(&self, command: &SetCommand<T>) -> Result<SetOperation<T>, CausalError> {
+        Ok(match command {
             Add(value) => Added(value.clone()),
             Remove(value_to_remove) => {
-                Removed(self.elements.0
+                Removed(value_to_remove.clone(), self.elements.0
                     .iter()
                     .filter(|(value, _)| value == value_to_remove)
                     .map(|(_, version)| version)
@@ -89,49 +94,95 @@ impl<T> CRDT<BinarySet<T>, SetCommand<T>, SetOperation<T>> for ORSet<T>
                     .collect()
                 )
             }
-        }
+        })
     }
 
-    fn effect(&mut self, event: &Event<SetOperation<T>>) {
+    fn effect(&mut self, event: &Event<SetOperation<T>>) -> Result<(), CausalError> {
         match &event.data {
             Added(value) => {
                 self.elements.0.insert((value.clone(), event.version.clone()));
             }
-            Removed(versions) => {
+            Removed(value_to_remove, versions) => {
                 self.elements = BinarySet(self.elements.0.iter()
-                    .filter(|(_, version)| { !versions.contains(&version) })
+                    .filter(|(value, version)| value != value_to_remove || !is_observed(version, versions))
                     .cloned()
                     .collect());
             }
         }
+
+        Ok(())
+    }
+
+    fn stable(&mut self, frontier: &VTime) {
+        self.elements = BinarySet(self.elements.0.iter()
+            .map(|(value, version)| (value.clone(), compact(version, frontier)))
+            .collect());
     }
 }
 
-impl<T> EventStore<ORSet<T>, BinarySet<T>, SetCommand<T>, SetOperation<T>> for InMemory<ORSet<T>, BinarySet<T>, SetCommand<T>, SetOperation<T>>
-    where T: Clone + Eq + PartialEq + Hash + Display
-{
-    fn save_snapshot(&mut self, state: &ReplicaState<ORSet<T>, BinarySet<T>, SetCommand<T>, SetOperation<T>>) {
-        self.last_snapshot = Some(state.clone())
+
+/** UTILS **/
+// The tag of a stable addition is no longer needed to tell apart concurrent additions, thus it is replaced with an empty
+// version, which merges multiple additions of the same value into a single element.
+pub(crate) fn compact(tag: &VTime, frontier: &VTime) -> VTime {
+    let comparison = tag.compare(frontier);
+    if comparison == Less || comparison == Equal {
+        VTime::init()
+    } else {
+        tag.clone()
     }
+}
 
-    fn load_snapshot(&self) -> Option<ReplicaState<ORSet<T>, BinarySet<T>, SetCommand<T>, SetOperation<T>>> {
-        match &self.last_snapshot {
-            Some(last_snapshot) => Some(last_snapshot.clone()),
-            _ => None
-        }
+// Whether a removal which observed the given tags removes the tag. A replica that observed the value must have observed
+// all its stable additions too, which might have already been compacted here into a single empty tag.
+pub(crate) fn is_observed(tag: &VTime, observed: &HashSet<VTime>) -> bool {
+    observed.contains(tag) || (*tag == VTime::init() && !observed.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CRDT, ReplicaState};
+    use crate::causal_or_set::{BinarySet, ORSet, SetCommand, SetOperation};
+    use crate::causal_utils::InMemory;
+
+    type SetState = ReplicaState<ORSet<i32>, BinarySet<i32>, SetCommand<i32>, SetOperation<i32>>;
+    type SetStore = InMemory<ORSet<i32>, BinarySet<i32>, SetCommand<i32>, SetOperation<i32>>;
+
+    fn replica(id: isize) -> (SetState, SetStore) {
+        (ReplicaState::create(id, ORSet::default(Some(id))), InMemory::create())
     }
 
-    fn save_events(&mut self, events: Vec<Event<SetOperation<T>>>) {
-        events.iter().for_each(|event| self.events.push(event.clone()));
+    #[test]
+    fn test_stable_additions_are_compacted() {
+        let (mut state, mut store) = replica(0);
+        state.process_command(&SetCommand::Add(1), &mut store).unwrap();
+        state.process_command(&SetCommand::Add(1), &mut store).unwrap();
+        state.process_command(&SetCommand::Add(2), &mut store).unwrap();
+
+        assert_eq!(state.process_query().0.len(), 3);
+
+        let frontier = state.version.clone();
+        state.crdt.stable(&frontier);
+
+        assert_eq!(state.process_query().to_string().len(), "{1,2}".len());
+        assert_eq!(state.process_query().0.len(), 2);
     }
 
-    fn load_events(&self, start_seq_nr: SeqNr) -> Vec<Event<SetOperation<T>>> {
-        self.events
-            .clone()
-            .into_iter()
-            .filter(|event| {
-                event.local_seq_nr >= start_seq_nr
-            })
-            .collect()
+    #[test]
+    fn test_remove_after_compaction() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+
+        state_0.process_command(&SetCommand::Add(1), &mut store_0).unwrap();
+        state_1.process_replicated(0, 1, store_0.events.clone(), &mut store_1).unwrap();
+
+        // Replica 0 compacts the addition while replica 1 removes the value using the original version.
+        let frontier = state_0.version.clone();
+        state_0.crdt.stable(&frontier);
+        state_1.process_command(&SetCommand::Remove(1), &mut store_1).unwrap();
+        state_0.process_replicated(1, 2, vec![store_1.events[1].clone()], &mut store_0).unwrap();
+
+        assert!(state_0.process_query().0.is_empty());
+        assert!(state_1.process_query().0.is_empty());
     }
-}
\ No newline at end of file
+}
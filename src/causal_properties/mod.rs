@@ -0,0 +1,329 @@
+use std::fmt::Debug;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::{CRDT, Event, ReplicaId};
+use crate::causal_core::VTime;
+use crate::causal_time::ClockComparison::Less;
+use crate::causal_time::VectorClock;
+
+/** DATA STRUCTURES **/
+#[derive(Clone, Debug)]
+pub struct PropertyConfig {
+    // The commands are generated from this seed, the events are reproducible as long as the CRDT itself doesn't use
+    // randomness when preparing them.
+    pub seed: u64,
+    pub replicas: usize,
+    pub commands: usize,
+    // The probability that a replica receives the events of another one before running its next command, which makes
+    // the events causally related instead of concurrent.
+    pub sync_rate: f64,
+    // The number of orders grows factorially with the concurrent events. When there are more than this many, we check
+    // this many orders picked at random instead of enumerating them.
+    pub max_orders: usize,
+}
+
+// A replica used to generate the events, with the indexes of the events it has delivered, in the order it did so.
+struct GeneratingReplica<C> {
+    crdt: C,
+    version: VTime,
+    delivered: Vec<usize>,
+}
+
+
+/** IMPLEMENTATIONS **/
+impl Default for PropertyConfig {
+    fn default() -> Self {
+        PropertyConfig {
+            seed: 0,
+            replicas: 3,
+            commands: 6,
+            sync_rate: 0.3,
+            max_orders: 5000,
+        }
+    }
+}
+
+// Runs random commands on several replicas, which randomly receive each other's events in between. The generator is
+// given the current state of the replica running the command. Commands refused by the CRDT are skipped.
+pub fn generate_events<C, STATE, CMD, EVENT>(
+    config: &PropertyConfig,
+    mut generator: impl FnMut(&mut StdRng, ReplicaId, &STATE) -> CMD,
+) -> Vec<Event<EVENT>>
+    where C: CRDT<STATE, CMD, EVENT>,
+          EVENT: Clone
+{
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut replicas = (0..config.replicas)
+        .map(|replica_id| GeneratingReplica {
+            crdt: C::default(Some(replica_id as ReplicaId)),
+            version: VectorClock::init(),
+            delivered: vec![],
+        })
+        .collect::<Vec<GeneratingReplica<C>>>();
+    let mut events: Vec<Event<EVENT>> = vec![];
+
+    while events.len() < config.commands {
+        let replica_id = rng.gen_range(0..config.replicas);
+
+        if config.replicas > 1 && rng.gen_bool(config.sync_rate) {
+            // The events are received in the order the other replica delivered them, which is a causal one.
+            let other_id = (replica_id + rng.gen_range(1..config.replicas)) % config.replicas;
+            let unseen = replicas[other_id].delivered
+                .iter()
+                .copied()
+                .filter(|index| !replicas[replica_id].delivered.contains(index))
+                .collect::<Vec<usize>>();
+
+            let replica = &mut replicas[replica_id];
+            for index in unseen {
+                replica.crdt
+                    .effect(&events[index])
+                    .unwrap_or_else(|error| panic!("Effect failed with seed {}: {}", config.seed, error));
+                replica.version.merge(replica_id as ReplicaId, &events[index].version);
+                replica.delivered.push(index);
+            }
+        }
+
+        let replica = &mut replicas[replica_id];
+        let command = generator(&mut rng, replica_id as ReplicaId, &replica.crdt.query());
+        let Ok(data) = replica.crdt.prepare(&command) else {
+            continue;
+        };
+
+        let mut version = replica.version.clone();
+        version.increment(replica_id as ReplicaId);
+        let seq_nr = version.get(&(replica_id as ReplicaId)) as u64;
+        let event = Event {
+            origin: replica_id as ReplicaId,
+            origin_seq_nr: seq_nr,
+            local_seq_nr: seq_nr,
+            version: version.clone(),
+            data,
+        };
+
+        replica.crdt
+            .effect(&event)
+            .unwrap_or_else(|error| panic!("Effect failed with seed {}: {}", config.seed, error));
+        replica.version = version;
+        replica.delivered.push(events.len());
+        events.push(event);
+    }
+
+    events
+}
+
+// Returns the orders in which the events can be delivered without breaking causality, as indexes of the events. If
+// there are more than the given number of orders, that many orders are sampled at random instead, so that the checked
+// orders are not all sharing the same prefix.
+pub fn causal_orders<EVENT>(events: &[Event<EVENT>], max_orders: usize, rng: &mut impl Rng) -> Vec<Vec<usize>>
+    where EVENT: Clone
+{
+    // We keep for every event the events that must be delivered before it.
+    let dependencies = events
+        .iter()
+        .map(|event| {
+            (0..events.len())
+                .filter(|index| events[*index].version.compare(&event.version) == Less)
+                .collect::<Vec<usize>>()
+        })
+        .collect::<Vec<Vec<usize>>>();
+
+    let mut orders = vec![];
+    let mut order = vec![];
+    let mut delivered = vec![false; events.len()];
+    extend_orders(&dependencies, &mut order, &mut delivered, &mut orders, max_orders + 1);
+
+    if orders.len() > max_orders {
+        return (0..max_orders)
+            .map(|_| random_order(&dependencies, rng))
+            .collect();
+    }
+
+    orders
+}
+
+// Delivers the events of every causal order to a new replica, and checks that all of them reach the same state.
+// Panics with the seed of the config otherwise, returns the number of orders checked.
+pub fn check_convergence<C, STATE, CMD, EVENT>(
+    config: &PropertyConfig,
+    generator: impl FnMut(&mut StdRng, ReplicaId, &STATE) -> CMD,
+) -> usize
+    where C: CRDT<STATE, CMD, EVENT>,
+          STATE: PartialEq + Debug,
+          EVENT: Clone
+{
+    let events = generate_events::<C, STATE, CMD, EVENT>(config, generator);
+    let orders = causal_orders(&events, config.max_orders, &mut StdRng::seed_from_u64(config.seed));
+    // The replica receiving the events is not one of the replicas which generated them.
+    let observer_id = config.replicas as ReplicaId;
+
+    let mut expected: Option<(Vec<usize>, STATE)> = None;
+    for order in &orders {
+        let mut crdt = C::default(Some(observer_id));
+        for index in order {
+            crdt
+                .effect(&events[*index])
+                .unwrap_or_else(|error| panic!("Effect failed in order {:?} with seed {}: {}", order, config.seed, error));
+        }
+
+        let state = crdt.query();
+        match &expected {
+            None => expected = Some((order.clone(), state)),
+            Some((expected_order, expected_state)) => assert!(
+                state == *expected_state,
+                "Orders {:?} and {:?} diverged with seed {}: {:?} != {:?}",
+                expected_order, order, config.seed, expected_state, state,
+            ),
+        }
+    }
+
+    orders.len()
+}
+
+
+/** UTILS **/
+fn extend_orders(
+    dependencies: &[Vec<usize>],
+    order: &mut Vec<usize>,
+    delivered: &mut [bool],
+    orders: &mut Vec<Vec<usize>>,
+    max_orders: usize,
+) {
+    if orders.len() >= max_orders {
+        return;
+    }
+    if order.len() == dependencies.len() {
+        orders.push(order.clone());
+        return;
+    }
+
+    for index in 0..dependencies.len() {
+        if !delivered[index] && dependencies[index].iter().all(|dependency| delivered[*dependency]) {
+            delivered[index] = true;
+            order.push(index);
+            extend_orders(dependencies, order, delivered, orders, max_orders);
+            order.pop();
+            delivered[index] = false;
+        }
+    }
+}
+
+// Builds a causal order by delivering, at every step, one of the events whose dependencies are delivered at random.
+fn random_order(dependencies: &[Vec<usize>], rng: &mut impl Rng) -> Vec<usize> {
+    let mut order = vec![];
+    let mut delivered = vec![false; dependencies.len()];
+
+    while order.len() < dependencies.len() {
+        let ready = (0..dependencies.len())
+            .filter(|index| !delivered[*index] && dependencies[*index].iter().all(|dependency| delivered[*dependency]))
+            .collect::<Vec<usize>>();
+        let index = ready[rng.gen_range(0..ready.len())];
+        delivered[index] = true;
+        order.push(index);
+    }
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::Rng;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::causal_lseq::{LSeq, LSeqCommand, LSeqOperation};
+    use crate::causal_or_set::{BinarySet, ORSet, SetCommand, SetOperation};
+    use crate::causal_properties::{causal_orders, check_convergence, generate_events, PropertyConfig};
+    use crate::causal_rga::{RGA, RGACommand, RGAOperation};
+    use crate::causal_time::ClockComparison::Less;
+
+    fn config(seed: u64) -> PropertyConfig {
+        PropertyConfig {
+            seed,
+            ..PropertyConfig::default()
+        }
+    }
+
+    fn random_char(rng: &mut impl Rng) -> char {
+        char::from(rng.gen_range(b'a'..=b'z'))
+    }
+
+    #[test]
+    fn test_causal_orders() {
+        let config = PropertyConfig { replicas: 1, ..config(0) };
+        let events = generate_events::<RGA<char>, _, _, RGAOperation<char>>(&config, |rng, _, text: &Vec<char>| {
+            RGACommand::Insert(rng.gen_range(0..=text.len()), random_char(rng))
+        });
+
+        // The events of a single replica can only be delivered in the order they were created.
+        assert_eq!(causal_orders(&events, 100, &mut StdRng::seed_from_u64(0)), vec![(0..6).collect::<Vec<usize>>()]);
+    }
+
+    #[test]
+    fn test_causal_orders_are_sampled_over_the_limit() {
+        let config = PropertyConfig { sync_rate: 0.0, ..config(0) };
+        let events = generate_events::<RGA<char>, _, _, RGAOperation<char>>(&config, |rng, _, text: &Vec<char>| {
+            RGACommand::Insert(rng.gen_range(0..=text.len()), random_char(rng))
+        });
+        let orders = causal_orders(&events, 10, &mut StdRng::seed_from_u64(0));
+
+        assert_eq!(orders.len(), 10);
+        for order in &orders {
+            for (position, index) in order.iter().enumerate() {
+                let causal = order[position..].iter().all(|other| events[*other].version.compare(&events[*index].version) != Less);
+                assert!(causal, "The order {:?} is not causal", order);
+            }
+        }
+
+        // The enumeration would have returned orders which all start with the same events.
+        let starts = orders.iter().map(|order| order[0]).collect::<HashSet<usize>>();
+        assert!(starts.len() > 1);
+    }
+
+    #[test]
+    fn test_or_set_converges() {
+        for seed in 0..20 {
+            check_convergence::<ORSet<u8>, BinarySet<u8>, _, SetOperation<u8>>(&config(seed), |rng, _, _| {
+                match rng.gen_bool(0.6) {
+                    true => SetCommand::Add(rng.gen_range(0..4)),
+                    false => SetCommand::Remove(rng.gen_range(0..4)),
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn test_rga_converges() {
+        let orders = (0..20)
+            .map(|seed| {
+                check_convergence::<RGA<char>, Vec<char>, _, RGAOperation<char>>(&config(seed), |rng, _, text| {
+                    if !text.is_empty() && rng.gen_bool(0.3) {
+                        RGACommand::Remove(rng.gen_range(0..text.len()))
+                    } else {
+                        RGACommand::Insert(rng.gen_range(0..=text.len()), random_char(rng))
+                    }
+                })
+            })
+            .sum::<usize>();
+
+        // The replicas run most of their commands concurrently, thus there are many more orders than runs.
+        assert!(orders > 20 * 10, "Only {} orders checked", orders);
+    }
+
+    #[test]
+    fn test_lseq_converges() {
+        for seed in 0..20 {
+            check_convergence::<LSeq<char>, Vec<char>, _, LSeqOperation<char>>(&config(seed), |rng, replica_id, text| {
+                if !text.is_empty() && rng.gen_bool(0.3) {
+                    LSeqCommand::Remove(rng.gen_range(0..text.len()))
+                } else {
+                    LSeqCommand::Insert(rng.gen_range(0..=text.len()), replica_id, random_char(rng))
+                }
+            });
+        }
+    }
+}
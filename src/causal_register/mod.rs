@@ -0,0 +1,260 @@
+use std::cmp;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{CRDT, Event, ReplicaId};
+use crate::causal_core::{CausalError, VTime};
+use crate::causal_time::ClockComparison::Less;
+
+/** TYPES **/
+// Milliseconds since the unix epoch.
+pub type Timestamp = u64;
+
+
+/** DATA STRUCTURES **/
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RegisterCommand<T>
+    where T: Clone
+{
+    Assign(T),
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LWWOperation<T>
+    where T: Clone
+{
+    Assigned(T, Timestamp),
+}
+
+// A register where the assignment with the greatest timestamp wins. Assignments with the same timestamp are ordered
+// by the id of the replica which made them, so that every replica picks the same winner.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LWWRegister<T>
+    where T: Clone
+{
+    value: Option<(T, Timestamp, ReplicaId)>,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MVOperation<T>
+    where T: Clone
+{
+    Assigned(T),
+}
+
+// A register which keeps all the values assigned concurrently, until a later assignment replaces all of them.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MVRegister<T>
+    where T: Clone
+{
+    values: Vec<(T, VTime)>,
+}
+
+
+/** IMPLEMENTATIONS **/
+impl<T> Clone for LWWRegister<T>
+    where T: Clone
+{
+    fn clone(&self) -> Self {
+        LWWRegister {
+            value: self.value.clone(),
+        }
+    }
+}
+
+impl<T> CRDT<Option<T>, RegisterCommand<T>, LWWOperation<T>> for LWWRegister<T>
+    where T: Clone
+{
+    fn default(_: Option<ReplicaId>) -> Self {
+        LWWRegister {
+            value: None,
+        }
+    }
+
+    fn query(&self) -> Option<T> {
+        self.value
+            .as_ref()
+            .map(|(value, _, _)| value.clone())
+    }
+
+    fn prepare(&self, command: &RegisterCommand<T>) -> Result<LWWOperation<T>, CausalError> {
+        match command {
+            RegisterCommand::Assign(value) => {
+                // A new assignment must win over the ones we have already seen, even if our clock is behind. Nothing
+                // can win over the greatest timestamp, thus the assignment is refused.
+                let timestamp = match &self.value {
+                    Some((_, current_timestamp, _)) => {
+                        let next_timestamp = current_timestamp.checked_add(1).ok_or(CausalError::Overflow)?;
+                        cmp::max(now(), next_timestamp)
+                    }
+                    None => now(),
+                };
+
+                Ok(LWWOperation::Assigned(value.clone(), timestamp))
+            }
+        }
+    }
+
+    fn effect(&mut self, event: &Event<LWWOperation<T>>) -> Result<(), CausalError> {
+        match &event.data {
+            LWWOperation::Assigned(value, timestamp) => {
+                let wins = match &self.value {
+                    Some((_, current_timestamp, current_origin)) => {
+                        (*timestamp, event.origin) > (*current_timestamp, *current_origin)
+                    }
+                    None => true,
+                };
+
+                if wins {
+                    self.value = Some((value.clone(), *timestamp, event.origin));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> Clone for MVRegister<T>
+    where T: Clone
+{
+    fn clone(&self) -> Self {
+        MVRegister {
+            values: self.values.to_vec(),
+        }
+    }
+}
+
+impl<T> CRDT<Vec<T>, RegisterCommand<T>, MVOperation<T>> for MVRegister<T>
+    where T: Clone
+{
+    fn default(_: Option<ReplicaId>) -> Self {
+        MVRegister {
+            values: vec![],
+        }
+    }
+
+    fn query(&self) -> Vec<T> {
+        self.values
+            .iter()
+            .map(|(value, _)| value.clone())
+            .collect()
+    }
+
+    fn prepare(&self, command: &RegisterCommand<T>) -> Result<MVOperation<T>, CausalError> {
+        match command {
+            RegisterCommand::Assign(value) => Ok(MVOperation::Assigned(value.clone())),
+        }
+    }
+
+    fn effect(&mut self, event: &Event<MVOperation<T>>) -> Result<(), CausalError> {
+        match &event.data {
+            MVOperation::Assigned(value) => {
+                // The values that happened before the assignment are overwritten, the concurrent ones are kept.
+                self.values.retain(|(_, version)| version.compare(&event.version) != Less);
+                self.values.push((value.clone(), event.version.clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+/** UTILS **/
+fn now() -> Timestamp {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as Timestamp)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CRDT, Event, ReplicaState, VectorClock};
+    use crate::causal_core::CausalError;
+    use crate::causal_register::{LWWOperation, LWWRegister, MVOperation, MVRegister, RegisterCommand};
+    use crate::causal_utils::InMemory;
+
+    type MVState = ReplicaState<MVRegister<char>, Vec<char>, RegisterCommand<char>, MVOperation<char>>;
+    type MVStore = InMemory<MVRegister<char>, Vec<char>, RegisterCommand<char>, MVOperation<char>>;
+
+    fn mv_replica(id: isize) -> (MVState, MVStore) {
+        (ReplicaState::create(id, MVRegister::default(Some(id))), InMemory::create())
+    }
+
+    fn assigned(origin: isize, value: char, timestamp: u64) -> Event<LWWOperation<char>> {
+        let mut version = VectorClock::init();
+        version.increment(origin);
+
+        Event {
+            origin,
+            origin_seq_nr: 1,
+            local_seq_nr: 1,
+            version,
+            data: LWWOperation::Assigned(value, timestamp),
+        }
+    }
+
+    #[test]
+    fn test_mv_register_keeps_concurrent_values() {
+        let (mut state_0, mut store_0) = mv_replica(0);
+        let (mut state_1, mut store_1) = mv_replica(1);
+
+        state_0.process_command(&RegisterCommand::Assign('a'), &mut store_0).unwrap();
+        state_1.process_command(&RegisterCommand::Assign('b'), &mut store_1).unwrap();
+        state_0.process_replicated(1, 1, store_1.events.clone(), &mut store_0).unwrap();
+        state_1.process_replicated(0, 1, store_0.events[..1].to_vec(), &mut store_1).unwrap();
+
+        let mut values_0 = state_0.process_query();
+        let mut values_1 = state_1.process_query();
+        values_0.sort();
+        values_1.sort();
+        assert_eq!(values_0, vec!['a', 'b']);
+        assert_eq!(values_0, values_1);
+
+        // An assignment which observed both values replaces them.
+        state_1.process_command(&RegisterCommand::Assign('c'), &mut store_1).unwrap();
+        state_0.process_replicated(1, 3, store_1.events[2..].to_vec(), &mut store_0).unwrap();
+
+        assert_eq!(state_0.process_query(), vec!['c']);
+        assert_eq!(state_1.process_query(), vec!['c']);
+    }
+
+    #[test]
+    fn test_lww_register_ties_are_broken_by_replica() {
+        let mut register_0 = LWWRegister::default(Some(0));
+        let mut register_1 = LWWRegister::default(Some(1));
+
+        register_0.effect(&assigned(0, 'a', 10)).unwrap();
+        register_0.effect(&assigned(1, 'b', 10)).unwrap();
+        register_1.effect(&assigned(1, 'b', 10)).unwrap();
+        register_1.effect(&assigned(0, 'a', 10)).unwrap();
+
+        assert_eq!(register_0.query(), Some('b'));
+        assert_eq!(register_1.query(), Some('b'));
+
+        register_0.effect(&assigned(0, 'c', 11)).unwrap();
+        register_0.effect(&assigned(1, 'd', 9)).unwrap();
+        assert_eq!(register_0.query(), Some('c'));
+    }
+
+    #[test]
+    fn test_lww_register_local_assignment_wins_over_future_timestamps() {
+        let mut store = InMemory::create();
+        let mut state = ReplicaState::create(0, LWWRegister::default(Some(0)));
+        // An assignment from a replica whose clock is far ahead.
+        state.crdt.effect(&assigned(1, 'a', u64::MAX - 1)).unwrap();
+
+        state.process_command(&RegisterCommand::Assign('b'), &mut store).unwrap();
+
+        assert_eq!(state.process_query(), Some('b'));
+
+        state.crdt.effect(&assigned(1, 'c', u64::MAX)).unwrap();
+        let result = state.process_command(&RegisterCommand::Assign('d'), &mut store);
+
+        assert_eq!(result.err(), Some(CausalError::Overflow));
+        assert_eq!(state.process_query(), Some('c'));
+    }
+}
@@ -1,41 +1,68 @@
 use std::cmp;
 use std::cmp::Ordering;
 use std::cmp::Ordering::{Greater, Less};
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 
-use crate::{CRDT, Event, EventStore, InMemory, InputReceiver, ReplicaId, ReplicaState};
-use crate::causal_core::SeqNr;
+use crate::{CRDT, Event, InputReceiver, ReplicaId};
+use crate::causal_core::{CausalError, SeqNr, VTime};
+use crate::causal_time::ClockComparison;
 use crate::causal_rga::RGACommand::{Insert, Remove};
 use crate::causal_rga::RGAOperation::{Inserted, Removed};
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RGAPtr {
     seq_nr: SeqNr,
     replica_id: ReplicaId,
 }
 
 impl RGAPtr {
-    fn new(replica_id: ReplicaId) -> RGAPtr {
+    pub(crate) fn new(replica_id: ReplicaId) -> RGAPtr {
         RGAPtr {
             seq_nr: 0,
             replica_id,
         }
     }
 
-    fn next_seq_nr(&self) -> Self {
+    pub(crate) fn next_seq_nr(&self) -> Self {
         let mut clone = self.clone();
         clone.seq_nr += 1;
         clone
     }
 
-    fn update_highest_observed_seq_nr(&mut self, other_v_ptr: &RGAPtr) {
+    pub(crate) fn update_highest_observed_seq_nr(&mut self, other_v_ptr: &RGAPtr) {
         self.seq_nr = cmp::max(self.seq_nr, other_v_ptr.seq_nr);
     }
+
+    // Returns the pointer generated by the same replica the given number of insertions later.
+    pub(crate) fn offset(&self, offset: usize) -> Self {
+        RGAPtr {
+            seq_nr: self.seq_nr + offset as SeqNr,
+            replica_id: self.replica_id,
+        }
+    }
+
+    // Returns the replica which generated the pointer and its sequence number, which order the pointers of every
+    // replica by the time they were generated.
+    pub(crate) fn run_key(&self) -> (ReplicaId, SeqNr) {
+        (self.replica_id, self.seq_nr)
+    }
+
+    // Returns how many insertions of the same replica separate the given pointer from this one, if it comes after.
+    pub(crate) fn distance_from(&self, start_v_ptr: &RGAPtr) -> Option<usize> {
+        if self.replica_id == start_v_ptr.replica_id && self.seq_nr >= start_v_ptr.seq_nr {
+            Some((self.seq_nr - start_v_ptr.seq_nr) as usize)
+        } else {
+            None
+        }
+    }
 }
 
 impl Clone for RGAPtr {
     fn clone(&self) -> Self {
         RGAPtr {
-            seq_nr: self.seq_nr.clone(),
-            replica_id: self.replica_id.clone(),
+            seq_nr: self.seq_nr,
+            replica_id: self.replica_id,
         }
     }
 }
@@ -46,6 +73,15 @@ impl PartialEq<Self> for RGAPtr {
     }
 }
 
+impl Eq for RGAPtr {}
+
+impl Hash for RGAPtr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.seq_nr.hash(state);
+        self.replica_id.hash(state);
+    }
+}
+
 impl PartialOrd<Self> for RGAPtr {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         if self.seq_nr < other.seq_nr {
@@ -64,6 +100,102 @@ impl PartialOrd<Self> for RGAPtr {
     }
 }
 
+// A run of insertions of the same replica, in which the seq nr of the pointer and the seq nr of the event in the
+// version of the replica grow together, as they do while the replica is typing.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct InsertionRun {
+    seq_nr: SeqNr,
+    event_seq_nr: SeqNr,
+    length: SeqNr,
+}
+
+// Tells whether the insertion of an element is known to be delivered by every replica. The pointers of a replica grow
+// with its events, thus the stable insertions of every replica are the ones up to a pointer seq nr. Only the runs of
+// the insertions which are not yet stable are kept, to find that seq nr once the frontier advances.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct InsertionStability {
+    stable_seq_nrs: HashMap<ReplicaId, SeqNr>,
+    unstable: HashMap<ReplicaId, Vec<InsertionRun>>,
+}
+
+impl Clone for InsertionRun {
+    fn clone(&self) -> Self {
+        InsertionRun {
+            seq_nr: self.seq_nr,
+            event_seq_nr: self.event_seq_nr,
+            length: self.length,
+        }
+    }
+}
+
+impl InsertionStability {
+    pub(crate) fn new() -> InsertionStability {
+        InsertionStability {
+            stable_seq_nrs: HashMap::new(),
+            unstable: HashMap::new(),
+        }
+    }
+
+    // Records the insertion of the pointer by the event with the given version.
+    pub(crate) fn insert(&mut self, v_ptr: &RGAPtr, version: &VTime) {
+        let event_seq_nr = version.get(&v_ptr.replica_id) as SeqNr;
+        let runs = self.unstable.entry(v_ptr.replica_id).or_default();
+        match runs.last_mut() {
+            Some(run) if run.seq_nr + run.length == v_ptr.seq_nr && run.event_seq_nr + run.length == event_seq_nr => {
+                run.length += 1;
+            }
+            _ => runs.push(InsertionRun {
+                seq_nr: v_ptr.seq_nr,
+                event_seq_nr,
+                length: 1,
+            }),
+        }
+    }
+
+    // Every origin entry of the frontier counts its events delivered by every replica.
+    pub(crate) fn advance(&mut self, frontier: &VTime) {
+        for (replica_id, runs) in self.unstable.iter_mut() {
+            let stable_event_seq_nr = frontier.get(replica_id) as SeqNr;
+            let stable_runs = runs
+                .iter()
+                .take_while(|run| run.event_seq_nr + run.length - 1 <= stable_event_seq_nr)
+                .count();
+            let mut stable_seq_nr = runs[..stable_runs].last().map(|run| run.seq_nr + run.length - 1);
+            runs.drain(..stable_runs);
+
+            // The runs are split when only their beginning is stable.
+            if let Some(run) = runs.first_mut().filter(|run| run.event_seq_nr <= stable_event_seq_nr) {
+                let stable_length = stable_event_seq_nr - run.event_seq_nr + 1;
+                stable_seq_nr = Some(run.seq_nr + stable_length - 1);
+                run.seq_nr += stable_length;
+                run.event_seq_nr += stable_length;
+                run.length -= stable_length;
+            }
+
+            if let Some(stable_seq_nr) = stable_seq_nr {
+                self.stable_seq_nrs.insert(*replica_id, stable_seq_nr);
+            }
+        }
+
+        self.unstable.retain(|_, runs| !runs.is_empty());
+    }
+
+    // The head of the sequence has the seq nr 0, which is always stable.
+    pub(crate) fn is_stable(&self, v_ptr: &RGAPtr) -> bool {
+        v_ptr.seq_nr <= self.stable_seq_nrs.get(&v_ptr.replica_id).copied().unwrap_or(0)
+    }
+}
+
+impl Clone for InsertionStability {
+    fn clone(&self) -> Self {
+        InsertionStability {
+            stable_seq_nrs: self.stable_seq_nrs.clone(),
+            unstable: self.unstable.clone(),
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RGACommand<T>
     where T: Clone
 {
@@ -72,6 +204,7 @@ pub enum RGACommand<T>
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RGAOperation<T>
     where T: Clone
 {
@@ -79,36 +212,42 @@ pub enum RGAOperation<T>
     Removed(RGAPtr),
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RGA<T>
     where T: Clone
 {
     sequencer: RGAPtr,
     elements: Vec<(RGAPtr, Option<T>)>,
+    // The insertions that are not yet known to be delivered by every replica.
+    unstable: InsertionStability,
+    // Removed elements together with the version of their removal.
+    tombstones: Vec<(RGAPtr, VTime)>,
 }
 
 impl<T> RGA<T>
     where T: Clone
 {
-    fn index_with_tombstones(&self, index: usize) -> usize {
-        let mut offset = 1;
-        let mut remaining = index;
-
-        while offset < self.elements.len() && remaining > 0 {
-            let element = &self.elements[offset];
-            if let Some(_) = element.1 {
-                remaining -= 1;
-            }
-            offset += 1;
-        }
+    // Returns all the elements in order, starting from the head and including the tombstones.
+    pub(crate) fn elements(&self) -> &[(RGAPtr, Option<T>)] {
+        &self.elements
+    }
 
-        offset
+    // Returns the position of the element visible at the given index, skipping the tombstones.
+    fn index_with_tombstones(&self, index: usize) -> Result<usize, CausalError> {
+        self.elements
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, value))| value.is_some())
+            .nth(index)
+            .map(|(offset, _)| offset)
+            .ok_or(CausalError::IndexOutOfBounds(index))
     }
 
-    fn index_of_v_ptr(&self, v_ptr: &RGAPtr) -> usize {
+    fn index_of_v_ptr(&self, v_ptr: &RGAPtr) -> Result<usize, CausalError> {
         self.elements
             .iter()
             .position(|(inner_v_ptr, _)| v_ptr == inner_v_ptr)
-            .expect("Couldn't find position of with the given virtual pointer.")
+            .ok_or(CausalError::UnknownElement)
     }
 
     fn shift(&self, offset: usize, v_ptr: &RGAPtr) -> usize {
@@ -127,7 +266,9 @@ impl<T> Clone for RGA<T>
     fn clone(&self) -> Self {
         RGA {
             sequencer: self.sequencer.clone(),
-            elements: self.elements.iter().cloned().collect(),
+            elements: self.elements.to_vec(),
+            unstable: self.unstable.clone(),
+            tombstones: self.tombstones.to_vec(),
         }
     }
 }
@@ -142,80 +283,98 @@ impl<T> CRDT<Vec<T>, RGACommand<T>, RGAOperation<T>> for RGA<T>
             sequencer: RGAPtr::new(replica_id),
             // The base pointer is the default (0,-1) which must be the same across all the replicas.
             elements: vec![(RGAPtr::new(-1), None)],
+            unstable: InsertionStability::new(),
+            tombstones: vec![],
         }
     }
 
     fn query(&self) -> Vec<T> {
         self.elements
             .iter()
-            .cloned()
-            .filter(|(_, value)| value.is_some())
-            .map(|(_, value)| value.unwrap())
+            .filter_map(|(_, value)| value.clone())
             .collect()
     }
 
-    fn prepare(&self, command: &RGACommand<T>) -> RGAOperation<T> {
+    fn prepare(&self, command: &RGACommand<T>) -> Result<RGAOperation<T>, CausalError> {
         match command {
             Insert(index, value) => {
-                let index = self.index_with_tombstones(*index);
-                let prev_v_ptr = self.elements[index - 1].0.clone();
+                // We insert after the element visible right before the index, or after the head.
+                let prev_index = match index {
+                    0 => 0,
+                    _ => self.index_with_tombstones(*index - 1).map_err(|_| CausalError::IndexOutOfBounds(*index))?,
+                };
+                let prev_v_ptr = self.elements[prev_index].0.clone();
                 let at_v_ptr = self.sequencer.next_seq_nr();
 
-                Inserted(prev_v_ptr, at_v_ptr, value.clone())
+                Ok(Inserted(prev_v_ptr, at_v_ptr, value.clone()))
             }
             Remove(index) => {
-                let index = self.index_with_tombstones(*index);
+                let index = self.index_with_tombstones(*index)?;
                 let at_vt_ptr = self.elements[index].0.clone();
 
-                Removed(at_vt_ptr)
+                Ok(Removed(at_vt_ptr))
             }
         }
     }
 
-    fn effect(&mut self, event: &Event<RGAOperation<T>>) {
+    fn effect(&mut self, event: &Event<RGAOperation<T>>) -> Result<(), CausalError> {
         match &event.data {
             Inserted(prev_v_ptr, at_v_ptr, value) => {
-                let predecessor_index = self.index_of_v_ptr(prev_v_ptr);
+                let predecessor_index = self.index_of_v_ptr(prev_v_ptr)?;
                 let insert_index = self.shift(predecessor_index + 1, at_v_ptr);
                 self.sequencer.update_highest_observed_seq_nr(at_v_ptr);
                 self.elements.insert(insert_index, (at_v_ptr.clone(), Some(value.clone())));
+                self.unstable.insert(at_v_ptr, &event.version);
             }
             Removed(at_v_ptr) => {
-                let index = self.index_of_v_ptr(at_v_ptr);
+                let index = self.index_of_v_ptr(at_v_ptr)?;
                 let mut element = self.elements[index].clone();
                 element.1 = None;
                 self.elements[index] = element;
+                self.tombstones.push((at_v_ptr.clone(), event.version.clone()));
             }
         }
-    }
-}
 
-impl<T> EventStore<RGA<T>, Vec<T>, RGACommand<T>, RGAOperation<T>> for InMemory<RGA<T>, Vec<T>, RGACommand<T>, RGAOperation<T>>
-    where T: Clone
-{
-    fn save_snapshot(&mut self, state: &ReplicaState<RGA<T>, Vec<T>, RGACommand<T>, RGAOperation<T>>) {
-        self.last_snapshot = Some(state.clone())
+        Ok(())
     }
 
-    fn load_snapshot(&self) -> Option<ReplicaState<RGA<T>, Vec<T>, RGACommand<T>, RGAOperation<T>>> {
-        match &self.last_snapshot {
-            Some(last_snapshot) => Some(last_snapshot.clone()),
-            _ => None
+    fn stable(&mut self, frontier: &VTime) {
+        let is_stable = |version: &VTime| {
+            let comparison = version.compare(frontier);
+            comparison == ClockComparison::Less || comparison == ClockComparison::Equal
+        };
+
+        self.unstable.advance(frontier);
+
+        // Once a removal is stable no replica will ever refer to the tombstone again, because new insertions always
+        // point to a visible predecessor. However, the tombstone can be purged only if the insertion of its successor
+        // is stable too, otherwise a future insertion would be ordered after the successor instead of before it.
+        let stable_tombstones = self.tombstones
+            .iter()
+            .filter(|(_, version)| is_stable(version))
+            .map(|(v_ptr, _)| v_ptr.clone())
+            .collect::<HashSet<RGAPtr>>();
+        if stable_tombstones.is_empty() {
+            return;
         }
-    }
 
-    fn save_events(&mut self, events: Vec<Event<RGAOperation<T>>>) {
-        events.iter().for_each(|event| self.events.push(event.clone()));
-    }
+        // We purge in a single pass from the end, so that the successor of each tombstone is already the final one.
+        let mut purged = HashSet::new();
+        let mut elements = Vec::with_capacity(self.elements.len());
+        let mut successor_stable = true;
+        for (v_ptr, value) in std::mem::take(&mut self.elements).into_iter().rev() {
+            if successor_stable && stable_tombstones.contains(&v_ptr) {
+                purged.insert(v_ptr);
+                continue;
+            }
 
-    fn load_events(&self, start_seq_nr: SeqNr) -> Vec<Event<RGAOperation<T>>> {
-        self.events
-            .clone()
-            .into_iter()
-            .filter(|event| {
-                event.local_seq_nr >= start_seq_nr
-            })
-            .collect()
+            successor_stable = self.unstable.is_stable(&v_ptr);
+            elements.push((v_ptr, value));
+        }
+        elements.reverse();
+        self.elements = elements;
+
+        self.tombstones.retain(|(v_ptr, _)| !purged.contains(v_ptr));
     }
 }
 
@@ -231,6 +390,12 @@ impl RGAReceiver {
     }
 }
 
+impl Default for RGAReceiver {
+    fn default() -> Self {
+        RGAReceiver::new()
+    }
+}
+
 impl InputReceiver for RGAReceiver {
     fn insert_at(&mut self, position: usize, character: char) {
         self.commands.push(Insert(position, character));
@@ -239,4 +404,119 @@ impl InputReceiver for RGAReceiver {
     fn remove_at(&mut self, position: usize) {
         self.commands.push(Remove(position))
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::{CRDT, ReplicaState, VectorClock};
+    use crate::causal_core::CausalError;
+    use crate::causal_rga::{RGA, RGACommand, RGAOperation};
+    use crate::causal_utils::InMemory;
+
+    type RGAState = ReplicaState<RGA<char>, Vec<char>, RGACommand<char>, RGAOperation<char>>;
+    type RGAStore = InMemory<RGA<char>, Vec<char>, RGACommand<char>, RGAOperation<char>>;
+
+    fn replica(id: isize) -> (RGAState, RGAStore) {
+        (ReplicaState::create(id, RGA::default(Some(id))), InMemory::create())
+    }
+
+    #[test]
+    fn test_stable_tombstones_are_purged() {
+        let (mut state, mut store) = replica(0);
+        state.process_command(&RGACommand::Insert(0, 'a'), &mut store).unwrap();
+        state.process_command(&RGACommand::Insert(1, 'b'), &mut store).unwrap();
+        state.process_command(&RGACommand::Remove(0), &mut store).unwrap();
+
+        assert_eq!(state.crdt.elements.len(), 3);
+
+        let frontier = state.version.clone();
+        state.crdt.stable(&frontier);
+
+        assert_eq!(state.crdt.elements.len(), 2);
+        assert!(state.crdt.tombstones.is_empty());
+        assert!(state.crdt.unstable.unstable.is_empty());
+        assert_eq!(state.process_query(), vec!['b']);
+    }
+
+    #[test]
+    fn test_insertions_are_tracked_in_runs() {
+        let (mut state, mut store) = replica(0);
+        for (index, value) in "abc".chars().enumerate() {
+            state.process_command(&RGACommand::Insert(index, value), &mut store).unwrap();
+        }
+        // The removal is an event without a pointer, thus the next insertion starts a new run.
+        state.process_command(&RGACommand::Remove(0), &mut store).unwrap();
+        state.process_command(&RGACommand::Insert(2, 'd'), &mut store).unwrap();
+
+        assert_eq!(state.crdt.unstable.unstable[&0].len(), 2);
+
+        let mut frontier = VectorClock::init();
+        frontier.increment(0);
+        frontier.increment(0);
+        state.crdt.stable(&frontier);
+        let stable = state.crdt.elements
+            .iter()
+            .map(|(v_ptr, _)| state.crdt.unstable.is_stable(v_ptr))
+            .collect::<Vec<bool>>();
+
+        assert_eq!(stable, vec![true, true, true, false, false]);
+
+        let frontier = state.version.clone();
+        state.crdt.stable(&frontier);
+
+        assert!(state.crdt.unstable.unstable.is_empty());
+        assert!(state.crdt.elements.iter().all(|(v_ptr, _)| state.crdt.unstable.is_stable(v_ptr)));
+    }
+
+    #[test]
+    fn test_tombstone_with_unstable_successor_is_kept() {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+        // A third replica which never replicates keeps the frontier under our control.
+        state_0.process_connect(2);
+
+        state_0.process_command(&RGACommand::Insert(0, 'a'), &mut store_0).unwrap();
+        state_1.process_replicated(0, 1, store_0.events.clone(), &mut store_1).unwrap();
+        // Replica 0 removes 'a' while replica 1 concurrently appends 'c' after it.
+        state_0.process_command(&RGACommand::Remove(0), &mut store_0).unwrap();
+        state_1.process_command(&RGACommand::Insert(1, 'c'), &mut store_1).unwrap();
+        state_0.process_replicated(1, 2, vec![store_1.events[1].clone()], &mut store_0).unwrap();
+
+        let mut frontier = VectorClock::init();
+        frontier.increment(0);
+        frontier.increment(0);
+        state_0.crdt.stable(&frontier);
+
+        assert_eq!(state_0.crdt.elements.len(), 3);
+
+        frontier.increment(1);
+        state_0.crdt.stable(&frontier);
+
+        assert_eq!(state_0.crdt.elements.len(), 2);
+        assert!(state_0.crdt.unstable.unstable.is_empty());
+        assert_eq!(state_0.process_query(), vec!['c']);
+    }
+
+    #[test]
+    fn test_invalid_index_is_rejected() {
+        let (mut state, mut store) = replica(0);
+        state.process_command(&RGACommand::Insert(0, 'a'), &mut store).unwrap();
+
+        assert_eq!(state.process_command(&RGACommand::Insert(2, 'b'), &mut store).err(), Some(CausalError::IndexOutOfBounds(2)));
+        assert_eq!(state.process_command(&RGACommand::Remove(1), &mut store).err(), Some(CausalError::IndexOutOfBounds(1)));
+        assert_eq!(state.seq_nr, 1);
+        assert_eq!(state.version.get(&0), 1);
+        assert_eq!(store.events.len(), 1);
+    }
+
+    #[test]
+    fn test_remove_skips_tombstones() {
+        let (mut state, mut store) = replica(0);
+        state.process_command(&RGACommand::Insert(0, 'a'), &mut store).unwrap();
+        state.process_command(&RGACommand::Insert(1, 'b'), &mut store).unwrap();
+        state.process_command(&RGACommand::Insert(2, 'c'), &mut store).unwrap();
+        state.process_command(&RGACommand::Remove(0), &mut store).unwrap();
+        state.process_command(&RGACommand::Remove(0), &mut store).unwrap();
+
+        assert_eq!(state.process_query(), vec!['c']);
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+
+use crate::{CRDT, Event, ReplicaId};
+use crate::causal_core::CausalError;
+use crate::causal_rga::{RGA, RGACommand, RGAOperation, RGAPtr};
+use crate::causal_rich_text::RichTextCommand::{AddMark, Insert, Remove, RemoveMark};
+use crate::causal_rich_text::RichTextOperation::{Marked, Text};
+
+/** DATA STRUCTURES **/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mark {
+    Bold,
+    Italic,
+    Link(String),
+}
+
+// Whether the text inserted right before or right after a span takes its mark too. Usually bold and italic expand to
+// the right, while links don't expand.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Expand {
+    Neither,
+    Left,
+    Right,
+    Both,
+}
+
+// A point between two characters: right before or right after one of them, or at the end of the text.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Anchor {
+    Before(RGAPtr),
+    After(RGAPtr),
+    End,
+}
+
+// A run of text whose characters have the same marks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub text: String,
+    pub marks: Vec<Mark>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RichTextCommand {
+    Insert(usize, char),
+    Remove(usize),
+    // Adds the mark to the characters from the first index up to the second one, excluded.
+    AddMark(usize, usize, Mark, Expand),
+    RemoveMark(usize, usize, Mark, Expand),
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RichTextOperation {
+    Text(RGAOperation<char>),
+    // The id of the operation, the span, the mark and whether it is added or removed.
+    Marked(RGAPtr, Anchor, Anchor, Mark, bool),
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct MarkOperation {
+    id: RGAPtr,
+    start: Anchor,
+    end: Anchor,
+    mark: Mark,
+    added: bool,
+}
+
+// A rich text in the style of Peritext. The characters are stored in an RGA, and the marks are attached to anchors
+// between the characters, so that a span keeps covering the same characters, and the ones inserted inside it, while
+// the text is edited concurrently. When several operations on the same kind of mark cover a character, the one with
+// the greatest id wins, and the ids are generated like the pointers of the RGA, thus a later operation always wins
+// over the ones it has observed. The removed characters are never purged, because the marks might be anchored to them.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RichText {
+    text: RGA<char>,
+    sequencer: RGAPtr,
+    marks: Vec<MarkOperation>,
+}
+
+
+/** IMPLEMENTATIONS **/
+impl Mark {
+    fn is_same_kind(&self, other: &Mark) -> bool {
+        matches!(
+            (self, other),
+            (Mark::Bold, Mark::Bold) | (Mark::Italic, Mark::Italic) | (Mark::Link(_), Mark::Link(_))
+        )
+    }
+}
+
+impl RichText {
+    // Returns the positions, including the tombstones, of the visible characters.
+    fn visible_positions(&self) -> Vec<usize> {
+        self.text
+            .elements()
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, value))| value.is_some())
+            .map(|(position, _)| position)
+            .collect()
+    }
+
+    fn anchors(&self, start: usize, end: usize, expand: Expand) -> Result<(Anchor, Anchor), CausalError> {
+        let positions = self.visible_positions();
+        if start >= end || end > positions.len() {
+            return Err(CausalError::IndexOutOfBounds(end));
+        }
+        let elements = self.text.elements();
+
+        let start_anchor = match expand {
+            // The head of the RGA is right before the first character.
+            Expand::Left | Expand::Both => {
+                let position = start.checked_sub(1).map_or(0, |index| positions[index]);
+                Anchor::After(elements[position].0.clone())
+            }
+            Expand::Neither | Expand::Right => Anchor::Before(elements[positions[start]].0.clone()),
+        };
+        let end_anchor = match expand {
+            Expand::Right | Expand::Both => positions
+                .get(end)
+                .map_or(Anchor::End, |position| Anchor::Before(elements[*position].0.clone())),
+            Expand::Neither | Expand::Left => Anchor::After(elements[positions[end - 1]].0.clone()),
+        };
+
+        Ok((start_anchor, end_anchor))
+    }
+}
+
+impl Clone for RichText {
+    fn clone(&self) -> Self {
+        RichText {
+            text: self.text.clone(),
+            sequencer: self.sequencer.clone(),
+            marks: self.marks.to_vec(),
+        }
+    }
+}
+
+impl CRDT<Vec<Segment>, RichTextCommand, RichTextOperation> for RichText {
+    fn default(replica_id: Option<ReplicaId>) -> Self {
+        let replica_id = replica_id.expect("You must set a valid replica id for the rich text to work.");
+
+        RichText {
+            text: RGA::default(Some(replica_id)),
+            sequencer: RGAPtr::new(replica_id),
+            marks: vec![],
+        }
+    }
+
+    fn query(&self) -> Vec<Segment> {
+        let elements = self.text.elements();
+        let positions = elements
+            .iter()
+            .enumerate()
+            .map(|(position, (v_ptr, _))| (v_ptr, position))
+            .collect::<HashMap<&RGAPtr, usize>>();
+
+        // Every character sits between the point before it and the point after it.
+        let point = |anchor: &Anchor| match anchor {
+            Anchor::Before(v_ptr) => positions[v_ptr] * 3,
+            Anchor::After(v_ptr) => positions[v_ptr] * 3 + 2,
+            Anchor::End => usize::MAX,
+        };
+
+        let mut segments: Vec<Segment> = vec![];
+        for (position, (_, value)) in elements.iter().enumerate() {
+            let Some(character) = value else {
+                continue;
+            };
+
+            let mut winners: Vec<&MarkOperation> = vec![];
+            for operation in &self.marks {
+                let covered = point(&operation.start) < position * 3 + 1 && position * 3 + 1 < point(&operation.end);
+                if !covered {
+                    continue;
+                }
+                match winners.iter_mut().find(|winner| winner.mark.is_same_kind(&operation.mark)) {
+                    Some(winner) if winner.id < operation.id => *winner = operation,
+                    Some(_) => {}
+                    None => winners.push(operation),
+                }
+            }
+
+            // The marks are listed in a fixed order, which doesn't depend on the order of the operations.
+            let mut marks = winners
+                .into_iter()
+                .filter(|winner| winner.added)
+                .map(|winner| winner.mark.clone())
+                .collect::<Vec<Mark>>();
+            marks.sort_by_key(|mark| match mark {
+                Mark::Bold => 0,
+                Mark::Italic => 1,
+                Mark::Link(_) => 2,
+            });
+
+            match segments.last_mut() {
+                Some(segment) if segment.marks == marks => segment.text.push(*character),
+                _ => segments.push(Segment { text: character.to_string(), marks }),
+            }
+        }
+
+        segments
+    }
+
+    fn prepare(&self, command: &RichTextCommand) -> Result<RichTextOperation, CausalError> {
+        match command {
+            Insert(index, character) => Ok(Text(self.text.prepare(&RGACommand::Insert(*index, *character))?)),
+            Remove(index) => Ok(Text(self.text.prepare(&RGACommand::Remove(*index))?)),
+            AddMark(start, end, mark, expand) => {
+                let (start, end) = self.anchors(*start, *end, *expand)?;
+                Ok(Marked(self.sequencer.next_seq_nr(), start, end, mark.clone(), true))
+            }
+            RemoveMark(start, end, mark, expand) => {
+                let (start, end) = self.anchors(*start, *end, *expand)?;
+                Ok(Marked(self.sequencer.next_seq_nr(), start, end, mark.clone(), false))
+            }
+        }
+    }
+
+    fn effect(&mut self, event: &Event<RichTextOperation>) -> Result<(), CausalError> {
+        match &event.data {
+            Text(operation) => self.text.effect(&nested_event(event, operation.clone()))?,
+            Marked(id, start, end, mark, added) => {
+                let elements = self.text.elements();
+                for anchor in [start, end] {
+                    if let Anchor::Before(v_ptr) | Anchor::After(v_ptr) = anchor {
+                        if !elements.iter().any(|(inner_v_ptr, _)| inner_v_ptr == v_ptr) {
+                            return Err(CausalError::UnknownElement);
+                        }
+                    }
+                }
+
+                self.sequencer.update_highest_observed_seq_nr(id);
+                self.marks.push(MarkOperation {
+                    id: id.clone(),
+                    start: start.clone(),
+                    end: end.clone(),
+                    mark: mark.clone(),
+                    added: *added,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+/** UTILS **/
+// The event seen by the text has the same metadata as the event of the rich text.
+fn nested_event(event: &Event<RichTextOperation>, data: RGAOperation<char>) -> Event<RGAOperation<char>> {
+    Event {
+        origin: event.origin,
+        origin_seq_nr: event.origin_seq_nr,
+        local_seq_nr: event.local_seq_nr,
+        version: event.version.clone(),
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CRDT, ReplicaState};
+    use crate::causal_core::CausalError;
+    use crate::causal_rich_text::{Expand, Mark, RichText, RichTextCommand, RichTextOperation, Segment};
+    use crate::causal_utils::InMemory;
+
+    type RichTextState = ReplicaState<RichText, Vec<Segment>, RichTextCommand, RichTextOperation>;
+    type RichTextStore = InMemory<RichText, Vec<Segment>, RichTextCommand, RichTextOperation>;
+
+    fn replica(id: isize, text: &str) -> (RichTextState, RichTextStore) {
+        let mut state = ReplicaState::create(id, RichText::default(Some(id)));
+        let mut store = InMemory::create();
+        for (index, character) in text.chars().enumerate() {
+            state.process_command(&RichTextCommand::Insert(index, character), &mut store).unwrap();
+        }
+        (state, store)
+    }
+
+    fn segment(text: &str, marks: Vec<Mark>) -> Segment {
+        Segment { text: String::from(text), marks }
+    }
+
+    #[test]
+    fn test_marks_expand_according_to_their_rule() {
+        let (mut state, mut store) = replica(0, "ab cd");
+        state.process_command(&RichTextCommand::AddMark(0, 2, Mark::Bold, Expand::Right), &mut store).unwrap();
+        state.process_command(&RichTextCommand::AddMark(3, 5, Mark::Link(String::from("url")), Expand::Neither), &mut store).unwrap();
+
+        // Typing at the end of both spans only extends the bold one.
+        state.process_command(&RichTextCommand::Insert(5, '!'), &mut store).unwrap();
+        state.process_command(&RichTextCommand::Insert(2, 'X'), &mut store).unwrap();
+        state.process_command(&RichTextCommand::Insert(0, '_'), &mut store).unwrap();
+
+        assert_eq!(state.process_query(), vec![
+            segment("_", vec![]),
+            segment("abX", vec![Mark::Bold]),
+            segment(" ", vec![]),
+            segment("cd", vec![Mark::Link(String::from("url"))]),
+            segment("!", vec![]),
+        ]);
+    }
+
+    #[test]
+    fn test_concurrent_marks_and_edits_converge() {
+        let (mut state_0, mut store_0) = replica(0, "hello");
+        let (mut state_1, mut store_1) = replica(1, "");
+        state_1.process_replicated(0, 5, store_0.events.clone(), &mut store_1).unwrap();
+
+        // Replica 0 makes the whole text bold, while replica 1 inserts in the middle and makes the end italic.
+        state_0.process_command(&RichTextCommand::AddMark(0, 5, Mark::Bold, Expand::Right), &mut store_0).unwrap();
+        state_1.process_command(&RichTextCommand::Insert(2, 'X'), &mut store_1).unwrap();
+        state_1.process_command(&RichTextCommand::AddMark(3, 6, Mark::Italic, Expand::Right), &mut store_1).unwrap();
+        state_0.process_replicated(1, 2, store_1.events[5..].to_vec(), &mut store_0).unwrap();
+        state_1.process_replicated(0, 6, store_0.events[5..6].to_vec(), &mut store_1).unwrap();
+
+        let expected = vec![
+            segment("heX", vec![Mark::Bold]),
+            segment("llo", vec![Mark::Bold, Mark::Italic]),
+        ];
+        assert_eq!(state_0.process_query(), expected);
+        assert_eq!(state_1.process_query(), expected);
+    }
+
+    #[test]
+    fn test_later_removal_wins() {
+        let (mut state, mut store) = replica(0, "abcd");
+        state.process_command(&RichTextCommand::AddMark(0, 4, Mark::Bold, Expand::Right), &mut store).unwrap();
+        state.process_command(&RichTextCommand::RemoveMark(1, 3, Mark::Bold, Expand::Neither), &mut store).unwrap();
+        state.process_command(&RichTextCommand::Remove(0), &mut store).unwrap();
+
+        assert_eq!(state.process_query(), vec![
+            segment("bc", vec![]),
+            segment("d", vec![Mark::Bold]),
+        ]);
+        assert_eq!(
+            state.process_command(&RichTextCommand::AddMark(2, 4, Mark::Italic, Expand::Both), &mut store).err(),
+            Some(CausalError::IndexOutOfBounds(4))
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::io;
+use std::io::ErrorKind;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::causal_file::Codec;
+
+/** TYPES **/
+pub type FormatVersion = u16;
+
+// The version of the format written by this release. It must be incremented every time the serialized representation
+// of a type changes, and decode must keep reading all the previous versions.
+pub const FORMAT_VERSION: FormatVersion = 1;
+const HEADER_SIZE: usize = 2;
+
+
+/** DATA STRUCTURES **/
+// Every stored or transmitted value is wrapped in an envelope, which is laid out as the format version in little
+// endian followed by the payload. The header never changes across releases, so that readers can always tell which
+// format the payload is written in.
+pub struct Envelope<T> {
+    pub version: FormatVersion,
+    pub payload: T,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct SerdeCodec;
+
+
+/** IMPLEMENTATIONS **/
+impl<T> Envelope<T>
+    where T: Serialize + DeserializeOwned
+{
+    pub fn new(payload: T) -> Envelope<T> {
+        Envelope {
+            version: FORMAT_VERSION,
+            payload,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        encode_payload(self.version, &self.payload)
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Envelope<T>> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidData, "The envelope is missing its header."));
+        }
+
+        let version = FormatVersion::from_le_bytes([bytes[0], bytes[1]]);
+        let payload = match version {
+            1 => bincode::deserialize(&bytes[HEADER_SIZE..])
+                .map_err(|error| io::Error::new(ErrorKind::InvalidData, error))?,
+            _ => return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("The format version {} is not supported, the latest known is {}.", version, FORMAT_VERSION),
+            )),
+        };
+
+        Ok(Envelope {
+            version,
+            payload,
+        })
+    }
+}
+
+impl<T> Codec<T> for SerdeCodec
+    where T: Serialize + DeserializeOwned
+{
+    fn encode(&self, value: &T) -> Vec<u8> {
+        encode_payload(FORMAT_VERSION, value)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<T> {
+        Envelope::decode(bytes).map(|envelope| envelope.payload)
+    }
+}
+
+
+/** UTILS **/
+fn encode_payload(version: FormatVersion, payload: &impl Serialize) -> Vec<u8> {
+    let mut bytes = version.to_le_bytes().to_vec();
+    bincode::serialize_into(&mut bytes, payload).expect("Error while serializing the payload.");
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{CRDT, Event, EventStore, ReplicaState};
+    use crate::causal_file::{Codec, FileStore};
+    use crate::causal_or_set::{BinarySet, ORSet, SetCommand, SetOperation};
+    use crate::causal_rga::{RGA, RGACommand, RGAOperation};
+    use crate::causal_serde::{Envelope, FORMAT_VERSION, SerdeCodec};
+    use crate::causal_utils::InMemory;
+
+    type RGAState = ReplicaState<RGA<char>, Vec<char>, RGACommand<char>, RGAOperation<char>>;
+
+    #[test]
+    fn test_envelope_roundtrip() {
+        let envelope = Envelope::new(vec![1, 2, 3]);
+        let bytes = envelope.encode();
+
+        assert_eq!(&bytes[..2], &FORMAT_VERSION.to_le_bytes());
+
+        let decoded = Envelope::<Vec<i32>>::decode(&bytes).unwrap();
+        assert_eq!(decoded.version, FORMAT_VERSION);
+        assert_eq!(decoded.payload, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_unknown_version_is_rejected() {
+        let mut bytes = Envelope::new(1u64).encode();
+        bytes[..2].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+
+        assert!(Envelope::<u64>::decode(&bytes).is_err());
+        assert!(Envelope::<u64>::decode(&[1]).is_err());
+    }
+
+    #[test]
+    fn test_replica_state_roundtrip() {
+        let mut store = InMemory::create();
+        let mut state: RGAState = ReplicaState::create(0, RGA::default(Some(0)));
+        state.process_command(&RGACommand::Insert(0, 'a'), &mut store).unwrap();
+        state.process_command(&RGACommand::Insert(1, 'b'), &mut store).unwrap();
+        state.process_command(&RGACommand::Remove(0), &mut store).unwrap();
+        state.process_connect(1);
+
+        let codec = SerdeCodec;
+        let bytes = Codec::<RGAState>::encode(&codec, &state);
+        let decoded: RGAState = codec.decode(&bytes).unwrap();
+
+        assert_eq!(decoded.process_query(), vec!['b']);
+        assert_eq!(decoded.version, state.version);
+        assert_eq!(decoded.seq_nr, 3);
+        assert!(decoded.acknowledged.contains_key(&1));
+
+        let event: Event<RGAOperation<char>> = codec.decode(&codec.encode(&store.events[0])).unwrap();
+        assert_eq!(event.local_seq_nr, 1);
+        assert_eq!(event.version, store.events[0].version);
+    }
+
+    #[test]
+    fn test_file_store_with_serde_codec() {
+        let directory = std::env::temp_dir().join(format!("causal-serde-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        let mut state = ReplicaState::create(0, ORSet::default(Some(0)));
+        {
+            let mut store: FileStore<ORSet<String>, BinarySet<String>, SetCommand<String>, SetOperation<String>, SerdeCodec> =
+                FileStore::open(&directory, SerdeCodec).unwrap();
+            state.process_command(&SetCommand::Add(String::from("a")), &mut store).unwrap();
+            store.save_snapshot(&state).unwrap();
+            state.process_command(&SetCommand::Add(String::from("b")), &mut store).unwrap();
+        }
+
+        let store: FileStore<ORSet<String>, BinarySet<String>, SetCommand<String>, SetOperation<String>, SerdeCodec> =
+            FileStore::open(&directory, SerdeCodec).unwrap();
+        let mut recovered = store.load_snapshot().unwrap().unwrap();
+        for event in store.load_events(recovered.seq_nr + 1).unwrap() {
+            recovered = recovered.process_event(&event).unwrap();
+        }
+
+        assert_eq!(recovered.process_query().to_string().len(), "{a,b}".len());
+        assert_eq!(recovered.version, state.version);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,402 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Debug;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::{CRDT, Event, ReplicaId, ReplicaState};
+use crate::causal_core::{CausalError, SeqNr, VTime};
+use crate::causal_simulator::Message::{Replicate, Replicated};
+use crate::causal_utils::InMemory;
+
+/** DATA STRUCTURES **/
+#[derive(Clone, Debug)]
+pub struct SimulatorConfig {
+    // Every choice of the simulator is taken from a generator with this seed, thus a run can be reproduced by using
+    // the same seed, as long as the CRDT itself doesn't use randomness.
+    pub seed: u64,
+    pub drop_rate: f64,
+    pub duplicate_rate: f64,
+    // Every message is delivered after a random delay up to this number of ticks, which reorders the messages.
+    pub max_delay: u64,
+    pub replay_batch_size: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimulatorStats {
+    pub sent: usize,
+    pub delivered: usize,
+    pub dropped: usize,
+    pub duplicated: usize,
+    // The commands refused by the CRDT, and the replays and effects that failed while replicating events.
+    pub refused: usize,
+    pub failed: usize,
+}
+
+// The same messages exchanged by the actors of the replicas.
+#[derive(Clone)]
+enum Message<EVENT>
+    where EVENT: Clone
+{
+    Replicate(ReplicaId, SeqNr, VTime, usize),
+    Replicated(ReplicaId, SeqNr, Vec<Event<EVENT>>, Option<SeqNr>),
+}
+
+// A replica with its own event store.
+struct SimulatedReplica<C, STATE, CMD, EVENT>
+    where C: CRDT<STATE, CMD, EVENT> + Clone,
+          EVENT: Clone
+{
+    state: ReplicaState<C, STATE, CMD, EVENT>,
+    store: InMemory<C, STATE, CMD, EVENT>,
+}
+
+struct InFlight<EVENT>
+    where EVENT: Clone
+{
+    deliver_at: u64,
+    recipient: ReplicaId,
+    message: Message<EVENT>,
+}
+
+// Drives a group of replicas through the replication protocol without actix, over a simulated network which can drop,
+// duplicate, delay and reorder the messages, and which can be partitioned.
+pub struct Simulator<C, STATE, CMD, EVENT>
+    where C: CRDT<STATE, CMD, EVENT> + Clone,
+          EVENT: Clone
+{
+    config: SimulatorConfig,
+    rng: StdRng,
+    time: u64,
+    // Faults are disabled while the replicas settle.
+    faulty: bool,
+    replicas: BTreeMap<ReplicaId, SimulatedReplica<C, STATE, CMD, EVENT>>,
+    in_flight: Vec<InFlight<EVENT>>,
+    // The links between replicas which are currently cut, in both directions.
+    partitions: HashSet<(ReplicaId, ReplicaId)>,
+    pub stats: SimulatorStats,
+}
+
+
+/** IMPLEMENTATIONS **/
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        SimulatorConfig {
+            seed: 0,
+            drop_rate: 0.1,
+            duplicate_rate: 0.1,
+            max_delay: 5,
+            replay_batch_size: 10,
+        }
+    }
+}
+
+impl<C, STATE, CMD, EVENT> Simulator<C, STATE, CMD, EVENT>
+    where C: CRDT<STATE, CMD, EVENT> + Clone,
+          EVENT: Clone
+{
+    pub fn create(config: SimulatorConfig, replica_ids: &[ReplicaId]) -> Simulator<C, STATE, CMD, EVENT> {
+        let mut replicas = BTreeMap::new();
+        for replica_id in replica_ids {
+            let mut state = ReplicaState::create(*replica_id, C::default(Some(*replica_id)));
+            for other_id in replica_ids.iter().filter(|other_id| *other_id != replica_id) {
+                state.process_connect(*other_id);
+            }
+            replicas.insert(*replica_id, SimulatedReplica { state, store: InMemory::create() });
+        }
+
+        Simulator {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            time: 0,
+            faulty: true,
+            replicas,
+            in_flight: vec![],
+            partitions: HashSet::new(),
+            stats: SimulatorStats::default(),
+        }
+    }
+
+    pub fn replica(&self, replica_id: ReplicaId) -> Option<&ReplicaState<C, STATE, CMD, EVENT>> {
+        self.replicas.get(&replica_id).map(|replica| &replica.state)
+    }
+
+    pub fn command(&mut self, replica_id: ReplicaId, command: &CMD) -> Result<(), CausalError> {
+        let replica = self.replicas
+            .get_mut(&replica_id)
+            .ok_or(CausalError::UnknownReplica(replica_id))?;
+
+        replica.state.process_command(command, &mut replica.store).map(|_| ())
+    }
+
+    // The replica asks every other replica for the events it hasn't seen yet.
+    pub fn sync(&mut self, replica_id: ReplicaId) {
+        let other_ids = self.replicas.keys().copied().filter(|other_id| *other_id != replica_id).collect::<Vec<_>>();
+
+        for other_id in other_ids {
+            if let Some(replica) = self.replicas.get_mut(&replica_id) {
+                let (sender, seq_nr, version) = replica.state.process_sync(other_id);
+                self.send(other_id, Replicate(sender, seq_nr, version, self.config.replay_batch_size));
+            }
+        }
+    }
+
+    // Cuts the links between replicas which are in different groups. The messages already sent on a cut link are lost.
+    pub fn partition(&mut self, groups: &[&[ReplicaId]]) {
+        for (index, group) in groups.iter().enumerate() {
+            for other_group in &groups[index + 1..] {
+                for replica_id in group.iter() {
+                    for other_id in other_group.iter() {
+                        self.partitions.insert((*replica_id, *other_id));
+                        self.partitions.insert((*other_id, *replica_id));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn heal(&mut self) {
+        self.partitions.clear();
+    }
+
+    // Delivers the next message, returning false when there are no messages left.
+    pub fn step(&mut self) -> bool {
+        let Some(deliver_at) = self.in_flight.iter().map(|in_flight| in_flight.deliver_at).min() else {
+            return false;
+        };
+
+        // The messages due at the same time are delivered in a random order.
+        let due = self.in_flight
+            .iter()
+            .enumerate()
+            .filter(|(_, in_flight)| in_flight.deliver_at == deliver_at)
+            .map(|(index, _)| index)
+            .collect::<Vec<usize>>();
+        let index = due[self.rng.gen_range(0..due.len())];
+        let InFlight { recipient, message, .. } = self.in_flight.remove(index);
+        self.time = deliver_at;
+
+        let sender = match &message {
+            Replicate(sender, ..) | Replicated(sender, ..) => *sender,
+        };
+        if self.partitions.contains(&(sender, recipient)) {
+            self.stats.dropped += 1;
+            return true;
+        }
+
+        self.stats.delivered += 1;
+        self.deliver(recipient, message);
+        true
+    }
+
+    // Delivers all the messages, including the ones sent while delivering.
+    pub fn run(&mut self) {
+        while self.step() {}
+    }
+
+    // Runs the given number of random steps. At every step a random replica either syncs or runs a command made by the
+    // generator from its current state, then some of the messages are delivered.
+    pub fn run_workload(&mut self, steps: usize, mut generator: impl FnMut(&mut StdRng, &STATE) -> CMD) {
+        let replica_ids = self.replicas.keys().copied().collect::<Vec<ReplicaId>>();
+
+        for _ in 0..steps {
+            let replica_id = replica_ids[self.rng.gen_range(0..replica_ids.len())];
+            if self.rng.gen_bool(0.5) {
+                let state = self.replicas[&replica_id].state.process_query();
+                let command = generator(&mut self.rng, &state);
+                if self.command(replica_id, &command).is_err() {
+                    self.stats.refused += 1;
+                }
+            } else {
+                self.sync(replica_id);
+            }
+
+            for _ in 0..self.rng.gen_range(0..=3) {
+                self.step();
+            }
+        }
+    }
+
+    // Heals the network and disables the faults, then syncs all the replicas until every one has delivered the same
+    // events. Returns false if they are still behind after the given number of rounds.
+    pub fn settle(&mut self, max_rounds: usize) -> bool {
+        self.heal();
+        self.faulty = false;
+
+        for _ in 0..max_rounds {
+            self.run();
+            if self.is_settled() {
+                self.faulty = true;
+                return true;
+            }
+
+            let replica_ids = self.replicas.keys().copied().collect::<Vec<ReplicaId>>();
+            for replica_id in replica_ids {
+                self.sync(replica_id);
+            }
+        }
+
+        self.faulty = true;
+        false
+    }
+
+    fn is_settled(&self) -> bool {
+        let mut states = self.replicas.values().map(|replica| &replica.state);
+        let first = states.next().map(|state| state.version.clone());
+        self.in_flight.is_empty()
+            && self.replicas.values().all(|replica| replica.state.pending.is_empty())
+            && states.all(|state| Some(&state.version) == first.as_ref())
+    }
+
+    fn send(&mut self, recipient: ReplicaId, message: Message<EVENT>) {
+        self.stats.sent += 1;
+        if self.faulty && self.rng.gen_bool(self.config.drop_rate) {
+            self.stats.dropped += 1;
+            return;
+        }
+
+        let copies = if self.faulty && self.rng.gen_bool(self.config.duplicate_rate) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let deliver_at = self.time + self.rng.gen_range(0..=self.config.max_delay);
+            self.in_flight.push(InFlight {
+                deliver_at,
+                recipient,
+                message: message.clone(),
+            });
+        }
+    }
+
+    fn deliver(&mut self, recipient: ReplicaId, message: Message<EVENT>) {
+        let Some(SimulatedReplica { state, store }) = self.replicas.get_mut(&recipient) else {
+            return;
+        };
+
+        match message {
+            Replicate(sender, seq_nr, version, batch_size) => {
+                let batch_size = batch_size.min(self.config.replay_batch_size);
+                match state.process_replay(sender, seq_nr, version, batch_size, store) {
+                    Ok((current_replica_id, last_seq_nr, events, continuation)) => {
+                        self.send(sender, Replicated(current_replica_id, last_seq_nr, events, continuation));
+                    }
+                    Err(_) => self.stats.failed += 1,
+                }
+            }
+            Replicated(sender, last_seq_nr, events, continuation) => {
+                if state.process_replicated(sender, last_seq_nr, events, store).is_err() {
+                    self.stats.failed += 1;
+                }
+
+                // As the actors do, we immediately request the next batch until we catch up.
+                if let Some(next_seq_nr) = continuation {
+                    let (current_replica_id, _, version) = state.process_sync(sender);
+                    self.send(sender, Replicate(current_replica_id, next_seq_nr, version, self.config.replay_batch_size));
+                }
+            }
+        }
+    }
+}
+
+impl<C, STATE, CMD, EVENT> Simulator<C, STATE, CMD, EVENT>
+    where C: CRDT<STATE, CMD, EVENT> + Clone,
+          STATE: PartialEq + Debug,
+          EVENT: Clone
+{
+    // Panics with the seed of the run if two replicas don't have the same state.
+    pub fn assert_converged(&self) {
+        let mut replicas = self.replicas.iter().map(|(replica_id, replica)| (replica_id, replica.state.process_query()));
+        let Some((first_id, first_state)) = replicas.next() else {
+            return;
+        };
+
+        for (replica_id, state) in replicas {
+            assert!(
+                state == first_state,
+                "Replicas {} and {} diverged with seed {}: {:?} != {:?}",
+                first_id, replica_id, self.config.seed, first_state, state,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use crate::CRDT;
+    use crate::causal_counter::{PNCounter, PNCounterCommand, PNCounterOperation};
+    use crate::causal_or_set::{BinarySet, ORSet, SetCommand, SetOperation};
+    use crate::causal_rga::{RGA, RGACommand, RGAOperation};
+    use crate::causal_simulator::{Simulator, SimulatorConfig};
+
+    type RGASimulator = Simulator<RGA<char>, Vec<char>, RGACommand<char>, RGAOperation<char>>;
+    type CounterSimulator = Simulator<PNCounter, i64, PNCounterCommand, PNCounterOperation>;
+    type SetSimulator = Simulator<ORSet<u8>, BinarySet<u8>, SetCommand<u8>, SetOperation<u8>>;
+
+    fn config(seed: u64) -> SimulatorConfig {
+        SimulatorConfig {
+            seed,
+            ..SimulatorConfig::default()
+        }
+    }
+
+    fn run_counter(seed: u64) -> CounterSimulator {
+        let mut simulator = CounterSimulator::create(config(seed), &[0, 1, 2]);
+        simulator.run_workload(200, |rng, _| match rng.gen_bool(0.5) {
+            true => PNCounterCommand::Increment(rng.gen_range(1..10)),
+            false => PNCounterCommand::Decrement(rng.gen_range(1..10)),
+        });
+        assert!(simulator.settle(100));
+        simulator
+    }
+
+    #[test]
+    fn test_rga_converges_with_faults() {
+        for seed in 0..10 {
+            let mut simulator = RGASimulator::create(config(seed), &[0, 1, 2]);
+            simulator.run_workload(150, |rng, text| {
+                if !text.is_empty() && rng.gen_bool(0.3) {
+                    RGACommand::Remove(rng.gen_range(0..text.len()))
+                } else {
+                    RGACommand::Insert(rng.gen_range(0..=text.len()), char::from(rng.gen_range(b'a'..=b'z')))
+                }
+            });
+
+            assert!(simulator.settle(100), "Replicas did not settle with seed {}", seed);
+            simulator.assert_converged();
+            assert!(simulator.stats.dropped > 0);
+            assert_eq!(simulator.stats.failed, 0);
+        }
+    }
+
+    #[test]
+    fn test_or_set_converges_after_partition() {
+        let mut simulator = SetSimulator::create(config(3), &[0, 1, 2, 3]);
+        simulator.partition(&[&[0, 1], &[2, 3]]);
+        simulator.run_workload(200, |rng, _| match rng.gen_bool(0.6) {
+            true => SetCommand::Add(rng.gen_range(0..10)),
+            false => SetCommand::Remove(rng.gen_range(0..10)),
+        });
+
+        // The replicas on different sides of the partition haven't seen each other's events.
+        assert_ne!(simulator.replica(0).unwrap().version, simulator.replica(2).unwrap().version);
+
+        assert!(simulator.settle(100));
+        simulator.assert_converged();
+    }
+
+    #[test]
+    fn test_runs_are_reproducible() {
+        let first = run_counter(42);
+        let second = run_counter(42);
+
+        first.assert_converged();
+        assert_eq!(first.stats, second.stats);
+        assert_eq!(first.replica(0).unwrap().process_query(), second.replica(0).unwrap().process_query());
+        assert_eq!(first.replica(0).unwrap().crdt.query(), second.replica(1).unwrap().crdt.query());
+    }
+}
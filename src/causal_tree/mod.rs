@@ -0,0 +1,284 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::{CRDT, Event, ReplicaId};
+use crate::causal_core::{CausalError, VTime};
+use crate::causal_time::ClockComparison::{Equal, Less};
+use crate::causal_tree::TreeCommand::Move;
+use crate::causal_tree::TreeOperation::Moved;
+
+/** DATA STRUCTURES **/
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TreeCommand<N, M> {
+    // Moves the node under the parent with the given metadata, e.g. the name of a file. A node is created by moving it
+    // for the first time, and it can be removed by moving it under a trash node.
+    Move(N, N, M),
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TreeOperation<N, M>
+    where N: Clone,
+          M: Clone
+{
+    Moved(N, N, M),
+}
+
+// A move that has been applied, with the position of the node before it, so that the move can be undone.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct LogMove<N, M>
+    where N: Clone,
+          M: Clone
+{
+    version: VTime,
+    origin: ReplicaId,
+    node: N,
+    parent: N,
+    metadata: M,
+    old_position: Option<(N, M)>,
+}
+
+// A tree whose nodes can be moved atomically, as described in "A highly-available move operation for replicated trees"
+// by Kleppmann et al. The moves are applied in a total order which extends the causal one: when a move is delivered,
+// all the moves that come after it are undone, the move is applied and then the moves undone are applied again. A move
+// that would make a node an ancestor of itself is skipped, thus concurrent moves never create cycles.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "N: serde::Serialize, M: serde::Serialize",
+    deserialize = "N: serde::Deserialize<'de> + Eq + Hash, M: serde::Deserialize<'de>",
+)))]
+pub struct MoveTree<N, M>
+    where N: Clone + Eq + Hash,
+          M: Clone
+{
+    // The parent and the metadata of every node. The nodes without a parent are roots.
+    positions: HashMap<N, (N, M)>,
+    // The moves that might still be undone, sorted in the total order.
+    log: Vec<LogMove<N, M>>,
+}
+
+
+/** IMPLEMENTATIONS **/
+impl<N, M> MoveTree<N, M>
+    where N: Clone + Eq + Hash,
+          M: Clone
+{
+    pub fn parent(&self, node: &N) -> Option<&N> {
+        self.positions.get(node).map(|(parent, _)| parent)
+    }
+
+    pub fn children(&self, node: &N) -> Vec<&N> {
+        self.positions
+            .iter()
+            .filter(|(_, (parent, _))| parent == node)
+            .map(|(child, _)| child)
+            .collect()
+    }
+
+    // Tells whether the ancestor is the node itself or one of its ancestors.
+    pub fn is_ancestor(&self, ancestor: &N, node: &N) -> bool {
+        let mut current = node;
+        loop {
+            if current == ancestor {
+                return true;
+            }
+            match self.parent(current) {
+                Some(parent) => current = parent,
+                None => return false,
+            }
+        }
+    }
+
+    fn do_move(&mut self, log_move: &mut LogMove<N, M>) {
+        log_move.old_position = self.positions.get(&log_move.node).cloned();
+
+        // We skip the moves of a node under itself or under one of its descendants.
+        if !self.is_ancestor(&log_move.node, &log_move.parent) {
+            self.positions.insert(log_move.node.clone(), (log_move.parent.clone(), log_move.metadata.clone()));
+        }
+    }
+
+    fn undo_move(&mut self, log_move: &LogMove<N, M>) {
+        match &log_move.old_position {
+            Some(position) => self.positions.insert(log_move.node.clone(), position.clone()),
+            None => self.positions.remove(&log_move.node),
+        };
+    }
+}
+
+impl<N, M> Clone for MoveTree<N, M>
+    where N: Clone + Eq + Hash,
+          M: Clone
+{
+    fn clone(&self) -> Self {
+        MoveTree {
+            positions: self.positions.clone(),
+            log: self.log.to_vec(),
+        }
+    }
+}
+
+impl<N, M> CRDT<HashMap<N, (N, M)>, TreeCommand<N, M>, TreeOperation<N, M>> for MoveTree<N, M>
+    where N: Clone + Eq + Hash,
+          M: Clone
+{
+    fn default(_: Option<ReplicaId>) -> Self {
+        MoveTree {
+            positions: HashMap::new(),
+            log: vec![],
+        }
+    }
+
+    fn query(&self) -> HashMap<N, (N, M)> {
+        self.positions.clone()
+    }
+
+    fn prepare(&self, command: &TreeCommand<N, M>) -> Result<TreeOperation<N, M>, CausalError> {
+        match command {
+            Move(node, parent, metadata) => Ok(Moved(node.clone(), parent.clone(), metadata.clone())),
+        }
+    }
+
+    fn effect(&mut self, event: &Event<TreeOperation<N, M>>) -> Result<(), CausalError> {
+        match &event.data {
+            Moved(node, parent, metadata) => {
+                let mut log_move = LogMove {
+                    version: event.version.clone(),
+                    origin: event.origin,
+                    node: node.clone(),
+                    parent: parent.clone(),
+                    metadata: metadata.clone(),
+                    old_position: None,
+                };
+
+                // We undo the moves that come after the new one, starting from the last.
+                let index = self.log.partition_point(|inner_move| {
+                    compare(&inner_move.version, inner_move.origin, &event.version, event.origin) == Ordering::Less
+                });
+                let undone = self.log.split_off(index);
+                undone.iter().rev().for_each(|inner_move| self.undo_move(inner_move));
+
+                self.do_move(&mut log_move);
+                self.log.push(log_move);
+
+                // The moves undone are applied again, in order, which might skip some of them now.
+                for mut inner_move in undone {
+                    self.do_move(&mut inner_move);
+                    self.log.push(inner_move);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stable(&mut self, frontier: &VTime) {
+        // Every move delivered from now on causally follows the stable moves, thus they will never be undone.
+        self.log.retain(|log_move| {
+            let comparison = log_move.version.compare(frontier);
+            comparison != Less && comparison != Equal
+        });
+    }
+}
+
+
+/** UTILS **/
+// Extends the causal order to a total one. An event that happened before another one has a lower sum of its version
+// entries, while concurrent events with the same sum are ordered by their origin.
+fn compare(version: &VTime, origin: ReplicaId, other_version: &VTime, other_origin: ReplicaId) -> Ordering {
+    let sum = version.vector().values().sum::<i32>();
+    let other_sum = other_version.vector().values().sum::<i32>();
+
+    sum.cmp(&other_sum).then(origin.cmp(&other_origin))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{CRDT, ReplicaState};
+    use crate::causal_tree::{MoveTree, TreeCommand, TreeOperation};
+    use crate::causal_utils::InMemory;
+
+    type Tree = MoveTree<&'static str, ()>;
+    type TreeState = ReplicaState<Tree, HashMap<&'static str, (&'static str, ())>, TreeCommand<&'static str, ()>, TreeOperation<&'static str, ()>>;
+    type TreeStore = InMemory<Tree, HashMap<&'static str, (&'static str, ())>, TreeCommand<&'static str, ()>, TreeOperation<&'static str, ()>>;
+
+    fn replica(id: isize) -> (TreeState, TreeStore) {
+        (ReplicaState::create(id, MoveTree::default(Some(id))), InMemory::create())
+    }
+
+    fn move_to(node: &'static str, parent: &'static str) -> TreeCommand<&'static str, ()> {
+        TreeCommand::Move(node, parent, ())
+    }
+
+    // Creates the tree root -> {a, b} on both replicas.
+    fn setup() -> ((TreeState, TreeStore), (TreeState, TreeStore)) {
+        let (mut state_0, mut store_0) = replica(0);
+        let (mut state_1, mut store_1) = replica(1);
+
+        state_0.process_command(&move_to("a", "root"), &mut store_0).unwrap();
+        state_0.process_command(&move_to("b", "root"), &mut store_0).unwrap();
+        state_1.process_replicated(0, 2, store_0.events.clone(), &mut store_1).unwrap();
+
+        ((state_0, store_0), (state_1, store_1))
+    }
+
+    #[test]
+    fn test_concurrent_moves_do_not_create_cycles() {
+        let ((mut state_0, mut store_0), (mut state_1, mut store_1)) = setup();
+
+        // Each move is valid on its own, but applying both would make a and b ancestors of each other.
+        state_0.process_command(&move_to("a", "b"), &mut store_0).unwrap();
+        state_1.process_command(&move_to("b", "a"), &mut store_1).unwrap();
+        state_0.process_replicated(1, 3, store_1.events[2..].to_vec(), &mut store_0).unwrap();
+        state_1.process_replicated(0, 3, store_0.events[2..3].to_vec(), &mut store_1).unwrap();
+
+        assert_eq!(state_0.process_query(), state_1.process_query());
+        assert!(state_0.crdt.is_ancestor(&"root", &"a"));
+        assert!(state_0.crdt.is_ancestor(&"root", &"b"));
+        // The move of replica 1 comes last in the total order, thus it is the one which is skipped.
+        assert_eq!(state_0.crdt.parent(&"a"), Some(&"b"));
+        assert_eq!(state_0.crdt.parent(&"b"), Some(&"root"));
+    }
+
+    #[test]
+    fn test_concurrent_moves_of_the_same_node() {
+        let ((mut state_0, mut store_0), (mut state_1, mut store_1)) = setup();
+        state_0.process_command(&move_to("c", "root"), &mut store_0).unwrap();
+        state_1.process_replicated(0, 3, store_0.events[2..].to_vec(), &mut store_1).unwrap();
+
+        state_0.process_command(&move_to("c", "a"), &mut store_0).unwrap();
+        state_1.process_command(&move_to("c", "b"), &mut store_1).unwrap();
+        state_0.process_replicated(1, 4, store_1.events[3..].to_vec(), &mut store_0).unwrap();
+        state_1.process_replicated(0, 4, store_0.events[3..4].to_vec(), &mut store_1).unwrap();
+
+        assert_eq!(state_0.process_query(), state_1.process_query());
+        assert_eq!(state_0.crdt.parent(&"c"), Some(&"b"));
+        assert_eq!(state_0.crdt.children(&"root").len(), 2);
+    }
+
+    #[test]
+    fn test_move_under_itself_is_skipped() {
+        let ((mut state_0, mut store_0), _) = setup();
+        state_0.process_command(&move_to("c", "a"), &mut store_0).unwrap();
+        state_0.process_command(&move_to("a", "c"), &mut store_0).unwrap();
+
+        assert_eq!(state_0.crdt.parent(&"a"), Some(&"root"));
+        assert_eq!(state_0.crdt.parent(&"c"), Some(&"a"));
+    }
+
+    #[test]
+    fn test_stable_moves_are_purged() {
+        let ((mut state_0, _), _) = setup();
+        assert_eq!(state_0.crdt.log.len(), 2);
+
+        let frontier = state_0.version.clone();
+        state_0.crdt.stable(&frontier);
+
+        assert!(state_0.crdt.log.is_empty());
+        assert_eq!(state_0.crdt.parent(&"b"), Some(&"root"));
+    }
+}
@@ -1,10 +1,12 @@
-use crate::{CRDT, Event, ReplicaState};
+use crate::{CRDT, Event, EventStore, ReplicaState};
+use crate::causal_core::{CausalError, SeqNr};
 
 pub struct InMemory<C, STATE, CMD, EVENT>
     where C: CRDT<STATE, CMD, EVENT> + Clone,
           EVENT: Clone
 {
     pub last_snapshot: Option<ReplicaState<C, STATE, CMD, EVENT>>,
+    // The events are appended in order of local seq nr, which allows us to binary search them.
     pub events: Vec<Event<EVENT>>,
 }
 
@@ -18,4 +20,76 @@ impl<C, STATE, CMD, EVENT> InMemory<C, STATE, CMD, EVENT>
             events: vec![],
         }
     }
-}
\ No newline at end of file
+
+    fn position(&self, start_seq_nr: SeqNr) -> usize {
+        self.events.partition_point(|event| event.local_seq_nr < start_seq_nr)
+    }
+}
+
+impl<C, STATE, CMD, EVENT> EventStore<C, STATE, CMD, EVENT> for InMemory<C, STATE, CMD, EVENT>
+    where C: CRDT<STATE, CMD, EVENT> + Clone,
+          EVENT: Clone
+{
+    fn save_snapshot(&mut self, state: &ReplicaState<C, STATE, CMD, EVENT>) -> Result<(), CausalError> {
+        self.last_snapshot = Some(state.clone());
+        Ok(())
+    }
+
+    fn load_snapshot(&self) -> Result<Option<ReplicaState<C, STATE, CMD, EVENT>>, CausalError> {
+        Ok(self.last_snapshot.clone())
+    }
+
+    fn save_events(&mut self, events: Vec<Event<EVENT>>) -> Result<(), CausalError> {
+        self.events.extend(events);
+        Ok(())
+    }
+
+    fn load_events(&self, start_seq_nr: SeqNr) -> Result<Vec<Event<EVENT>>, CausalError> {
+        Ok(self.events[self.position(start_seq_nr)..].to_vec())
+    }
+
+    fn load_events_batch(&self, start_seq_nr: SeqNr, limit: usize) -> Result<Vec<Event<EVENT>>, CausalError> {
+        Ok(self.events[self.position(start_seq_nr)..]
+            .iter()
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn truncate_events(&mut self, end_seq_nr: SeqNr) -> Result<(), CausalError> {
+        let position = self.position(end_seq_nr);
+        self.events.drain(..position);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CRDT, Event, EventStore, ReplicaState};
+    use crate::causal_core::SeqNr;
+    use crate::causal_or_set::{ORSet, SetCommand, SetOperation};
+    use crate::causal_utils::InMemory;
+
+    fn seq_nrs(events: Vec<Event<SetOperation<i32>>>) -> Vec<SeqNr> {
+        events.iter().map(|event| event.local_seq_nr).collect()
+    }
+
+    #[test]
+    fn test_load_events_from_seq_nr() {
+        let mut store = InMemory::create();
+        let mut state = ReplicaState::create(0, ORSet::default(Some(0)));
+        for value in 0..5 {
+            state.process_command(&SetCommand::Add(value), &mut store).unwrap();
+        }
+
+        assert_eq!(seq_nrs(store.load_events(0).unwrap()), vec![1, 2, 3, 4, 5]);
+        assert_eq!(seq_nrs(store.load_events(3).unwrap()), vec![3, 4, 5]);
+        assert!(store.load_events(6).unwrap().is_empty());
+        assert_eq!(seq_nrs(store.load_events_batch(2, 2).unwrap()), vec![2, 3]);
+        assert_eq!(seq_nrs(store.load_events_batch(4, 10).unwrap()), vec![4, 5]);
+
+        store.truncate_events(3).unwrap();
+        assert_eq!(seq_nrs(store.load_events(0).unwrap()), vec![3, 4, 5]);
+        assert_eq!(seq_nrs(store.load_events_batch(4, 1).unwrap()), vec![4]);
+    }
+}
@@ -0,0 +1,30 @@
+use crate::causal_actix::VoidCausalMessage;
+use crate::causal_console::InputReceiver;
+use crate::causal_core::{CRDT, Event, EventStore, ReplicaId, ReplicaState};
+use crate::causal_time::ClockComparison::{Concurrent, Greater};
+use crate::causal_time::VectorClock;
+
+pub mod causal_time;
+pub mod causal_core;
+pub mod causal_actix;
+pub mod causal_or_set;
+pub mod causal_console;
+pub mod causal_utils;
+pub mod causal_lseq;
+pub mod causal_rga;
+pub mod causal_file;
+pub mod causal_counter;
+pub mod causal_register;
+pub mod causal_or_map;
+pub mod causal_json;
+pub mod causal_tree;
+pub mod causal_block_rga;
+pub mod causal_indexed_rga;
+pub mod causal_fugue;
+pub mod causal_rich_text;
+pub mod causal_simulator;
+pub mod causal_properties;
+#[cfg(feature = "serde")]
+pub mod causal_serde;
+#[cfg(feature = "net")]
+pub mod causal_net;
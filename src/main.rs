@@ -3,30 +3,19 @@ use std::collections::HashMap;
 
 use actix::prelude::*;
 
-use crate::causal_actix::{Replica, send_valued, send_void, ValuedCausalMessage, VoidCausalMessage};
-use crate::causal_console::{InputField, InputReceiver};
-use crate::causal_core::{CRDT, Event, EventStore, ReplicaId, ReplicaState};
-use crate::causal_rga::{RGA, RGACommand, RGAOperation, RGAReceiver};
-use crate::causal_time::ClockComparison::{Concurrent, Greater};
-use crate::causal_time::VectorClock;
-use crate::causal_utils::InMemory;
-use crate::VoidCausalMessage::{Command, Connect, Sync};
-
-mod causal_time;
-mod causal_core;
-mod causal_actix;
-mod causal_or_set;
-mod causal_console;
-mod causal_utils;
-mod causal_lseq;
-mod causal_rga;
+use causal::causal_actix::{Replica, send_valued, send_void, ValuedCausalMessage};
+use causal::causal_actix::VoidCausalMessage::{Command, Connect, Sync};
+use causal::causal_console::InputField;
+use causal::causal_core::CRDT;
+use causal::causal_rga::{RGA, RGACommand, RGAOperation, RGAReceiver};
+use causal::causal_utils::InMemory;
 
 fn start() {
     let replicas_number: isize = 3;
     let system = System::new();
     let mut replicas = HashMap::new();
 
-    let _addr = system.block_on(async {
+    system.block_on(async {
         // We spawn the replicas.
         for id in 0..replicas_number {
             // For each replica we will craft specific messages that will trigger actions towards the CRDT.
@@ -52,7 +41,7 @@ fn start() {
                         .clone()
                         .recipient(),
                     ),
-                );
+                ).expect("The replica has just been spawned.");
             }
         }
     }
@@ -72,7 +61,7 @@ fn start() {
                     .expect("Failed to read from CLI");
 
                 let command_parts: Vec<&str> = command.split(":").collect();
-                let action: &str = command_parts.get(0).unwrap();
+                let action: &str = command_parts.first().unwrap();
                 let replica_id: &str = command_parts.get(1).unwrap();
                 let replica_id: isize = match replica_id.trim().parse() {
                     Ok(value) => value,
@@ -82,38 +71,45 @@ fn start() {
                     }
                 };
 
-                match action {
+                let result = match action {
                     "Q" => {
-                        let state = send_valued(
+                        send_valued(
                             &replicas,
                             replica_id,
                             ValuedCausalMessage::Query(Default::default()),
-                        ).await;
-
-                        for value in state {
-                            print!("{}", value)
-                        }
-                        println!()
+                        ).await.map(|state| {
+                            for value in state {
+                                print!("{}", value)
+                            }
+                            println!()
+                        })
                     }
                     "S" => {
-                        send_void(&replicas, replica_id, Sync);
+                        send_void(&replicas, replica_id, Sync)
                     }
                     "E" => {
-                        let state = send_valued(
+                        send_valued(
                             &replicas,
                             replica_id,
                             ValuedCausalMessage::Query(Default::default()),
-                        ).await;
-
-                        let mut receiver = RGAReceiver::new();
-                        InputField::start(String::from_iter(state.iter()), &mut receiver);
-
-                        for command in receiver.commands {
-                            send_void(&replicas, replica_id, Command(command));
-                        }
+                        ).await.and_then(|state| {
+                            let mut receiver = RGAReceiver::new();
+                            InputField::start(String::from_iter(state.iter()), &mut receiver);
+
+                            receiver.commands
+                                .into_iter()
+                                .try_for_each(|command| send_void(&replicas, replica_id, Command(command)))
+                        })
+                    }
+                    &_ => {
+                        println!("The command is not parsable");
+                        Ok(())
                     }
-                    &_ => println!("The command is not parsable")
                 };
+
+                if let Err(error) = result {
+                    println!("{}", error);
+                }
             }
         });
 
@@ -124,9 +120,7 @@ fn start() {
 }
 
 // TODO:
-// * Try a socket based implementation.
 // * Implement more complex operation-based CRDTs.
-// * Reduce duplication of in-memory event store.
 // * Implement more extensive unit tests for CRDTs.
 fn main() {
     start();